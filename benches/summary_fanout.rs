@@ -0,0 +1,147 @@
+//! Benchmarks fanning summaries out to many BookSummary clients.
+//! Compares cloning and encoding the summary for each client (how summaries were published before SharedSummary)
+//! with encoding it once and sharing the bytes. Each run publishes summaries one at a time and waits for every
+//! client to receive and encode it, like the codec does when writing to the connection.
+//! Run with `cargo bench --bench summary_fanout`.
+#[macro_use]
+extern crate log;
+
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use prost::Message;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_stream::StreamExt;
+
+use crate::conflation::{stream_summaries, Conflation};
+use crate::orderbook::{Level, Summary, SummaryRequest};
+use crate::shared_summary::SharedSummary;
+
+// the benchmark only uses the publishing path of these modules.
+#[allow(dead_code)]
+#[path = "../src/conflation.rs"]
+mod conflation;
+#[allow(dead_code)]
+#[path = "../src/result.rs"]
+mod result;
+#[allow(dead_code)]
+#[path = "../src/shared_summary.rs"]
+mod shared_summary;
+
+pub mod orderbook {
+    tonic::include_proto!("orderbook");
+}
+
+const CLIENTS: [usize; 4] = [10, 100, 1_000, 5_000];
+const PUBLISHES: u64 = 100;
+// matches OrderBookData's TOP_N.
+const LEVELS: usize = 10;
+
+fn summary(sequence: u64) -> Summary {
+    let level = |i: usize, price: f64| Level {
+        exchange: if i.is_multiple_of(2) {
+            "binance"
+        } else {
+            "bitstamp"
+        }
+        .to_string(),
+        price,
+        amount: 1.0 + i as f64,
+    };
+
+    Summary {
+        spread: 1.0,
+        bids: (0..LEVELS).map(|i| level(i, 100.0 - i as f64)).collect(),
+        asks: (0..LEVELS).map(|i| level(i, 101.0 + i as f64)).collect(),
+        sequence,
+        dropped: 0,
+    }
+}
+
+// waits for every client to report it has received a summary.
+async fn wait_for_clients(done_rx: &mut mpsc::UnboundedReceiver<u64>, clients: usize) {
+    for _ in 0..clients {
+        done_rx.recv().await.expect("clients stopped");
+    }
+}
+
+// each client clones the summary from the watch, then encodes it.
+async fn cloned(clients: usize) -> Duration {
+    let (wtx, wrx) = watch::channel(Summary::default());
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+
+    for _ in 0..clients {
+        let mut wrx = wrx.clone();
+        let (tx, mut rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            while wrx.changed().await.is_ok() {
+                let summary = wrx.borrow().clone();
+                if tx.send(summary).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let done_tx = done_tx.clone();
+        tokio::spawn(async move {
+            let mut buf = BytesMut::new();
+            while let Some(summary) = rx.recv().await {
+                buf.clear();
+                summary.encode(&mut buf).unwrap();
+                let _ = done_tx.send(summary.sequence);
+            }
+        });
+    }
+
+    let start = Instant::now();
+    for sequence in 1..=PUBLISHES {
+        wtx.send(summary(sequence)).unwrap();
+        wait_for_clients(&mut done_rx, clients).await;
+    }
+    start.elapsed()
+}
+
+// the summary is encoded once and shared by all clients.
+async fn shared(clients: usize) -> Duration {
+    let (wtx, wrx) = watch::channel(SharedSummary::default());
+    // only used by QUEUE clients.
+    let (summaries_tx, _) = broadcast::channel(1);
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let conflation = Conflation::from_request(&SummaryRequest::default()).unwrap();
+
+    for _ in 0..clients {
+        let mut stream = stream_summaries(wrx.clone(), &summaries_tx, conflation.clone());
+
+        let done_tx = done_tx.clone();
+        tokio::spawn(async move {
+            let mut buf = BytesMut::new();
+            while let Some(Ok(summary)) = stream.next().await {
+                buf.clear();
+                summary.encode(&mut buf).unwrap();
+                let _ = done_tx.send(summary.sequence());
+            }
+        });
+    }
+
+    let start = Instant::now();
+    for sequence in 1..=PUBLISHES {
+        wtx.send(SharedSummary::new(summary(sequence))).unwrap();
+        wait_for_clients(&mut done_rx, clients).await;
+    }
+    start.elapsed()
+}
+
+#[tokio::main]
+async fn main() {
+    println!(
+        "{} summaries of {} levels per side, time per summary for all clients to receive it:",
+        PUBLISHES, LEVELS
+    );
+    println!("{:>8} {:>14} {:>14}", "clients", "cloned", "shared");
+
+    for clients in CLIENTS {
+        let cloned = cloned(clients).await / PUBLISHES as u32;
+        let shared = shared(clients).await / PUBLISHES as u32;
+        println!("{:>8} {:>14?} {:>14?}", clients, cloned, shared);
+    }
+}
@@ -1,4 +1,25 @@
+use std::path::PathBuf;
+
 fn main() {
-    tonic_build::compile_protos("proto/orderbook.proto")
+    // messages and the client, used by the clients and by the server internally.
+    // the descriptors are served by the reflection service.
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    tonic_build::configure()
+        .build_server(false)
+        .file_descriptor_set_path(out_dir.join("orderbook_descriptor.bin"))
+        .compile(&["proto/orderbook.proto"], &["proto"])
         .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
+
+    // the server's service. It uses the messages generated above, except for Summary which is streamed to clients
+    // pre-encoded so it's only encoded once for all clients (see shared_summary.rs.)
+    let service_dir = out_dir.join("service");
+    std::fs::create_dir_all(&service_dir)
+        .unwrap_or_else(|e| panic!("Failed to create service dir {:?}", e));
+    tonic_build::configure()
+        .build_client(false)
+        .out_dir(service_dir)
+        .extern_path(".orderbook", "crate::orderbook")
+        .extern_path(".orderbook.Summary", "crate::shared_summary::SharedSummary")
+        .compile(&["proto/orderbook.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("Failed to compile service protos {:?}", e));
 }
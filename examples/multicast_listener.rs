@@ -0,0 +1,112 @@
+//! An example consumer of the multicast feed (see src/multicast.rs.)
+//! Joins the multicast group, builds the book from the packets and prints the best bid and ask as they change.
+//! Lost packets are requested from the retransmit address, or a snapshot if they're no longer kept.
+//! Run with `cargo run --example multicast_listener -- --group 239.255.0.1:30001 --retransmit 127.0.0.1:30002`.
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use clap::Parser;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use orderbooks_rs::multicast_packet::{Apply, Book, Packet, Request};
+use orderbooks_rs::result::Result;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// the multicast group and port (server.multicast.group_addr.)
+    #[arg(long)]
+    group: SocketAddrV4,
+
+    /// the address of the interface to receive on.
+    #[arg(long, default_value = "0.0.0.0")]
+    interface: Ipv4Addr,
+
+    /// the retransmit address (server.multicast.retransmit_addr.)
+    #[arg(long)]
+    retransmit: SocketAddr,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let socket = join(&args)?;
+    let mut retransmits = TcpStream::connect(args.retransmit).await?;
+    let mut book = Book::default();
+    let mut buf = [0; 65536];
+
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        let packet = match Packet::decode(&buf[..len]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                eprintln!("ignoring invalid packet: {}", e);
+                continue;
+            }
+        };
+
+        match book.apply(packet) {
+            Apply::Applied | Apply::Stale => {}
+            Apply::NeedSnapshot => snapshot(&mut retransmits, &mut book).await?,
+            Apply::Gap { expected, received } => {
+                eprintln!("lost packets {} to {}", expected, received - 1);
+                recover(&mut retransmits, &mut book, expected, received).await?;
+            }
+        }
+
+        if let (Some(bid), Some(ask)) = (book.bids().first(), book.asks().first()) {
+            println!(
+                "#{:<8} bid {:>12.2} x {:<10.4} ({:<8}) ask {:>12.2} x {:<10.4} ({})",
+                book.sequence().unwrap_or_default(),
+                bid.price,
+                bid.amount,
+                bid.exchange,
+                ask.price,
+                ask.amount,
+                ask.exchange
+            );
+        }
+    }
+}
+
+fn join(args: &Args) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // other consumers on the host can join the group too.
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), args.group.port()).into())?;
+    socket.join_multicast_v4(args.group.ip(), &args.interface)?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+// applies the packets from and to the sequence numbers, or a snapshot if they're no longer all kept.
+async fn recover(stream: &mut TcpStream, book: &mut Book, from: u64, to: u64) -> Result<()> {
+    for packet in fetch(stream, Request::Retransmit { from, to }).await? {
+        book.apply(packet);
+    }
+    if book.sequence() < Some(to) {
+        snapshot(stream, book).await?;
+    }
+    Ok(())
+}
+
+async fn snapshot(stream: &mut TcpStream, book: &mut Book) -> Result<()> {
+    for packet in fetch(stream, Request::Snapshot).await? {
+        book.apply(packet);
+    }
+    Ok(())
+}
+
+async fn fetch(stream: &mut TcpStream, request: Request) -> Result<Vec<Packet>> {
+    stream.write_all(&request.encode()).await?;
+    let mut packets = vec![];
+    loop {
+        let len = stream.read_u16_le().await? as usize;
+        if len == 0 {
+            return Ok(packets);
+        }
+        let mut packet = vec![0; len];
+        stream.read_exact(&mut packet).await?;
+        packets.push(Packet::decode(&packet)?);
+    }
+}
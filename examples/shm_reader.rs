@@ -0,0 +1,64 @@
+//! An example reader of the shared memory feed (see src/shm.rs.)
+//! Maps the file written by the server and prints the best bid and ask of each snapshot, and how long after it was
+//! written it was read. It polls the file without any syscalls, so it uses a whole core unless --poll-us is set.
+//! Run with `cargo run --example shm_reader -- --path /dev/shm/orderbooks-BTCUSDT`.
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+
+use orderbooks_rs::result::Result;
+use orderbooks_rs::shm_book::Reader;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// the file the server writes the book to (server.shared_memory.path.)
+    #[arg(long)]
+    path: PathBuf,
+
+    /// how long to sleep when there isn't a new snapshot, 0 to spin.
+    #[arg(long, default_value_t = 0)]
+    poll_us: u64,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut reader = Reader::open(&args.path)?;
+    let mut missed = 0;
+
+    loop {
+        let snapshot = match reader.next() {
+            Some(snapshot) => snapshot,
+            None if args.poll_us > 0 => {
+                std::thread::sleep(Duration::from_micros(args.poll_us));
+                continue;
+            }
+            None => {
+                std::hint::spin_loop();
+                continue;
+            }
+        };
+        let now_ns = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+
+        if reader.missed() > missed {
+            eprintln!(
+                "fell behind, skipped {} snapshots",
+                reader.missed() - missed
+            );
+            missed = reader.missed();
+        }
+        if let (Some(bid), Some(ask)) = (snapshot.bids.first(), snapshot.asks.first()) {
+            println!(
+                "#{:<8} bid {:>12.2} x {:<10.4} ({:<8}) ask {:>12.2} x {:<10.4} ({:<8}) +{}ns",
+                snapshot.summary_sequence,
+                bid.price,
+                bid.amount,
+                bid.exchange.as_str(),
+                ask.price,
+                ask.amount,
+                ask.exchange.as_str(),
+                now_ns.saturating_sub(snapshot.published_ns)
+            );
+        }
+    }
+}
@@ -2,19 +2,27 @@
 //! It will read from Settings.toml and find the pair, enabled exchanges, and exchange specific details.
 //! Rather than hardcoding exchange endpoints and details, it's in configuration.
 //! If something changes on the exchange side, it _should hopefully_ be fixable without recompiling.
-use config::Config;
+//! Any setting can be overridden by an environment variable, eg ORDERBOOK_SERVER__GRPC_ADDR for server.grpc_addr,
+//! and some settings can be overridden on the command line (see Overrides), which take precedence over everything.
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use config::{Config, ConfigError, Environment};
+use serde::Deserialize;
 
 // [jasongoodwin - 2022/11/10] may need to be made a bit more exchange specific as other exchanges added.
+use crate::auth;
+use crate::exchange::SUPPORTED_EXCHANGES;
 use crate::result::Result;
 
-// toml file name/location for application config. (Settings.toml used to follow the lib's examples)
-// TODO [jasongoodwin 2022/11/10] - make this configurable (env variable or arg)
-#[cfg(not(test))]
-const SETTINGS: &str = "Settings";
+/// default toml file name/location for application config, used if --config isn't given. (Settings.toml used to follow the lib's examples)
+pub const DEFAULT_SETTINGS: &str = "Settings";
 
-// a stable file is used for testing.
-#[cfg(test)]
-const SETTINGS: &str = "TestSettings";
+// prefix of environment variables that override settings. Nested keys are separated by "__".
+const ENV_PREFIX: &str = "ORDERBOOK";
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ExchangeConfig {
@@ -23,6 +31,140 @@ pub struct ExchangeConfig {
     pub(crate) subscription_message_template: String,
     pub(crate) spot_pair: String,
     pub(crate) receive_timeout_s: u64,
+    // public trades are optional and only subscribed to if configured for the exchange.
+    pub(crate) trades: Option<TradesConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradesConfig {
+    pub(crate) subscription_message_template: String,
+    pub(crate) receive_timeout_s: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// thresholds for detecting spoofing strategies (order fading/layering) from level lifetimes.
+pub struct SpoofingConfig {
+    // levels that are placed and cancelled within this time are considered fleeting.
+    pub(crate) max_lifetime_ms: u64,
+    // levels smaller than this are ignored.
+    pub(crate) min_amount: f64,
+    // a level is fading if this fraction of its max amount is cancelled within max_lifetime_ms.
+    pub(crate) fade_ratio: f64,
+    // layering is flagged when layering_min_levels fleeting levels are seen on the same side within the window.
+    pub(crate) layering_window_ms: u64,
+    pub(crate) layering_min_levels: usize,
+}
+
+/// the certificate and key the gRPC server uses for TLS, and the CA that client certificates must be signed by
+/// if clients are required to authenticate (mutual TLS.) The files are PEM encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub(crate) cert_path: PathBuf,
+    pub(crate) key_path: PathBuf,
+    pub(crate) client_ca_path: Option<PathBuf>,
+}
+
+/// the origins browsers may call the gRPC server from with gRPC-Web (see grpc_web.rs.) "*" allows any origin.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GrpcWebConfig {
+    pub(crate) allowed_origins: Vec<String>,
+}
+
+/// the UDP multicast feed of book updates (see multicast.rs.)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MulticastConfig {
+    // the IPv4 multicast group and port the packets are sent to.
+    pub(crate) group_addr: SocketAddr,
+    // the address of the interface to send from, or the default route's if not set.
+    pub(crate) interface: Option<Ipv4Addr>,
+    #[serde(default = "default_multicast_ttl")]
+    pub(crate) ttl: u32,
+    #[serde(default = "default_snapshot_interval_ms")]
+    pub(crate) snapshot_interval_ms: u64,
+    // clients request lost packets and snapshots over TCP at this address. Requests aren't authenticated,
+    // so it's only reachable from the host unless it's set.
+    #[serde(default = "default_retransmit_addr")]
+    pub(crate) retransmit_addr: SocketAddr,
+    // how many of the latest packets are kept for retransmission.
+    #[serde(default = "default_retransmit_buffer")]
+    pub(crate) retransmit_buffer: usize,
+}
+
+// packets stay on the local network by default.
+fn default_multicast_ttl() -> u32 {
+    1
+}
+
+fn default_snapshot_interval_ms() -> u64 {
+    1000
+}
+
+fn default_retransmit_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 30002))
+}
+
+fn default_retransmit_buffer() -> usize {
+    4096
+}
+
+/// the shared memory feed of the book, for readers on the same host (see shm.rs.)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SharedMemoryConfig {
+    // the file the book is mapped from, eg in /dev/shm.
+    pub(crate) path: PathBuf,
+    // how many of the latest snapshots are kept in the ring buffer.
+    #[serde(default = "default_shared_memory_slots")]
+    pub(crate) slots: usize,
+}
+
+fn default_shared_memory_slots() -> usize {
+    1024
+}
+
+/// the FIX acceptor for market data (see fix.rs.)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FixConfig {
+    pub(crate) addr: SocketAddr,
+    // the acceptor's CompID, which clients send as their TargetCompID.
+    #[serde(default = "default_sender_comp_id")]
+    pub(crate) sender_comp_id: String,
+}
+
+fn default_sender_comp_id() -> String {
+    "ORDERBOOKS".to_string()
+}
+
+/// the tokens clients authenticate with (see auth.rs.) Clients either use one of the static keys,
+/// or a JWT signed with the secret.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub(crate) keys: Vec<ApiKeyConfig>,
+    pub(crate) jwt_secret: Option<String>,
+    // if set, JWTs must have been issued by this issuer.
+    pub(crate) jwt_issuer: Option<String>,
+}
+
+/// a static key, and the rpcs, exchanges and pairs the client may use (all of them if not given.)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ApiKeyConfig {
+    pub(crate) client: String,
+    pub(crate) token: String,
+    pub(crate) rpcs: Option<Vec<String>>,
+    pub(crate) exchanges: Option<Vec<String>>,
+    pub(crate) pairs: Option<Vec<String>>,
+}
+
+/// settings given on the command line. Each one that's set overrides the settings file and environment.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub pair: Option<String>,
+    pub enabled_exchanges: Option<Vec<String>>,
+    pub grpc_addr: Option<SocketAddr>,
+    pub metrics_addr: Option<SocketAddr>,
+    pub websocket_addr: Option<SocketAddr>,
+    pub rest_addr: Option<SocketAddr>,
+    pub grpc_uds_path: Option<PathBuf>,
 }
 
 pub struct AppConfig {
@@ -30,16 +172,171 @@ pub struct AppConfig {
 }
 
 impl AppConfig {
-    /// returns a new app config holding the settings. (note the file is dynamically read currently...)
-    pub fn new() -> Result<AppConfig> {
-        info!("Using config file: {}.toml", SETTINGS);
+    /// returns a new app config holding the settings from the file (the .toml extension is optional.)
+    /// (note the file is dynamically read currently...)
+    pub fn new(settings: &str, overrides: &Overrides) -> Result<AppConfig> {
+        AppConfig::with_environment(settings, environment(), overrides)
+    }
+
+    fn with_environment(
+        settings: &str,
+        environment: Environment,
+        overrides: &Overrides,
+    ) -> Result<AppConfig> {
+        info!("Using config file: {}", settings);
         let config: Config = Config::builder()
-            .add_source(config::File::with_name(SETTINGS))
+            .add_source(config::File::with_name(settings))
+            .add_source(environment)
+            .set_override_option("pair", overrides.pair.clone())?
+            .set_override_option("enabled_exchanges", overrides.enabled_exchanges.clone())?
+            .set_override_option(
+                "server.grpc_addr",
+                overrides.grpc_addr.map(|a| a.to_string()),
+            )?
+            .set_override_option(
+                "server.metrics_addr",
+                overrides.metrics_addr.map(|a| a.to_string()),
+            )?
+            .set_override_option(
+                "server.websocket_addr",
+                overrides.websocket_addr.map(|a| a.to_string()),
+            )?
+            .set_override_option(
+                "server.rest_addr",
+                overrides.rest_addr.map(|a| a.to_string()),
+            )?
+            .set_override_option(
+                "server.grpc_uds_path",
+                overrides
+                    .grpc_uds_path
+                    .as_ref()
+                    .map(|path| path.display().to_string()),
+            )?
             .build()?;
 
         Ok(AppConfig { config })
     }
 
+    /// returns the address the gRPC server listens on, or None if it only listens on the Unix domain socket.
+    pub fn grpc_addr(&self) -> Result<Option<SocketAddr>> {
+        self.optional_addr("server.grpc_addr")
+    }
+
+    /// returns the path of the Unix domain socket the gRPC server listens on, or None if it doesn't.
+    pub fn grpc_uds_path(&self) -> Result<Option<PathBuf>> {
+        match self.config.get::<PathBuf>("server.grpc_uds_path") {
+            Ok(path) => Ok(Some(path)),
+            Err(ConfigError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// returns the address the prometheus metrics are served on.
+    pub fn metrics_addr(&self) -> Result<SocketAddr> {
+        Ok(self.config.get::<String>("server.metrics_addr")?.parse()?)
+    }
+
+    /// returns the address the WebSocket gateway listens on, or None if it's disabled.
+    pub fn websocket_addr(&self) -> Result<Option<SocketAddr>> {
+        self.optional_addr("server.websocket_addr")
+    }
+
+    /// returns the address the REST API listens on, or None if it's disabled.
+    pub fn rest_addr(&self) -> Result<Option<SocketAddr>> {
+        self.optional_addr("server.rest_addr")
+    }
+
+    // optional listen addresses are disabled if they're missing.
+    fn optional_addr(&self, key: &str) -> Result<Option<SocketAddr>> {
+        match self.config.get::<String>(key) {
+            Ok(addr) => Ok(Some(addr.parse()?)),
+            Err(ConfigError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// returns how often the settings file is checked for changes, or None if it isn't.
+    pub fn reload_check_interval(&self) -> Result<Option<Duration>> {
+        match self.config.get::<u64>("server.reload_check_interval_s")? {
+            0 => Ok(None),
+            s => Ok(Some(Duration::from_secs(s))),
+        }
+    }
+
+    /// returns the TLS settings from the [server.tls] section, or None if the server is plaintext.
+    pub fn tls_config(&self) -> Result<Option<TlsConfig>> {
+        let cert_path = match self.config.get::<PathBuf>("server.tls.cert_path") {
+            Ok(path) => path,
+            Err(ConfigError::NotFound(_)) => return Ok(None),
+            Err(e) => Err(e)?,
+        };
+
+        let client_ca_path = match self.config.get::<PathBuf>("server.tls.client_ca_path") {
+            Ok(path) => Some(path),
+            Err(ConfigError::NotFound(_)) => None,
+            Err(e) => Err(e)?,
+        };
+
+        Ok(Some(TlsConfig {
+            cert_path,
+            key_path: self.config.get("server.tls.key_path")?,
+            client_ca_path,
+        }))
+    }
+
+    /// returns the gRPC-Web settings, or None if gRPC-Web requests aren't accepted.
+    pub fn grpc_web_config(&self) -> Result<Option<GrpcWebConfig>> {
+        match self.config.get::<GrpcWebConfig>("server.grpc_web") {
+            Ok(grpc_web) => Ok(Some(grpc_web)),
+            Err(ConfigError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// returns the multicast feed settings, or None if the feed isn't published.
+    pub fn multicast_config(&self) -> Result<Option<MulticastConfig>> {
+        match self.config.get::<MulticastConfig>("server.multicast") {
+            Ok(multicast) => Ok(Some(multicast)),
+            Err(ConfigError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// returns the shared memory feed settings, or None if the feed isn't written.
+    pub fn shared_memory_config(&self) -> Result<Option<SharedMemoryConfig>> {
+        match self
+            .config
+            .get::<SharedMemoryConfig>("server.shared_memory")
+        {
+            Ok(shared_memory) => Ok(Some(shared_memory)),
+            Err(ConfigError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// returns the FIX acceptor settings, or None if FIX isn't served.
+    pub fn fix_config(&self) -> Result<Option<FixConfig>> {
+        match self.config.get::<FixConfig>("server.fix") {
+            Ok(fix) => Ok(Some(fix)),
+            Err(ConfigError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// returns the client authentication settings from the [auth] section, or None if clients aren't authenticated.
+    pub fn auth_config(&self) -> Result<Option<AuthConfig>> {
+        match self.config.get::<AuthConfig>("auth") {
+            Ok(auth) => Ok(Some(auth)),
+            Err(ConfigError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// returns how many exchanges must be streaming for the server to report itself as healthy.
+    pub fn min_streaming_exchanges(&self) -> Result<usize> {
+        Ok(self.config.get("server.min_streaming_exchanges")?)
+    }
+
     pub fn spot_pair(&self) -> Result<String> {
         Ok(self.config.get("pair")?)
     }
@@ -54,17 +351,19 @@ impl AppConfig {
         let mut exchange_configs = vec![];
         for id in self.enabled_exchanges()?.into_iter() {
             // get the conf.
-            let endpoint = self.config.get::<String>(&*format!("{}.endpoint", id))?;
+            let endpoint = self.config.get::<String>(&format!("{}.endpoint", id))?;
 
             let subscription_message_template = self
                 .config
-                .get::<String>(&*format!("{}.subscription_message_template", id))?;
+                .get::<String>(&format!("{}.subscription_message_template", id))?;
 
             let spot_pair = self.spot_pair()?;
 
             let receive_timeout_s = self
                 .config
-                .get::<u64>(&*format!("{}.receive_timeout_s", id))?;
+                .get::<u64>(&format!("{}.receive_timeout_s", id))?;
+
+            let trades = self.trades_config(&id)?;
 
             exchange_configs.push(ExchangeConfig {
                 id,
@@ -72,25 +371,501 @@ impl AppConfig {
                 subscription_message_template,
                 spot_pair,
                 receive_timeout_s,
+                trades,
             });
         }
 
         Ok(exchange_configs)
     }
+
+    // trades are configured if the exchange has a trades_subscription_message_template.
+    fn trades_config(&self, id: &str) -> Result<Option<TradesConfig>> {
+        let subscription_message_template = match self
+            .config
+            .get::<String>(&format!("{}.trades_subscription_message_template", id))
+        {
+            Ok(template) => template,
+            Err(ConfigError::NotFound(_)) => return Ok(None),
+            Err(e) => Err(e)?,
+        };
+
+        let receive_timeout_s = self
+            .config
+            .get::<u64>(&format!("{}.trades_receive_timeout_s", id))?;
+
+        Ok(Some(TradesConfig {
+            subscription_message_template,
+            receive_timeout_s,
+        }))
+    }
+
+    /// returns the intervals (in seconds) that candles are built for.
+    pub fn candle_intervals(&self) -> Result<Vec<u64>> {
+        Ok(self.config.get("candles.intervals_s")?)
+    }
+
+    /// returns the thresholds for the spoofing detector from the [spoofing] config section.
+    pub fn spoofing_config(&self) -> Result<SpoofingConfig> {
+        Ok(SpoofingConfig {
+            max_lifetime_ms: self.config.get("spoofing.max_lifetime_ms")?,
+            min_amount: self.config.get("spoofing.min_amount")?,
+            fade_ratio: self.config.get("spoofing.fade_ratio")?,
+            layering_window_ms: self.config.get("spoofing.layering_window_ms")?,
+            layering_min_levels: self.config.get("spoofing.layering_min_levels")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// every problem found validating the configuration.
+pub struct ValidationReport {
+    pub problems: Vec<String>,
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "found {} problem(s) in the configuration:",
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+impl AppConfig {
+    /// validates every setting the server uses, so problems are found before anything connects.
+    /// All the problems are returned in one report rather than stopping at the first.
+    pub fn validate(&self) -> std::result::Result<(), ValidationReport> {
+        let mut problems = vec![];
+
+        if let Some(addr) = self.require::<String>("server.metrics_addr", &mut problems) {
+            if let Err(e) = addr.parse::<SocketAddr>() {
+                problems.push(format!(
+                    "server.metrics_addr is not a valid address ({}): {}",
+                    addr, e
+                ));
+            }
+        }
+        for key in [
+            "server.grpc_addr",
+            "server.websocket_addr",
+            "server.rest_addr",
+        ] {
+            if let Ok(addr) = self.config.get::<String>(key) {
+                if let Err(e) = addr.parse::<SocketAddr>() {
+                    problems.push(format!("{} is not a valid address ({}): {}", key, addr, e));
+                }
+            }
+        }
+
+        self.validate_grpc_uds_path(&mut problems);
+        self.require::<u64>("server.reload_check_interval_s", &mut problems);
+        self.validate_tls(&mut problems);
+        self.validate_grpc_web(&mut problems);
+        self.validate_multicast(&mut problems);
+        self.validate_shared_memory(&mut problems);
+        self.validate_fix(&mut problems);
+
+        let pair = self.require::<String>("pair", &mut problems);
+        if let Some(pair) = &pair {
+            if pair.is_empty() || !pair.chars().all(|c| c.is_ascii_alphanumeric()) {
+                problems.push(format!(
+                    "pair must be letters and digits only, eg BTCUSDT: {:?}",
+                    pair
+                ));
+            }
+        }
+
+        let enabled_exchanges = self
+            .require::<Vec<String>>("enabled_exchanges", &mut problems)
+            .unwrap_or_default();
+        if enabled_exchanges.is_empty() {
+            problems.push("enabled_exchanges must have at least one exchange".to_string());
+        }
+        for id in duplicates(&enabled_exchanges) {
+            problems.push(format!("enabled_exchanges has {} more than once", id));
+        }
+        for id in enabled_exchanges.iter().collect::<HashSet<_>>() {
+            self.validate_exchange(id, pair.as_deref(), &mut problems);
+        }
+
+        if let Some(min_streaming) =
+            self.require::<usize>("server.min_streaming_exchanges", &mut problems)
+        {
+            let exchanges = enabled_exchanges.iter().collect::<HashSet<_>>().len();
+            if min_streaming > exchanges {
+                problems.push(format!(
+                    "server.min_streaming_exchanges ({}) is more than the enabled exchanges ({}), so the server would never be healthy",
+                    min_streaming, exchanges
+                ));
+            }
+        }
+
+        self.validate_auth(&mut problems);
+
+        if let Some(intervals) = self.require::<Vec<u64>>("candles.intervals_s", &mut problems) {
+            if intervals.contains(&0) {
+                problems.push("candles.intervals_s must be greater than 0".to_string());
+            }
+            for interval in duplicates(&intervals) {
+                problems.push(format!(
+                    "candles.intervals_s has {} more than once",
+                    interval
+                ));
+            }
+        }
+
+        self.require::<u64>("spoofing.max_lifetime_ms", &mut problems);
+        self.require::<u64>("spoofing.layering_window_ms", &mut problems);
+        if let Some(min_amount) = self.require::<f64>("spoofing.min_amount", &mut problems) {
+            if min_amount < 0.0 {
+                problems.push("spoofing.min_amount must not be negative".to_string());
+            }
+        }
+        if let Some(fade_ratio) = self.require::<f64>("spoofing.fade_ratio", &mut problems) {
+            if fade_ratio <= 0.0 || fade_ratio > 1.0 {
+                problems
+                    .push("spoofing.fade_ratio must be greater than 0 and at most 1".to_string());
+            }
+        }
+        if let Some(0) = self.require::<usize>("spoofing.layering_min_levels", &mut problems) {
+            problems.push("spoofing.layering_min_levels must be greater than 0".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationReport { problems })
+        }
+    }
+
+    fn validate_exchange(&self, id: &str, pair: Option<&str>, problems: &mut Vec<String>) {
+        if !SUPPORTED_EXCHANGES.contains(&id) {
+            problems.push(format!(
+                "unknown exchange id: {} (supported exchanges are {})",
+                id,
+                SUPPORTED_EXCHANGES.join(", ")
+            ));
+        }
+        if self.config.get_table(id).is_err() {
+            problems.push(format!("enabled exchange {} has no [{}] section", id, id));
+            return;
+        }
+
+        if let Some(endpoint) = self.require::<String>(&format!("{}.endpoint", id), problems) {
+            if !endpoint.starts_with("ws://") && !endpoint.starts_with("wss://") {
+                problems.push(format!(
+                    "{}.endpoint must be a ws:// or wss:// url: {}",
+                    id, endpoint
+                ));
+            }
+        }
+
+        let key = format!("{}.subscription_message_template", id);
+        if let Some(template) = self.require::<String>(&key, problems) {
+            validate_template(&key, &template, pair, problems);
+        }
+        self.require_timeout(&format!("{}.receive_timeout_s", id), problems);
+
+        // trades are optional, but need a timeout if they're configured.
+        let key = format!("{}.trades_subscription_message_template", id);
+        match self.config.get::<String>(&key) {
+            Ok(template) => {
+                validate_template(&key, &template, pair, problems);
+                self.require_timeout(&format!("{}.trades_receive_timeout_s", id), problems);
+            }
+            Err(ConfigError::NotFound(_)) => {}
+            Err(e) => problems.push(format!("{} is invalid: {}", key, e)),
+        }
+    }
+
+    // tls is optional, but needs a certificate and key that exist if it's configured.
+    fn validate_tls(&self, problems: &mut Vec<String>) {
+        let configured = ["cert_path", "key_path", "client_ca_path"]
+            .iter()
+            .any(|key| {
+                self.config
+                    .get::<String>(&format!("server.tls.{}", key))
+                    .is_ok()
+            });
+        if !configured {
+            return;
+        }
+
+        for key in ["server.tls.cert_path", "server.tls.key_path"] {
+            if let Some(path) = self.require::<PathBuf>(key, problems) {
+                require_file(key, &path, problems);
+            }
+        }
+        if let Ok(path) = self.config.get::<PathBuf>("server.tls.client_ca_path") {
+            require_file("server.tls.client_ca_path", &path, problems);
+        }
+    }
+
+    // origins must be like they are in the browser's Origin header, eg https://trading.example.com
+    fn validate_grpc_web(&self, problems: &mut Vec<String>) {
+        let grpc_web = match self.config.get::<GrpcWebConfig>("server.grpc_web") {
+            Ok(grpc_web) => grpc_web,
+            Err(ConfigError::NotFound(_)) => return,
+            Err(e) => return problems.push(format!("server.grpc_web is invalid: {}", e)),
+        };
+
+        let origins = &grpc_web.allowed_origins;
+        if origins.is_empty() {
+            problems.push("server.grpc_web.allowed_origins must not be empty".to_string());
+        }
+        if origins.len() > 1 && origins.iter().any(|origin| origin == "*") {
+            problems.push(
+                "server.grpc_web.allowed_origins can't have \"*\" and other origins".to_string(),
+            );
+        }
+        for origin in origins.iter().filter(|origin| *origin != "*") {
+            let valid = (origin.starts_with("http://") || origin.starts_with("https://"))
+                && !origin.ends_with('/')
+                && origin.chars().all(|c| c.is_ascii_graphic());
+            if !valid {
+                problems.push(format!(
+                    "server.grpc_web.allowed_origins has an invalid origin: {:?} (eg https://trading.example.com)",
+                    origin
+                ));
+            }
+        }
+    }
+
+    // the gRPC server listens on TCP, a Unix domain socket, or both.
+    fn validate_grpc_uds_path(&self, problems: &mut Vec<String>) {
+        let path = match self.config.get::<PathBuf>("server.grpc_uds_path") {
+            Ok(path) => path,
+            Err(ConfigError::NotFound(_)) => {
+                if let Err(ConfigError::NotFound(_)) = self.config.get::<String>("server.grpc_addr")
+                {
+                    problems
+                        .push("server.grpc_addr or server.grpc_uds_path is required".to_string());
+                }
+                return;
+            }
+            Err(e) => return problems.push(format!("server.grpc_uds_path is invalid: {}", e)),
+        };
+
+        match path.parent() {
+            Some(dir) if dir.as_os_str().is_empty() || dir.is_dir() => {}
+            _ => problems.push(format!(
+                "server.grpc_uds_path must be in an existing directory: {}",
+                path.display()
+            )),
+        }
+    }
+
+    fn validate_multicast(&self, problems: &mut Vec<String>) {
+        let multicast = match self.config.get::<MulticastConfig>("server.multicast") {
+            Ok(multicast) => multicast,
+            Err(ConfigError::NotFound(_)) => return,
+            Err(e) => return problems.push(format!("server.multicast is invalid: {}", e)),
+        };
+
+        match multicast.group_addr {
+            SocketAddr::V4(addr) if addr.ip().is_multicast() => {}
+            addr => problems.push(format!(
+                "server.multicast.group_addr must be an IPv4 multicast address, eg 239.255.0.1:30001: {}",
+                addr
+            )),
+        }
+        if multicast.ttl == 0 || multicast.ttl > 255 {
+            problems.push(format!(
+                "server.multicast.ttl must be between 1 and 255: {}",
+                multicast.ttl
+            ));
+        }
+        if multicast.snapshot_interval_ms == 0 {
+            problems.push("server.multicast.snapshot_interval_ms must be more than 0".to_string());
+        }
+        if multicast.retransmit_buffer == 0 {
+            problems.push("server.multicast.retransmit_buffer must be more than 0".to_string());
+        }
+    }
+
+    fn validate_shared_memory(&self, problems: &mut Vec<String>) {
+        let shared_memory = match self
+            .config
+            .get::<SharedMemoryConfig>("server.shared_memory")
+        {
+            Ok(shared_memory) => shared_memory,
+            Err(ConfigError::NotFound(_)) => return,
+            Err(e) => return problems.push(format!("server.shared_memory is invalid: {}", e)),
+        };
+
+        match shared_memory.path.parent() {
+            Some(dir) if dir.as_os_str().is_empty() || dir.is_dir() => {}
+            _ => problems.push(format!(
+                "server.shared_memory.path must be in an existing directory: {}",
+                shared_memory.path.display()
+            )),
+        }
+        if shared_memory.slots == 0 {
+            problems.push("server.shared_memory.slots must be more than 0".to_string());
+        }
+    }
+
+    fn validate_fix(&self, problems: &mut Vec<String>) {
+        let fix = match self.config.get::<FixConfig>("server.fix") {
+            Ok(fix) => fix,
+            Err(ConfigError::NotFound(_)) => return,
+            Err(e) => return problems.push(format!("server.fix is invalid: {}", e)),
+        };
+
+        if fix.sender_comp_id.is_empty()
+            || !fix
+                .sender_comp_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        {
+            problems.push(format!(
+                "server.fix.sender_comp_id must be letters, digits, '-', '_' or '.': {:?}",
+                fix.sender_comp_id
+            ));
+        }
+    }
+
+    // auth is optional, but needs a way to authenticate if it's configured.
+    fn validate_auth(&self, problems: &mut Vec<String>) {
+        let auth = match self.config.get::<AuthConfig>("auth") {
+            Ok(auth) => auth,
+            Err(ConfigError::NotFound(_)) => return,
+            Err(e) => return problems.push(format!("auth is invalid: {}", e)),
+        };
+
+        if auth.keys.is_empty() && auth.jwt_secret.is_none() {
+            problems.push("auth must have keys or a jwt_secret".to_string());
+        }
+        if let Some("") = auth.jwt_secret.as_deref() {
+            problems.push("auth.jwt_secret must not be empty".to_string());
+        }
+
+        for key in &auth.keys {
+            if key.client.is_empty() || key.token.is_empty() {
+                problems.push("auth.keys must have a client and token".to_string());
+            }
+            for rpc in key.rpcs.iter().flatten() {
+                if !auth::RPCS.contains(&rpc.as_str()) {
+                    problems.push(format!(
+                        "auth.keys for {} has unknown rpc: {} (rpcs are {})",
+                        key.client,
+                        rpc,
+                        auth::RPCS.join(", ")
+                    ));
+                }
+            }
+            for id in key.exchanges.iter().flatten() {
+                if !SUPPORTED_EXCHANGES.contains(&id.as_str()) {
+                    problems.push(format!(
+                        "auth.keys for {} has unknown exchange id: {}",
+                        key.client, id
+                    ));
+                }
+            }
+        }
+
+        let tokens: Vec<&String> = auth.keys.iter().map(|key| &key.token).collect();
+        if !duplicates(&tokens).is_empty() {
+            problems.push("auth.keys has the same token more than once".to_string());
+        }
+    }
+
+    // returns the value of the key, or adds a problem if it's missing or the wrong type.
+    fn require<'de, T: Deserialize<'de>>(
+        &self,
+        key: &str,
+        problems: &mut Vec<String>,
+    ) -> Option<T> {
+        match self.config.get::<T>(key) {
+            Ok(value) => Some(value),
+            Err(ConfigError::NotFound(_)) => {
+                problems.push(format!("{} is missing", key));
+                None
+            }
+            Err(e) => {
+                problems.push(format!("{} is invalid: {}", key, e));
+                None
+            }
+        }
+    }
+
+    fn require_timeout(&self, key: &str, problems: &mut Vec<String>) {
+        if let Some(0) = self.require::<u64>(key, problems) {
+            problems.push(format!("{} must be greater than 0", key));
+        }
+    }
+}
+
+fn require_file(key: &str, path: &Path, problems: &mut Vec<String>) {
+    if !path.is_file() {
+        problems.push(format!("{} is not a file: {}", key, path.display()));
+    }
+}
+
+// subscription templates must include the pair, and be valid json once the pair is rendered.
+fn validate_template(key: &str, template: &str, pair: Option<&str>, problems: &mut Vec<String>) {
+    if !template.contains("{{pair}}") {
+        problems.push(format!("{} must contain {{{{pair}}}}", key));
+    }
+    let rendered = template.replace("{{pair}}", &pair.unwrap_or_default().to_lowercase());
+    if let Err(e) = serde_json::from_str::<serde_json::Value>(&rendered) {
+        problems.push(format!("{} is not valid json: {}", key, e));
+    }
+}
+
+/// returns the path of the settings file, which may have been given without the .toml extension.
+pub fn settings_path(settings: &str) -> PathBuf {
+    let path = PathBuf::from(settings);
+    if path.extension().is_some() {
+        path
+    } else {
+        path.with_extension("toml")
+    }
+}
+
+// returns each value that appears more than once, in order of its second appearance.
+fn duplicates<T: Eq + std::hash::Hash + Clone>(values: &[T]) -> Vec<T> {
+    let mut seen = HashSet::new();
+    values
+        .iter()
+        .filter(|value| !seen.insert(*value))
+        .cloned()
+        .collect()
+}
+
+// environment variables that override settings.
+fn environment() -> Environment {
+    Environment::with_prefix(ENV_PREFIX)
+        .prefix_separator("_")
+        .separator("__")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // a stable file is used for testing.
+    const TEST_SETTINGS: &str = "TestSettings";
+
+    fn test_config() -> Result<AppConfig> {
+        AppConfig::new(TEST_SETTINGS, &Overrides::default())
+    }
+
     #[test]
     fn should_instantiate() {
-        AppConfig::new().unwrap();
+        test_config().unwrap();
     }
 
     #[test]
     fn should_provide_spot_pair() -> Result<()> {
-        let conf = AppConfig::new()?;
+        let conf = test_config()?;
         let spot_pair = conf.spot_pair().unwrap();
 
         assert_eq!(spot_pair, "BTCUSDT");
@@ -100,7 +875,7 @@ mod tests {
 
     #[test]
     fn should_provide_exchange_configs() -> Result<()> {
-        let conf = AppConfig::new()?;
+        let conf = test_config()?;
         let exchange_configs = conf.exchange_configs().unwrap();
 
         assert_eq!(exchange_configs.len(), 2);
@@ -116,6 +891,16 @@ mod tests {
             .to_string(),
             spot_pair: "BTCUSDT".to_string(),
             receive_timeout_s: 20,
+            trades: Some(TradesConfig {
+                subscription_message_template: r#"{
+    "event": "bts:subscribe",
+    "data": {
+        "channel": "live_trades_{{pair}}"
+    }
+}"#
+                .to_string(),
+                receive_timeout_s: 60,
+            }),
         }));
 
         assert!(exchange_configs.contains(&ExchangeConfig {
@@ -131,8 +916,427 @@ mod tests {
             .to_string(),
             spot_pair: "BTCUSDT".to_string(),
             receive_timeout_s: 1,
+            trades: Some(TradesConfig {
+                subscription_message_template: r#"{
+  "method": "SUBSCRIBE",
+  "params": [
+    "{{pair}}@trade"
+  ],
+  "id": 1
+}"#
+                .to_string(),
+                receive_timeout_s: 10,
+            }),
         }));
 
         Ok(())
     }
+
+    #[test]
+    fn should_provide_spoofing_config() -> Result<()> {
+        let conf = test_config()?;
+
+        assert_eq!(
+            conf.spoofing_config()?,
+            SpoofingConfig {
+                max_lifetime_ms: 500,
+                min_amount: 0.5,
+                fade_ratio: 0.75,
+                layering_window_ms: 1000,
+                layering_min_levels: 3,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_provide_candle_intervals() -> Result<()> {
+        let conf = test_config()?;
+
+        assert_eq!(conf.candle_intervals()?, vec![60, 300]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_provide_listen_addresses() -> Result<()> {
+        let conf = test_config()?;
+
+        assert_eq!(conf.grpc_addr()?, Some("[::1]:10000".parse()?));
+        assert_eq!(conf.grpc_uds_path()?, None);
+        assert_eq!(conf.metrics_addr()?, "127.0.0.1:9001".parse()?);
+        assert_eq!(conf.reload_check_interval()?, Some(Duration::from_secs(5)));
+        assert_eq!(conf.min_streaming_exchanges()?, 1);
+        assert_eq!(conf.websocket_addr()?, Some("127.0.0.1:10001".parse()?));
+        assert_eq!(conf.rest_addr()?, Some("127.0.0.1:10002".parse()?));
+        assert_eq!(conf.tls_config()?, None);
+        assert_eq!(conf.auth_config()?, None);
+        assert_eq!(conf.grpc_web_config()?, None);
+        assert_eq!(conf.multicast_config()?, None);
+        assert_eq!(conf.shared_memory_config()?, None);
+        assert_eq!(conf.fix_config()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_find_settings_path_with_or_without_extension() {
+        assert_eq!(settings_path("Settings"), PathBuf::from("Settings.toml"));
+        assert_eq!(
+            settings_path("conf/prod.toml"),
+            PathBuf::from("conf/prod.toml")
+        );
+    }
+
+    #[test]
+    fn should_override_settings_from_environment() -> Result<()> {
+        let variables = [("ORDERBOOK_SERVER__GRPC_ADDR", "0.0.0.0:10001")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let conf = AppConfig::with_environment(
+            TEST_SETTINGS,
+            environment().source(Some(variables)),
+            &Overrides::default(),
+        )?;
+
+        assert_eq!(conf.grpc_addr()?, Some("0.0.0.0:10001".parse()?));
+        assert_eq!(conf.metrics_addr()?, "127.0.0.1:9001".parse()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_provide_tls_config() -> Result<()> {
+        let settings = r#"
+[server.tls]
+cert_path = "certs/server.pem"
+key_path = "certs/server.key"
+client_ca_path = "certs/ca.pem"
+"#;
+        let conf = AppConfig {
+            config: Config::builder()
+                .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+                .build()?,
+        };
+
+        assert_eq!(
+            conf.tls_config()?,
+            Some(TlsConfig {
+                cert_path: PathBuf::from("certs/server.pem"),
+                key_path: PathBuf::from("certs/server.key"),
+                client_ca_path: Some(PathBuf::from("certs/ca.pem")),
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_provide_grpc_web_config() -> Result<()> {
+        let settings = r#"
+[server.grpc_web]
+allowed_origins = ["https://trading.example.com"]
+"#;
+        let conf = AppConfig {
+            config: Config::builder()
+                .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+                .build()?,
+        };
+
+        assert_eq!(
+            conf.grpc_web_config()?,
+            Some(GrpcWebConfig {
+                allowed_origins: vec!["https://trading.example.com".to_string()],
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_provide_multicast_config() -> Result<()> {
+        let settings = r#"
+[server.multicast]
+group_addr = "239.255.0.1:30001"
+"#;
+        let conf = AppConfig {
+            config: Config::builder()
+                .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+                .build()?,
+        };
+
+        assert_eq!(
+            conf.multicast_config()?,
+            Some(MulticastConfig {
+                group_addr: "239.255.0.1:30001".parse()?,
+                interface: None,
+                ttl: 1,
+                snapshot_interval_ms: 1000,
+                retransmit_addr: "127.0.0.1:30002".parse()?,
+                retransmit_buffer: 4096,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_provide_shared_memory_config() -> Result<()> {
+        let settings = r#"
+[server.shared_memory]
+path = "/dev/shm/orderbooks-BTCUSDT"
+"#;
+        let conf = AppConfig {
+            config: Config::builder()
+                .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+                .build()?,
+        };
+
+        assert_eq!(
+            conf.shared_memory_config()?,
+            Some(SharedMemoryConfig {
+                path: PathBuf::from("/dev/shm/orderbooks-BTCUSDT"),
+                slots: 1024,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_provide_fix_config() -> Result<()> {
+        let settings = r#"
+[server.fix]
+addr = "0.0.0.0:9878"
+"#;
+        let conf = AppConfig {
+            config: Config::builder()
+                .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+                .build()?,
+        };
+
+        assert_eq!(
+            conf.fix_config()?,
+            Some(FixConfig {
+                addr: "0.0.0.0:9878".parse()?,
+                sender_comp_id: "ORDERBOOKS".to_string(),
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_provide_auth_config() -> Result<()> {
+        let settings = r#"
+[auth]
+jwt_secret = "secret"
+
+[[auth.keys]]
+client = "research"
+token = "research-key"
+exchanges = ["binance"]
+"#;
+        let conf = AppConfig {
+            config: Config::builder()
+                .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+                .build()?,
+        };
+
+        assert_eq!(
+            conf.auth_config()?,
+            Some(AuthConfig {
+                keys: vec![ApiKeyConfig {
+                    client: "research".to_string(),
+                    token: "research-key".to_string(),
+                    rpcs: None,
+                    exchanges: Some(vec!["binance".to_string()]),
+                    pairs: None,
+                }],
+                jwt_secret: Some("secret".to_string()),
+                jwt_issuer: None,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_overrides_over_environment_and_file() -> Result<()> {
+        let variables = [("ORDERBOOK_SERVER__GRPC_ADDR", "0.0.0.0:10001")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let overrides = Overrides {
+            pair: Some("ETHBTC".to_string()),
+            enabled_exchanges: Some(vec!["binance".to_string()]),
+            grpc_addr: Some("0.0.0.0:10002".parse()?),
+            metrics_addr: None,
+            websocket_addr: None,
+            rest_addr: None,
+            grpc_uds_path: Some(PathBuf::from("/tmp/orderbooks.sock")),
+        };
+        let conf = AppConfig::with_environment(
+            TEST_SETTINGS,
+            environment().source(Some(variables)),
+            &overrides,
+        )?;
+
+        assert_eq!(conf.grpc_addr()?, Some("0.0.0.0:10002".parse()?));
+        assert_eq!(
+            conf.grpc_uds_path()?,
+            Some(PathBuf::from("/tmp/orderbooks.sock"))
+        );
+        assert_eq!(conf.metrics_addr()?, "127.0.0.1:9001".parse()?);
+        let exchange_configs = conf.exchange_configs()?;
+        assert_eq!(exchange_configs.len(), 1);
+        assert_eq!(exchange_configs[0].id, "binance");
+        assert_eq!(exchange_configs[0].spot_pair, "ETHBTC");
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_validate_test_settings() -> Result<()> {
+        test_config()?.validate()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_require_a_grpc_listener() -> Result<()> {
+        let grpc_problems = |settings: &str| -> Result<Vec<String>> {
+            let conf = AppConfig {
+                config: Config::builder()
+                    .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+                    .build()?,
+            };
+            Ok(conf
+                .validate()
+                .unwrap_err()
+                .problems
+                .into_iter()
+                .filter(|problem| problem.contains("grpc"))
+                .collect())
+        };
+
+        assert_eq!(
+            grpc_problems("[server]\nmetrics_addr = \"127.0.0.1:9001\"")?,
+            vec!["server.grpc_addr or server.grpc_uds_path is required"]
+        );
+        let uds_only = format!(
+            "[server]\ngrpc_uds_path = \"{}\"",
+            std::env::temp_dir().join("orderbooks.sock").display()
+        );
+        assert!(grpc_problems(&uds_only)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_every_problem() -> Result<()> {
+        let settings = r#"
+enabled_exchanges = ["binance", "kraken", "binance"]
+pair = "BTC/USDT"
+
+[server]
+grpc_addr = "localhost"
+grpc_uds_path = "missing/orderbooks.sock"
+websocket_addr = "localhost:10001"
+rest_addr = "10002"
+min_streaming_exchanges = 3
+
+[server.tls]
+cert_path = "missing/server.pem"
+
+[server.grpc_web]
+allowed_origins = ["*", "trading.example.com"]
+
+[server.multicast]
+group_addr = "10.0.0.1:30001"
+ttl = 0
+retransmit_addr = "0.0.0.0:30002"
+
+[server.shared_memory]
+path = "missing/orderbooks-BTCUSDT"
+slots = 0
+
+[server.fix]
+addr = "0.0.0.0:9878"
+sender_comp_id = "ORDER BOOKS"
+
+[candles]
+intervals_s = [60, 0, 60]
+
+[[auth.keys]]
+client = "research"
+token = "research-key"
+rpcs = ["BookSummary", "OrderBook"]
+
+[[auth.keys]]
+client = "trading"
+token = "research-key"
+exchanges = ["kraken"]
+
+[spoofing]
+max_lifetime_ms = 500
+min_amount = 0.5
+fade_ratio = 1.5
+layering_window_ms = 1000
+layering_min_levels = 3
+
+[binance]
+endpoint = "https://stream.binance.com"
+subscription_message_template = """{"params": ["btcusdt@depth10"]"""
+receive_timeout_s = 0
+trades_subscription_message_template = """{"params": ["{{pair}}@trade"]}"""
+"#;
+        let conf = AppConfig {
+            config: Config::builder()
+                .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+                .build()?,
+        };
+
+        let mut problems = conf.validate().unwrap_err().problems;
+        problems.sort();
+
+        let mut expected = vec![
+            "server.grpc_addr is not a valid address (localhost): invalid socket address syntax",
+            "server.metrics_addr is missing",
+            "server.grpc_uds_path must be in an existing directory: missing/orderbooks.sock",
+            "server.websocket_addr is not a valid address (localhost:10001): invalid socket address syntax",
+            "server.rest_addr is not a valid address (10002): invalid socket address syntax",
+            "server.reload_check_interval_s is missing",
+            "server.tls.cert_path is not a file: missing/server.pem",
+            "server.tls.key_path is missing",
+            r#"server.grpc_web.allowed_origins can't have "*" and other origins"#,
+            r#"server.grpc_web.allowed_origins has an invalid origin: "trading.example.com" (eg https://trading.example.com)"#,
+            "server.multicast.group_addr must be an IPv4 multicast address, eg 239.255.0.1:30001: 10.0.0.1:30001",
+            "server.multicast.ttl must be between 1 and 255: 0",
+            "server.shared_memory.path must be in an existing directory: missing/orderbooks-BTCUSDT",
+            "server.shared_memory.slots must be more than 0",
+            r#"server.fix.sender_comp_id must be letters, digits, '-', '_' or '.': "ORDER BOOKS""#,
+            "server.min_streaming_exchanges (3) is more than the enabled exchanges (2), so the server would never be healthy",
+            r#"pair must be letters and digits only, eg BTCUSDT: "BTC/USDT""#,
+            "enabled_exchanges has binance more than once",
+            "unknown exchange id: kraken (supported exchanges are binance, bitstamp)",
+            "enabled exchange kraken has no [kraken] section",
+            "binance.endpoint must be a ws:// or wss:// url: https://stream.binance.com",
+            "binance.subscription_message_template must contain {{pair}}",
+            "binance.subscription_message_template is not valid json: EOF while parsing an object at line 1 column 30",
+            "binance.receive_timeout_s must be greater than 0",
+            "binance.trades_receive_timeout_s is missing",
+            "candles.intervals_s must be greater than 0",
+            "auth.keys for research has unknown rpc: OrderBook (rpcs are BookSummary, BookFeatures, FeatureLayout, SpoofingAlerts, Trades, Candles, ReloadConfig, ExchangeStatus, ExchangeStatusUpdates)",
+            "auth.keys for trading has unknown exchange id: kraken",
+            "auth.keys has the same token more than once",
+            "candles.intervals_s has 60 more than once",
+            "spoofing.fade_ratio must be greater than 0 and at most 1",
+        ];
+        expected.sort();
+        assert_eq!(problems, expected);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,450 @@
+//! contains the authentication of gRPC clients and their entitlements.
+//! When the [auth] section is configured, every OrderbookAggregator request must have an `authorization: Bearer <token>`
+//! header. The token is either one of the static keys from the config, or a JWT signed with the configured secret
+//! (verified locally, there's no call to an identity provider.)
+//! Each key or JWT carries entitlements: the rpcs, exchanges and pairs the client may use (all of them if not given.)
+//! The Authenticator interceptor checks the token and pair, and adds the client's Entitlements to the request.
+//! Each rpc then checks it's allowed (see authorize) and only streams data from the exchanges the client is entitled to.
+//! Rejected requests are logged and counted in the auth.rejected metric (labelled with the reason.)
+//! The health and reflection services aren't authenticated, so load balancers and tools can use them.
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use metrics::counter;
+use serde::Deserialize;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::app_config::AuthConfig;
+
+/// the rpcs that can be given in entitlements.
+pub const RPCS: [&str; 9] = [
+    "BookSummary",
+    "BookFeatures",
+    "FeatureLayout",
+    "SpoofingAlerts",
+    "Trades",
+    "Candles",
+    "ReloadConfig",
+    "ExchangeStatus",
+    "ExchangeStatusUpdates",
+];
+
+/// what a client may use. None means everything is allowed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entitlements {
+    pub client: String,
+    rpcs: Option<HashSet<String>>,
+    exchanges: Option<HashSet<String>>,
+    pairs: Option<HashSet<String>>,
+}
+
+impl Entitlements {
+    pub fn new(
+        client: &str,
+        rpcs: Option<Vec<String>>,
+        exchanges: Option<Vec<String>>,
+        pairs: Option<Vec<String>>,
+    ) -> Entitlements {
+        let set = |values: Option<Vec<String>>| values.map(|v| v.into_iter().collect());
+        Entitlements {
+            client: client.to_string(),
+            rpcs: set(rpcs),
+            exchanges: set(exchanges),
+            pairs: set(pairs),
+        }
+    }
+
+    /// used when authentication isn't configured.
+    pub fn unrestricted() -> Entitlements {
+        Entitlements::new("anonymous", None, None, None)
+    }
+
+    pub fn allows_rpc(&self, rpc: &str) -> bool {
+        allows(&self.rpcs, rpc)
+    }
+
+    pub fn allows_exchange(&self, exchange: &str) -> bool {
+        allows(&self.exchanges, exchange)
+    }
+
+    pub fn allows_pair(&self, pair: &str) -> bool {
+        allows(&self.pairs, pair)
+    }
+
+    /// whether the client is only entitled to some exchanges, so consolidated data must be filtered.
+    pub fn restricts_exchanges(&self) -> bool {
+        self.exchanges.is_some()
+    }
+
+    /// the exchanges the client is entitled to in name order, or None if it's entitled to every exchange.
+    pub fn exchanges(&self) -> Option<Vec<String>> {
+        self.exchanges.as_ref().map(|exchanges| {
+            let mut exchanges: Vec<String> = exchanges.iter().cloned().collect();
+            exchanges.sort();
+            exchanges
+        })
+    }
+}
+
+fn allows(allowed: &Option<HashSet<String>>, value: &str) -> bool {
+    allowed
+        .as_ref()
+        .is_none_or(|allowed| allowed.contains(value))
+}
+
+// the claims of a JWT. The subject is the client, and the entitlements are optional like static keys.
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    rpcs: Option<Vec<String>>,
+    exchanges: Option<Vec<String>>,
+    pairs: Option<Vec<String>>,
+}
+
+struct Keys {
+    // static tokens and the entitlements of the client they belong to.
+    tokens: Vec<(String, Entitlements)>,
+    jwt: Option<(DecodingKey, Validation)>,
+    // the pair the server streams. Clients that aren't entitled to it are rejected.
+    pair: String,
+}
+
+/// the interceptor that authenticates clients. If auth isn't configured every client is allowed.
+#[derive(Clone)]
+pub struct Authenticator {
+    keys: Option<Arc<Keys>>,
+}
+
+impl Authenticator {
+    pub fn new(config: Option<AuthConfig>, pair: &str) -> Authenticator {
+        let keys = config.map(|config| {
+            let tokens = config
+                .keys
+                .into_iter()
+                .map(|key| {
+                    let entitlements =
+                        Entitlements::new(&key.client, key.rpcs, key.exchanges, key.pairs);
+                    (key.token, entitlements)
+                })
+                .collect();
+
+            let jwt = config.jwt_secret.map(|secret| {
+                let mut validation = Validation::new(Algorithm::HS256);
+                if let Some(issuer) = &config.jwt_issuer {
+                    validation.set_issuer(&[issuer]);
+                }
+                (DecodingKey::from_secret(secret.as_bytes()), validation)
+            });
+
+            Arc::new(Keys {
+                tokens,
+                jwt,
+                pair: pair.to_string(),
+            })
+        });
+
+        Authenticator { keys }
+    }
+
+    /// authenticates the client from the request's headers, and checks it's entitled to the server's pair.
+    /// Every client is unrestricted if auth isn't configured.
+    #[allow(clippy::result_large_err)]
+    pub fn entitlements(&self, metadata: &MetadataMap) -> Result<Entitlements, Status> {
+        let keys = match &self.keys {
+            Some(keys) => keys,
+            None => return Ok(Entitlements::unrestricted()),
+        };
+
+        let entitlements = Authenticator::authenticate(keys, metadata)?;
+        if !entitlements.allows_pair(&keys.pair) {
+            return Err(reject(
+                Some(&entitlements.client),
+                "pair",
+                &format!("not entitled to {}", keys.pair),
+            ));
+        }
+        Ok(entitlements)
+    }
+
+    // the rejections are returned as a Status (like the interceptor), as the code depends on the reason.
+    #[allow(clippy::result_large_err)]
+    fn authenticate(keys: &Keys, metadata: &MetadataMap) -> Result<Entitlements, Status> {
+        let token = bearer_token(metadata)
+            .ok_or_else(|| reject(None, "missing_token", "missing bearer token"))?;
+
+        // every key is compared in constant time, so the time taken doesn't reveal how much of a key matched.
+        let mut matched = None;
+        for (key, entitlements) in &keys.tokens {
+            if constant_time_eq(key.as_bytes(), token.as_bytes()) {
+                matched = Some(entitlements);
+            }
+        }
+        if let Some(entitlements) = matched {
+            return Ok(entitlements.clone());
+        }
+
+        let (decoding_key, validation) = keys
+            .jwt
+            .as_ref()
+            .ok_or_else(|| reject(None, "invalid_token", "invalid token"))?;
+        let claims = decode::<Claims>(token, decoding_key, validation)
+            .map_err(|e| reject(None, "invalid_token", &format!("invalid token: {}", e)))?
+            .claims;
+
+        Ok(Entitlements::new(
+            &claims.sub,
+            claims.rpcs,
+            claims.exchanges,
+            claims.pairs,
+        ))
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let entitlements = self.entitlements(request.metadata())?;
+        request.extensions_mut().insert(entitlements);
+        Ok(request)
+    }
+}
+
+/// returns the client's entitlements (added by the interceptor) if it's allowed to use the rpc.
+/// The interceptor adds them even when auth is off, so a request without them is refused rather than let through.
+#[allow(clippy::result_large_err)]
+pub fn authorize<T>(request: &Request<T>, rpc: &str) -> Result<Entitlements, Status> {
+    let entitlements = request
+        .extensions()
+        .get::<Entitlements>()
+        .cloned()
+        .ok_or_else(|| Status::internal("request wasn't authenticated"))?;
+
+    authorize_rpc(&entitlements, rpc)?;
+    Ok(entitlements)
+}
+
+/// rejects the rpc if the client isn't entitled to it.
+#[allow(clippy::result_large_err)]
+pub fn authorize_rpc(entitlements: &Entitlements, rpc: &str) -> Result<(), Status> {
+    if !entitlements.allows_rpc(rpc) {
+        return Err(reject(
+            Some(&entitlements.client),
+            "rpc",
+            &format!("not entitled to {}", rpc),
+        ));
+    }
+    Ok(())
+}
+
+/// rejects a request for an exchange's data if the client isn't entitled to the exchange.
+#[allow(clippy::result_large_err)]
+pub fn authorize_exchange(entitlements: &Entitlements, exchange: &str) -> Result<(), Status> {
+    if !entitlements.allows_exchange(exchange) {
+        return Err(reject(
+            Some(&entitlements.client),
+            "exchange",
+            &format!("not entitled to {}", exchange),
+        ));
+    }
+    Ok(())
+}
+
+/// rejects an rpc that needs data from every exchange if the client is only entitled to some.
+#[allow(clippy::result_large_err)]
+pub fn require_all_exchanges(entitlements: &Entitlements, rpc: &str) -> Result<(), Status> {
+    if entitlements.restricts_exchanges() {
+        return Err(reject(
+            Some(&entitlements.client),
+            "exchange",
+            &format!("{} needs entitlement to every exchange", rpc),
+        ));
+    }
+    Ok(())
+}
+
+// logs and counts the rejection. Clients that couldn't be authenticated are unauthenticated, others are denied.
+fn reject(client: Option<&str>, reason: &'static str, message: &str) -> Status {
+    counter!("auth.rejected", 1, "reason" => reason);
+    match client {
+        Some(client) => {
+            warn!("rejected request from {}: {}", client, message);
+            Status::permission_denied(message)
+        }
+        None => {
+            warn!("rejected unauthenticated request: {}", message);
+            Status::unauthenticated(message)
+        }
+    }
+}
+
+fn bearer_token(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get("authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde::Serialize;
+    use tonic::Code;
+
+    use super::*;
+    use crate::app_config::ApiKeyConfig;
+
+    const SECRET: &str = "test-secret";
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(
+            Some(AuthConfig {
+                keys: vec![
+                    ApiKeyConfig {
+                        client: "research".to_string(),
+                        token: "research-key".to_string(),
+                        rpcs: Some(vec!["BookSummary".to_string()]),
+                        exchanges: Some(vec!["binance".to_string()]),
+                        pairs: None,
+                    },
+                    ApiKeyConfig {
+                        client: "other-pair".to_string(),
+                        token: "other-pair-key".to_string(),
+                        rpcs: None,
+                        exchanges: None,
+                        pairs: Some(vec!["ETHBTC".to_string()]),
+                    },
+                ],
+                jwt_secret: Some(SECRET.to_string()),
+                jwt_issuer: Some("orderbook-tests".to_string()),
+            }),
+            "BTCUSDT",
+        )
+    }
+
+    fn request(token: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(token) = token {
+            request.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+        }
+        request
+    }
+
+    #[derive(Serialize)]
+    struct TestClaims {
+        sub: String,
+        iss: String,
+        exp: u64,
+        exchanges: Option<Vec<String>>,
+    }
+
+    fn jwt(secret: &str, expires_in_s: i64) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let claims = TestClaims {
+            sub: "trading".to_string(),
+            iss: "orderbook-tests".to_string(),
+            exp: (now + expires_in_s) as u64,
+            exchanges: Some(vec!["bitstamp".to_string()]),
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn code(result: Result<Request<()>, Status>) -> Code {
+        result
+            .map(|_| Code::Ok)
+            .unwrap_or_else(|status| status.code())
+    }
+
+    #[test]
+    fn should_authenticate_static_keys() {
+        let request = authenticator().call(request(Some("research-key"))).unwrap();
+
+        let entitlements = authorize(&request, "BookSummary").unwrap();
+        assert_eq!(entitlements.client, "research");
+        assert!(entitlements.allows_exchange("binance"));
+        assert!(!entitlements.allows_exchange("bitstamp"));
+        assert_eq!(
+            authorize(&request, "Trades").unwrap_err().code(),
+            Code::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn should_authenticate_jwts() {
+        let request = authenticator()
+            .call(request(Some(&jwt(SECRET, 60))))
+            .unwrap();
+
+        let entitlements = authorize(&request, "Trades").unwrap();
+        assert_eq!(entitlements.client, "trading");
+        assert!(entitlements.allows_exchange("bitstamp"));
+        assert!(!entitlements.allows_exchange("binance"));
+    }
+
+    #[test]
+    fn should_reject_invalid_tokens() {
+        let mut authenticator = authenticator();
+
+        assert_eq!(
+            code(authenticator.call(request(None))),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            code(authenticator.call(request(Some("wrong-key")))),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            code(authenticator.call(request(Some(&jwt("wrong-secret", 60))))),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            code(authenticator.call(request(Some(&jwt(SECRET, -120))))),
+            Code::Unauthenticated
+        );
+        // authenticated, but not entitled to the server's pair.
+        assert_eq!(
+            code(authenticator.call(request(Some("other-pair-key")))),
+            Code::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn should_allow_everything_without_auth() {
+        let request = Authenticator::new(None, "BTCUSDT")
+            .call(request(None))
+            .unwrap();
+
+        let entitlements = authorize(&request, "ReloadConfig").unwrap();
+        assert!(!entitlements.restricts_exchanges());
+        assert!(require_all_exchanges(&entitlements, "BookFeatures").is_ok());
+    }
+
+    #[test]
+    fn should_refuse_requests_that_skipped_the_interceptor() {
+        assert_eq!(
+            authorize(&request(None), "BookSummary").unwrap_err().code(),
+            Code::Internal
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! contains the OHLCV candle builder.
+//! Candles are built for each configured interval from the mid price and the ingested trades,
+//! for each exchange (using the exchange's own top of book) and consolidated across all exchanges.
+//! Intervals are aligned to the unix epoch using the local clock, so eg 60s candles open on the minute.
+use std::collections::HashMap;
+
+use crate::orderbook::{Candle, Trade};
+use crate::result::Result;
+
+// source of the candles built across all exchanges.
+pub const CONSOLIDATED: &str = "consolidated";
+
+pub struct CandleBuilder {
+    intervals_s: Vec<u64>,
+    // candles being built, keyed by source (exchange or CONSOLIDATED) and interval.
+    open: HashMap<(String, u64), Candle>,
+    // last mid price of each source. Used to open a candle if a trade arrives before a price update.
+    last_price: HashMap<String, f64>,
+}
+
+impl CandleBuilder {
+    /// returns an error if an interval is 0s, as candles couldn't be aligned to it.
+    pub fn new(intervals_s: Vec<u64>) -> Result<CandleBuilder> {
+        if intervals_s.contains(&0) {
+            return Err("candle intervals must be greater than 0".into());
+        }
+        Ok(CandleBuilder {
+            intervals_s,
+            open: HashMap::new(),
+            last_price: HashMap::new(),
+        })
+    }
+
+    /// records a mid price for the source and returns any candles closed by moving into a new interval.
+    pub fn update_price(&mut self, source: &str, price: f64, now_ms: u64) -> Vec<Candle> {
+        self.last_price.insert(source.to_string(), price);
+
+        let mut closed = vec![];
+        for interval_s in self.intervals_s.clone() {
+            let candle = self.candle(source, interval_s, price, now_ms, &mut closed);
+            candle.high = candle.high.max(price);
+            candle.low = candle.low.min(price);
+            candle.close = price;
+        }
+
+        closed
+    }
+
+    /// adds the trade's volume to the exchange's and the consolidated candles.
+    /// Trades are dropped if there hasn't been a price for the source yet, or if they have no amount.
+    pub fn add_trade(&mut self, trade: &Trade, now_ms: u64) -> Vec<Candle> {
+        let mut closed = vec![];
+        // a trade without an amount has no volume, and would leave the vwap of an empty candle undefined.
+        if trade.amount <= 0.0 {
+            return closed;
+        }
+
+        for source in [trade.exchange.as_str(), CONSOLIDATED] {
+            let price = match self.last_price.get(source) {
+                Some(price) => *price,
+                None => continue,
+            };
+
+            for interval_s in self.intervals_s.clone() {
+                let candle = self.candle(source, interval_s, price, now_ms, &mut closed);
+                let volume = candle.volume + trade.amount;
+                candle.vwap = (candle.vwap * candle.volume + trade.price * trade.amount) / volume;
+                candle.volume = volume;
+                candle.trades += 1;
+            }
+        }
+
+        closed
+    }
+
+    /// closes any candles whose interval has ended. Should be called periodically so candles
+    /// are published even if there are no updates for a source.
+    pub fn close_expired(&mut self, now_ms: u64) -> Vec<Candle> {
+        let expired: Vec<(String, u64)> = self
+            .open
+            .iter()
+            .filter(|(_, candle)| candle.open_time_ms + candle.interval_s * 1000 <= now_ms)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut closed: Vec<Candle> = expired
+            .iter()
+            .filter_map(|key| self.open.remove(key))
+            .collect();
+        closed.sort_by(|a, b| {
+            (a.open_time_ms, &a.source, a.interval_s).cmp(&(
+                b.open_time_ms,
+                &b.source,
+                b.interval_s,
+            ))
+        });
+        closed
+    }
+
+    // returns the open candle for the source and interval containing now_ms.
+    // If the open candle is for an earlier interval it's closed, and a new candle is opened at the price.
+    fn candle(
+        &mut self,
+        source: &str,
+        interval_s: u64,
+        price: f64,
+        now_ms: u64,
+        closed: &mut Vec<Candle>,
+    ) -> &mut Candle {
+        let interval_ms = interval_s * 1000;
+        let open_time_ms = now_ms - now_ms % interval_ms;
+        let key = (source.to_string(), interval_s);
+
+        if let Some(candle) = self.open.get(&key) {
+            if candle.open_time_ms != open_time_ms {
+                closed.extend(self.open.remove(&key));
+            }
+        }
+
+        self.open.entry(key).or_insert_with(|| Candle {
+            source: source.to_string(),
+            interval_s,
+            open_time_ms,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: 0.0,
+            trades: 0,
+            vwap: 0.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(exchange: &str, price: f64, amount: f64) -> Trade {
+        Trade {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+            side: "buy".to_string(),
+            timestamp_ms: 0,
+            trade_id: "1".to_string(),
+        }
+    }
+
+    #[test]
+    fn should_build_candle_from_prices_and_trades() {
+        let mut builder = CandleBuilder::new(vec![60]).unwrap();
+
+        assert!(builder.update_price("binance", 100.0, 60_000).is_empty());
+        builder.update_price("binance", 105.0, 61_000);
+        builder.update_price("binance", 95.0, 62_000);
+        builder.update_price("binance", 101.0, 63_000);
+        builder.add_trade(&trade("binance", 100.0, 1.0), 64_000);
+        builder.add_trade(&trade("binance", 103.0, 2.0), 65_000);
+
+        let closed = builder.update_price("binance", 102.0, 120_000);
+
+        assert_eq!(
+            closed,
+            vec![Candle {
+                source: "binance".to_string(),
+                interval_s: 60,
+                open_time_ms: 60_000,
+                open: 100.0,
+                high: 105.0,
+                low: 95.0,
+                close: 101.0,
+                volume: 3.0,
+                trades: 2,
+                vwap: 306.0 / 3.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn should_add_trades_to_exchange_and_consolidated_candles() {
+        let mut builder = CandleBuilder::new(vec![60]).unwrap();
+        builder.update_price("binance", 100.0, 0);
+        builder.update_price(CONSOLIDATED, 100.5, 0);
+
+        builder.add_trade(&trade("binance", 100.0, 1.0), 1_000);
+        builder.add_trade(&trade("bitstamp", 101.0, 1.0), 1_000);
+
+        let closed = builder.close_expired(60_000);
+
+        assert_eq!(closed.len(), 2);
+        let consolidated = closed.iter().find(|c| c.source == CONSOLIDATED).unwrap();
+        assert_eq!(consolidated.volume, 2.0);
+        assert_eq!(consolidated.trades, 2);
+        let binance = closed.iter().find(|c| c.source == "binance").unwrap();
+        assert_eq!(binance.volume, 1.0);
+    }
+
+    #[test]
+    fn should_only_close_candles_whose_interval_has_ended() {
+        let mut builder = CandleBuilder::new(vec![60, 300]).unwrap();
+        builder.update_price(CONSOLIDATED, 100.0, 0);
+
+        let closed = builder.close_expired(60_000);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].interval_s, 60);
+
+        assert!(builder.close_expired(299_999).is_empty());
+        assert_eq!(builder.close_expired(300_000).len(), 1);
+    }
+
+    #[test]
+    fn should_drop_trades_before_there_is_a_price() {
+        let mut builder = CandleBuilder::new(vec![60]).unwrap();
+
+        builder.add_trade(&trade("binance", 100.0, 1.0), 0);
+
+        assert!(builder.close_expired(60_000).is_empty());
+    }
+
+    #[test]
+    fn should_reject_zero_intervals() {
+        assert!(CandleBuilder::new(vec![60, 0]).is_err());
+    }
+
+    #[test]
+    fn should_ignore_trades_without_an_amount() {
+        let mut builder = CandleBuilder::new(vec![60]).unwrap();
+        builder.update_price("binance", 100.0, 0);
+
+        builder.add_trade(&trade("binance", 100.0, 0.0), 1_000);
+
+        let closed = builder.close_expired(60_000);
+        assert_eq!(closed[0].trades, 0);
+        assert_eq!(closed[0].vwap, 0.0);
+    }
+}
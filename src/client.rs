@@ -1,22 +1,389 @@
-//! orderbook-rs demonstration client - this will connect to a running server and print updates.
+//! orderbook-rs client - this will connect to a running server and write the summaries as a table, JSON lines or CSV,
+//! or show them in a terminal UI (--tui, see client_tui.rs.)
+//! It reconnects (with backoff) if the connection is lost, until --count summaries are written or --duration-s is up.
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use config::{Config, Environment};
+use tokio::net::UnixStream;
+use tokio::time::timeout;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Uri};
+use tonic::{Code, Status};
+use tower::service_fn;
+
+use crate::client_output::{Format, Output};
 use crate::orderbook::orderbook_aggregator_client::*;
 use crate::orderbook::*;
 
+mod client_output;
+mod client_tui;
+
 pub mod orderbook {
     tonic::include_proto!("orderbook");
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = OrderbookAggregatorClient::connect("http://[::1]:10000").await?;
-    let mut stream = client
-        .book_summary(tonic::Request::new(Empty {}))
-        .await?
-        .into_inner();
+// used if the server isn't configured in Settings.toml (eg the client is run without it.)
+const DEFAULT_SERVER_URL: &str = "http://[::1]:10000";
+const INITIAL_BACKOFF_MS: u64 = 250;
+
+/// Connects to a running server and writes the summaries.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// the server to connect to, eg http://[::1]:10000, or unix:/path/to/socket for a server's Unix domain socket.
+    /// Defaults to client.server_url in Settings.toml, which can be overridden by the ORDERBOOK_CLIENT__SERVER_URL
+    /// environment variable.
+    #[arg(long)]
+    addr: Option<String>,
+
+    /// PEM encoded CA certificate to verify the server's certificate with. Enables TLS, so the server url must be https://.
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+
+    /// PEM encoded client certificate, for servers that require client certificates (mutual TLS.)
+    #[arg(long, requires_all = ["key", "ca_cert"])]
+    cert: Option<PathBuf>,
+
+    /// PEM encoded private key of the client certificate.
+    #[arg(long, requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// the name the server's certificate is verified against. Defaults to the server url's host.
+    #[arg(long, requires = "ca_cert")]
+    domain: Option<String>,
+
+    /// bearer token (a static key or JWT) for servers that authenticate clients.
+    #[arg(long)]
+    token: Option<String>,
+
+    /// the pair to stream, eg BTCUSDT. The server streams one pair, and rejects requests for others.
+    #[arg(long)]
+    pair: Option<String>,
+
+    /// the number of levels of each side to write.
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    depth: u32,
+
+    /// how the summaries are written.
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// exit after this many summaries.
+    #[arg(long)]
+    count: Option<u64>,
+
+    /// exit after this many seconds.
+    #[arg(long)]
+    duration_s: Option<u64>,
+
+    /// the longest wait between reconnects. The wait doubles from 250ms while the server can't be reached.
+    #[arg(long, default_value_t = 30_000)]
+    max_backoff_ms: u64,
+
+    /// exit rather than reconnect if the connection is lost.
+    #[arg(long)]
+    no_reconnect: bool,
+
+    /// show a live depth ladder, each exchange's best bid and ask and the exchanges' status, rather than writing the summaries.
+    #[arg(long, conflicts_with_all = ["format", "count", "duration_s"])]
+    tui: bool,
+}
+
+// the flag takes precedence over the environment, which takes precedence over Settings.toml.
+fn server_url(args: &Args) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(addr) = &args.addr {
+        return Ok(addr.clone());
+    }
+
+    let config = Config::builder()
+        .set_default("client.server_url", DEFAULT_SERVER_URL)?
+        .add_source(config::File::with_name("Settings").required(false))
+        .add_source(
+            Environment::with_prefix("ORDERBOOK")
+                .prefix_separator("_")
+                .separator("__"),
+        )
+        .build()?;
+
+    Ok(config.get("client.server_url")?)
+}
+
+// the server's endpoint. Errors here are in the settings, so they aren't retried.
+enum Server {
+    Tcp(Endpoint),
+    // the endpoint's uri isn't used, but it needs a valid one.
+    Uds(Endpoint, PathBuf),
+}
+
+// returns the endpoint of the server, using TLS if a CA certificate is given.
+fn server(args: &Args, server_url: &str) -> Result<Server, Box<dyn std::error::Error>> {
+    if let Some(path) = server_url.strip_prefix("unix:") {
+        if args.ca_cert.is_some() {
+            return Err("TLS isn't supported over Unix domain sockets".into());
+        }
+        return Ok(Server::Uds(
+            Endpoint::try_from("http://[::]:10000")?,
+            PathBuf::from(path),
+        ));
+    }
+
+    let mut endpoint = Channel::from_shared(server_url.to_string())?;
+
+    if let Some(ca_cert) = &args.ca_cert {
+        if !server_url.starts_with("https://") {
+            return Err(format!("TLS requires an https:// server url: {}", server_url).into());
+        }
+
+        let mut tls =
+            ClientTlsConfig::new().ca_certificate(Certificate::from_pem(std::fs::read(ca_cert)?));
+        if let (Some(cert), Some(key)) = (&args.cert, &args.key) {
+            tls = tls.identity(Identity::from_pem(
+                std::fs::read(cert)?,
+                std::fs::read(key)?,
+            ));
+        }
+        if let Some(domain) = &args.domain {
+            tls = tls.domain_name(domain);
+        }
+        endpoint = endpoint.tls_config(tls)?;
+    }
+
+    Ok(Server::Tcp(endpoint))
+}
+
+async fn connect(server: &Server) -> Result<Channel, tonic::transport::Error> {
+    match server {
+        Server::Tcp(endpoint) => endpoint.connect().await,
+        Server::Uds(endpoint, path) => {
+            let path = path.clone();
+            endpoint
+                .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+                .await
+        }
+    }
+}
+
+// the delay before each reconnect, which doubles up to the maximum while the server can't be reached.
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            next: initial.min(max),
+        }
+    }
+
+    fn next(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
 
-    while let Some(feature) = stream.message().await? {
-        println!("{:?}", feature);
+    // called once connected, so the next disconnect reconnects quickly.
+    fn reset(&mut self) {
+        self.next = self.initial.min(self.max);
     }
+}
+
+// why a stream of summaries ended.
+enum Ended {
+    // --count summaries were written, or the output was closed.
+    Done,
+    // the client reconnects after these.
+    Disconnected(String),
+}
+
+// statuses that are the request's fault (eg the token or pair) are returned as errors, others reconnect.
+fn ended(status: Status) -> Result<Ended, Box<dyn std::error::Error>> {
+    match status.code() {
+        Code::Unavailable
+        | Code::Unknown
+        | Code::Internal
+        | Code::Aborted
+        | Code::Cancelled
+        | Code::DeadlineExceeded
+        | Code::ResourceExhausted => Ok(Ended::Disconnected(describe(&status))),
+        _ => Err(describe(&status).into()),
+    }
+}
+
+fn describe(status: &Status) -> String {
+    format!("{:?}: {}", status.code(), status.message())
+}
+
+// the transport's errors only say "transport error", the reason is their source.
+// Some errors include their source's message already, so it isn't repeated.
+fn describe_error(e: &dyn std::error::Error) -> String {
+    let mut description = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        let reason = e.to_string();
+        if !description.ends_with(&reason) {
+            description = format!("{}: {}", description, reason);
+        }
+        source = e.source();
+    }
+    description
+}
+
+// what's done with the summaries: they're written to the output, or shown by the TUI.
+trait Summaries {
+    // returns false to stop streaming, eg the output was closed.
+    fn summary(&mut self, summary: Summary) -> Result<bool, Box<dyn std::error::Error>>;
+
+    fn disconnected(&mut self, reason: &str, reconnect_in: Duration);
+}
+
+impl<W: Write> Summaries for Output<W> {
+    fn summary(&mut self, summary: Summary) -> Result<bool, Box<dyn std::error::Error>> {
+        match self.write(&summary) {
+            Ok(()) => Ok(true),
+            // eg piped to head.
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // messages go to stderr, so the output can be piped.
+    fn disconnected(&mut self, reason: &str, reconnect_in: Duration) {
+        eprintln!("{}, reconnecting in {:?}", reason, reconnect_in);
+    }
+}
+
+// adds the token to the request, for servers that authenticate clients.
+fn request<T>(args: &Args, message: T) -> Result<tonic::Request<T>, Box<dyn std::error::Error>> {
+    let mut request = tonic::Request::new(message);
+    if let Some(token) = &args.token {
+        request
+            .metadata_mut()
+            .insert("authorization", format!("Bearer {}", token).parse()?);
+    }
+    Ok(request)
+}
 
-    Ok(())
+// passes on the summaries until the stream ends or --count is reached. received counts the summaries across reconnects.
+async fn stream_summaries(
+    args: &Args,
+    server: &Server,
+    summaries: &mut impl Summaries,
+    received: &mut u64,
+    backoff: &mut Backoff,
+) -> Result<Ended, Box<dyn std::error::Error>> {
+    let channel = match connect(server).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            return Ok(Ended::Disconnected(format!(
+                "couldn't connect: {}",
+                describe_error(&e)
+            )))
+        }
+    };
+    let mut client = OrderbookAggregatorClient::new(channel);
+    let request = request(
+        args,
+        SummaryRequest {
+            pair: args.pair.clone().unwrap_or_default(),
+            ..Default::default()
+        },
+    )?;
+    let mut stream = match client.book_summary(request).await {
+        Ok(response) => response.into_inner(),
+        Err(status) => return ended(status),
+    };
+
+    loop {
+        let summary = match stream.message().await {
+            Ok(Some(summary)) => summary,
+            Ok(None) => {
+                return Ok(Ended::Disconnected(
+                    "the server closed the stream".to_string(),
+                ))
+            }
+            Err(status) => return ended(status),
+        };
+        backoff.reset();
+
+        if !summaries.summary(summary)? {
+            return Ok(Ended::Done);
+        }
+        *received += 1;
+        if Some(*received) == args.count {
+            return Ok(Ended::Done);
+        }
+    }
+}
+
+// streams the summaries, reconnecting until they're done.
+async fn run(
+    args: &Args,
+    server: &Server,
+    summaries: &mut impl Summaries,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut received = 0;
+    let mut backoff = Backoff::new(
+        Duration::from_millis(INITIAL_BACKOFF_MS),
+        Duration::from_millis(args.max_backoff_ms),
+    );
+
+    loop {
+        match stream_summaries(args, server, summaries, &mut received, &mut backoff).await? {
+            Ended::Done => return Ok(()),
+            Ended::Disconnected(reason) if args.no_reconnect => return Err(reason.into()),
+            Ended::Disconnected(reason) => {
+                let delay = backoff.next();
+                summaries.disconnected(&reason, delay);
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+async fn client(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let server_url = server_url(&args)?;
+    let server = server(&args, &server_url)?;
+    if args.tui {
+        return client_tui::run(args, server_url, server).await;
+    }
+    let mut output = Output::new(args.format, args.depth as usize, std::io::stdout());
+
+    eprintln!("Connecting to {}", server_url);
+    let run = run(&args, &server, &mut output);
+    match args.duration_s {
+        // the time's up, which isn't an error.
+        Some(duration_s) => timeout(Duration::from_secs(duration_s), run)
+            .await
+            .unwrap_or(Ok(())),
+        None => run.await,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = client(Args::parse()).await {
+        eprintln!("error: {}", describe_error(e.as_ref()));
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_double_the_backoff_until_reset() {
+        let mut backoff = Backoff::new(Duration::from_millis(250), Duration::from_secs(1));
+        let delays: Vec<_> = (0..4).map(|_| backoff.next().as_millis()).collect();
+        assert_eq!(delays, vec![250, 500, 1000, 1000]);
+
+        backoff.reset();
+        assert_eq!(backoff.next(), Duration::from_millis(250));
+    }
 }
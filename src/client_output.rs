@@ -0,0 +1,219 @@
+//! contains the client's output formats: a table for people, and JSON lines or CSV for scripts.
+//! Only the top depth levels of each side are written. Each summary is flushed as it's written, so the output can be piped.
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::orderbook::{Level, Summary};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// the bids and asks side by side.
+    Table,
+    /// a JSON object per summary.
+    Json,
+    /// a row per level, with a header.
+    Csv,
+}
+
+// like the server's JSON (see json.rs), with the number of summaries dropped for this client.
+#[derive(Serialize)]
+struct SummaryJson<'a> {
+    sequence: u64,
+    spread: f64,
+    dropped: u64,
+    bids: Vec<LevelJson<'a>>,
+    asks: Vec<LevelJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct LevelJson<'a> {
+    exchange: &'a str,
+    price: f64,
+    amount: f64,
+}
+
+impl<'a> From<&'a Level> for LevelJson<'a> {
+    fn from(level: &'a Level) -> LevelJson<'a> {
+        LevelJson {
+            exchange: &level.exchange,
+            price: level.price,
+            amount: level.amount,
+        }
+    }
+}
+
+pub struct Output<W: Write> {
+    format: Format,
+    depth: usize,
+    out: W,
+    // the CSV header is written before the first row.
+    header_written: bool,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(format: Format, depth: usize, out: W) -> Output<W> {
+        Output {
+            format,
+            depth,
+            out,
+            header_written: false,
+        }
+    }
+
+    pub fn write(&mut self, summary: &Summary) -> io::Result<()> {
+        let bids = &summary.bids[..summary.bids.len().min(self.depth)];
+        let asks = &summary.asks[..summary.asks.len().min(self.depth)];
+        match self.format {
+            Format::Table => self.write_table(summary, bids, asks)?,
+            Format::Json => self.write_json(summary, bids, asks)?,
+            Format::Csv => self.write_csv(summary, bids, asks)?,
+        }
+        self.out.flush()
+    }
+
+    fn write_table(&mut self, summary: &Summary, bids: &[Level], asks: &[Level]) -> io::Result<()> {
+        write!(self.out, "#{} spread {}", summary.sequence, summary.spread)?;
+        if summary.dropped > 0 {
+            write!(self.out, " ({} dropped)", summary.dropped)?;
+        }
+        writeln!(self.out)?;
+        writeln!(
+            self.out,
+            "{:<10} {:>14} {:>14} | {:<14} {:<14} {:>10}",
+            "exchange", "amount", "bid", "ask", "amount", "exchange"
+        )?;
+
+        for row in 0..bids.len().max(asks.len()) {
+            match bids.get(row) {
+                Some(bid) => write!(
+                    self.out,
+                    "{:<10} {:>14} {:>14}",
+                    bid.exchange, bid.amount, bid.price
+                )?,
+                None => write!(self.out, "{:40}", "")?,
+            }
+            match asks.get(row) {
+                Some(ask) => writeln!(
+                    self.out,
+                    " | {:<14} {:<14} {:>10}",
+                    ask.price, ask.amount, ask.exchange
+                )?,
+                None => writeln!(self.out, " |")?,
+            }
+        }
+        writeln!(self.out)
+    }
+
+    fn write_json(&mut self, summary: &Summary, bids: &[Level], asks: &[Level]) -> io::Result<()> {
+        let summary = SummaryJson {
+            sequence: summary.sequence,
+            spread: summary.spread,
+            dropped: summary.dropped,
+            bids: bids.iter().map(LevelJson::from).collect(),
+            asks: asks.iter().map(LevelJson::from).collect(),
+        };
+        serde_json::to_writer(&mut self.out, &summary)?;
+        writeln!(self.out)
+    }
+
+    fn write_csv(&mut self, summary: &Summary, bids: &[Level], asks: &[Level]) -> io::Result<()> {
+        let mut csv = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(&mut self.out);
+        if !self.header_written {
+            csv.write_record([
+                "sequence", "spread", "dropped", "side", "position", "exchange", "price", "amount",
+            ])?;
+            self.header_written = true;
+        }
+
+        for (side, levels) in [("bid", bids), ("ask", asks)] {
+            for (position, level) in levels.iter().enumerate() {
+                csv.write_record([
+                    summary.sequence.to_string(),
+                    summary.spread.to_string(),
+                    summary.dropped.to_string(),
+                    side.to_string(),
+                    (position + 1).to_string(),
+                    level.exchange.clone(),
+                    level.price.to_string(),
+                    level.amount.to_string(),
+                ])?;
+            }
+        }
+        csv.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+        }
+    }
+
+    fn summary() -> Summary {
+        Summary {
+            spread: 1.5,
+            bids: vec![level("binance", 100.0, 1.0), level("bitstamp", 99.5, 2.25)],
+            asks: vec![
+                level("bitstamp", 101.5, 0.5),
+                level("binance", 102.0, 3.0),
+                level("binance", 103.0, 1.0),
+            ],
+            sequence: 7,
+            dropped: 2,
+        }
+    }
+
+    fn output(format: Format, depth: usize, summaries: usize) -> String {
+        let mut output = Output::new(format, depth, vec![]);
+        for _ in 0..summaries {
+            output.write(&summary()).unwrap();
+        }
+        String::from_utf8(output.out).unwrap()
+    }
+
+    #[test]
+    fn should_write_tables() {
+        let expected = "\
+#7 spread 1.5 (2 dropped)
+exchange           amount            bid | ask            amount           exchange
+binance                 1            100 | 101.5          0.5              bitstamp
+bitstamp             2.25           99.5 | 102            3                 binance
+                                         | 103            1                 binance
+
+";
+        assert_eq!(output(Format::Table, 10, 1), expected);
+    }
+
+    #[test]
+    fn should_write_json_lines() {
+        let expected = r#"{"sequence":7,"spread":1.5,"dropped":2,"bids":[{"exchange":"binance","price":100.0,"amount":1.0}],"asks":[{"exchange":"bitstamp","price":101.5,"amount":0.5}]}
+"#;
+        assert_eq!(output(Format::Json, 1, 2), expected.repeat(2));
+    }
+
+    #[test]
+    fn should_write_csv_with_one_header() {
+        let expected = "\
+sequence,spread,dropped,side,position,exchange,price,amount
+7,1.5,2,bid,1,binance,100,1
+7,1.5,2,bid,2,bitstamp,99.5,2.25
+7,1.5,2,ask,1,bitstamp,101.5,0.5
+7,1.5,2,ask,2,binance,102,3
+7,1.5,2,bid,1,binance,100,1
+7,1.5,2,bid,2,bitstamp,99.5,2.25
+7,1.5,2,ask,1,bitstamp,101.5,0.5
+7,1.5,2,ask,2,binance,102,3
+";
+        assert_eq!(output(Format::Csv, 2, 2), expected);
+    }
+}
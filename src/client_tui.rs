@@ -0,0 +1,588 @@
+//! contains the client's terminal UI (--tui), a live view of the book for operators.
+//! It shows a depth ladder of the consolidated book with each exchange's levels in its own colour, the spread, each exchange's
+//! best bid and ask, the rate summaries are received and the exchanges' connection status (from ExchangeStatusUpdates.)
+//! The summaries and statuses are streamed by their own tasks, which reconnect like the client, and the screen is redrawn
+//! at most every REDRAW_INTERVAL. q, Esc or Ctrl-C quits.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table};
+use ratatui::Frame;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::orderbook::orderbook_aggregator_client::OrderbookAggregatorClient;
+use crate::orderbook::{ConnectionState, Empty, ExchangeStatus, Level, Summary};
+use crate::{connect, describe_error, ended, request, Args, Backoff, Ended, Server, Summaries};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+// how often the keyboard thread checks whether the UI has quit.
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// exchanges are given these colours in the order they're first seen.
+const PALETTE: [Color; 6] = [
+    Color::Cyan,
+    Color::Magenta,
+    Color::Yellow,
+    Color::Blue,
+    Color::LightGreen,
+    Color::LightRed,
+];
+
+/// what the streams tell the UI.
+#[derive(Debug)]
+pub enum Update {
+    Summary(Summary, Instant),
+    Statuses(Vec<ExchangeStatus>),
+    // the summaries' connection was lost, and is being retried.
+    Disconnected(String),
+    // the summaries can't be streamed, eg the token was rejected.
+    Failed(String),
+    // the statuses can't be streamed, eg the client isn't entitled to them.
+    StatusUnavailable(String),
+}
+
+// sends the summaries to the UI.
+struct Updates(UnboundedSender<Update>);
+
+impl Summaries for Updates {
+    // stops when the UI has quit.
+    fn summary(&mut self, summary: Summary) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self
+            .0
+            .send(Update::Summary(summary, Instant::now()))
+            .is_ok())
+    }
+
+    fn disconnected(&mut self, reason: &str, reconnect_in: Duration) {
+        let reason = format!("{}, reconnecting in {:?}", reason, reconnect_in);
+        let _ = self.0.send(Update::Disconnected(reason));
+    }
+}
+
+/// what's shown.
+pub struct Dashboard {
+    server_url: String,
+    depth: usize,
+    summary: Option<Summary>,
+    statuses: Vec<ExchangeStatus>,
+    // when the summaries of the last second were received.
+    received: VecDeque<Instant>,
+    // the summaries the server dropped for this client since it connected.
+    dropped: u64,
+    // None while summaries are received.
+    connection: Option<String>,
+    status_error: Option<String>,
+    // the exchanges in the order they were first seen, which gives their colour.
+    exchanges: Vec<String>,
+}
+
+impl Dashboard {
+    pub fn new(server_url: &str, depth: usize) -> Dashboard {
+        Dashboard {
+            server_url: server_url.to_string(),
+            depth,
+            summary: None,
+            statuses: vec![],
+            received: VecDeque::new(),
+            dropped: 0,
+            connection: Some("connecting".to_string()),
+            status_error: None,
+            exchanges: vec![],
+        }
+    }
+
+    pub fn update(&mut self, update: Update) {
+        match update {
+            Update::Summary(summary, received) => {
+                for level in summary.bids.iter().chain(&summary.asks) {
+                    self.add_exchange(&level.exchange);
+                }
+                self.dropped += summary.dropped;
+                self.received.push_back(received);
+                self.summary = Some(summary);
+                self.connection = None;
+            }
+            Update::Statuses(statuses) => {
+                for status in &statuses {
+                    self.add_exchange(&status.exchange);
+                }
+                self.statuses = statuses;
+                self.status_error = None;
+            }
+            Update::Disconnected(reason) => self.connection = Some(reason),
+            Update::Failed(reason) => self.connection = Some(format!("error: {}", reason)),
+            Update::StatusUnavailable(reason) => self.status_error = Some(reason),
+        }
+    }
+
+    fn add_exchange(&mut self, exchange: &str) {
+        if !self.exchanges.iter().any(|e| e == exchange) {
+            self.exchanges.push(exchange.to_string());
+        }
+    }
+
+    fn colour(&self, exchange: &str) -> Color {
+        self.exchanges
+            .iter()
+            .position(|e| e == exchange)
+            .map_or(Color::Reset, |i| PALETTE[i % PALETTE.len()])
+    }
+
+    /// the summaries received in the second before now.
+    pub fn updates_per_s(&mut self, now: Instant) -> usize {
+        while let Some(received) = self.received.front() {
+            if now.duration_since(*received) < Duration::from_secs(1) {
+                break;
+            }
+            self.received.pop_front();
+        }
+        self.received.len()
+    }
+
+    /// each exchange's best bid and ask in the summary. They're None if the exchange has no levels in the top of the book.
+    pub fn best_by_exchange(&self) -> Vec<(&str, Option<&Level>, Option<&Level>)> {
+        fn best<'a>(levels: &'a [Level], exchange: &str) -> Option<&'a Level> {
+            levels.iter().find(|level| level.exchange == exchange)
+        }
+        self.exchanges
+            .iter()
+            .map(|exchange| match &self.summary {
+                Some(summary) => (
+                    exchange.as_str(),
+                    best(&summary.bids, exchange),
+                    best(&summary.asks, exchange),
+                ),
+                None => (exchange.as_str(), None, None),
+            })
+            .collect()
+    }
+}
+
+pub fn render(frame: &mut Frame, dashboard: &mut Dashboard) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [ladder, exchanges] =
+        Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(body);
+    let [best, statuses] = Layout::vertical([
+        Constraint::Length(dashboard.exchanges.len() as u16 + 3),
+        Constraint::Min(0),
+    ])
+    .areas(exchanges);
+
+    render_header(frame, header, dashboard);
+    render_ladder(frame, ladder, dashboard);
+    render_best(frame, best, dashboard);
+    render_statuses(frame, statuses, dashboard);
+    frame.render_widget(
+        Paragraph::new("q quit").style(Style::default().fg(Color::DarkGray)),
+        footer,
+    );
+}
+
+fn render_header(frame: &mut Frame, area: Rect, dashboard: &mut Dashboard) {
+    let updates_per_s = dashboard.updates_per_s(Instant::now());
+    let mut spans = vec![Span::styled(
+        dashboard.server_url.clone(),
+        Style::default().add_modifier(Modifier::BOLD),
+    )];
+    if let Some(summary) = &dashboard.summary {
+        spans.push(Span::raw(format!(
+            "  #{}  spread {}  {} updates/s  {} dropped",
+            summary.sequence, summary.spread, updates_per_s, dashboard.dropped
+        )));
+    }
+    if let Some(connection) = &dashboard.connection {
+        spans.push(Span::styled(
+            format!("  {}", connection),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    frame.render_widget(Paragraph::new(Line::from(spans)), area);
+}
+
+// the asks above the bids, with the best of each next to the spread.
+fn render_ladder(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let row = |level: &Level, bid: bool| {
+        let amount = Cell::from(level.amount.to_string());
+        let (bid_amount, ask_amount) = if bid {
+            (amount, Cell::from(""))
+        } else {
+            (Cell::from(""), amount)
+        };
+        Row::new(vec![
+            Cell::from(level.exchange.clone()),
+            bid_amount,
+            Cell::from(Span::styled(
+                level.price.to_string(),
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            ask_amount,
+        ])
+        .style(Style::default().fg(dashboard.colour(&level.exchange)))
+    };
+
+    let mut rows = vec![];
+    if let Some(summary) = &dashboard.summary {
+        let depth = dashboard.depth;
+        rows.extend(
+            summary
+                .asks
+                .iter()
+                .take(depth)
+                .rev()
+                .map(|ask| row(ask, false)),
+        );
+        rows.push(
+            Row::new(vec![
+                Cell::from(""),
+                Cell::from(""),
+                Cell::from(format!("spread {}", summary.spread)),
+            ])
+            .style(Style::default().fg(Color::DarkGray)),
+        );
+        rows.extend(summary.bids.iter().take(depth).map(|bid| row(bid, true)));
+    }
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(12),
+            Constraint::Length(14),
+            Constraint::Length(16),
+            Constraint::Length(14),
+        ],
+    )
+    .header(
+        Row::new(vec!["exchange", "bid amount", "price", "ask amount"])
+            .style(Style::default().add_modifier(Modifier::UNDERLINED)),
+    )
+    .block(Block::bordered().title("depth"));
+    frame.render_widget(table, area);
+}
+
+fn render_best(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let price = |level: Option<&Level>| level.map_or("-".to_string(), |l| l.price.to_string());
+    let rows = dashboard
+        .best_by_exchange()
+        .into_iter()
+        .map(|(exchange, bid, ask)| {
+            Row::new(vec![exchange.to_string(), price(bid), price(ask)])
+                .style(Style::default().fg(dashboard.colour(exchange)))
+        });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(12),
+            Constraint::Length(16),
+            Constraint::Length(16),
+        ],
+    )
+    .header(
+        Row::new(vec!["exchange", "best bid", "best ask"])
+            .style(Style::default().add_modifier(Modifier::UNDERLINED)),
+    )
+    .block(Block::bordered().title("best by exchange"));
+    frame.render_widget(table, area);
+}
+
+fn render_statuses(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let block = Block::bordered().title("exchange status");
+    if let Some(error) = &dashboard.status_error {
+        frame.render_widget(
+            Paragraph::new(format!("unavailable: {}", error)).block(block),
+            area,
+        );
+        return;
+    }
+
+    let rows = dashboard.statuses.iter().map(|status| {
+        let (state, colour) = match ConnectionState::try_from(status.state) {
+            Ok(ConnectionState::Streaming) => ("streaming", Color::Green),
+            Ok(ConnectionState::Subscribed) => ("subscribed", Color::Yellow),
+            Ok(ConnectionState::Connecting) => ("connecting", Color::Yellow),
+            Ok(ConnectionState::BackingOff) => ("backing off", Color::Red),
+            Err(_) => ("unknown", Color::Red),
+        };
+        Row::new(vec![
+            Cell::from(Span::styled(
+                status.exchange.clone(),
+                Style::default().fg(dashboard.colour(&status.exchange)),
+            )),
+            Cell::from(Span::styled(state, Style::default().fg(colour))),
+            Cell::from(format!("{:.1}", status.messages_per_s)),
+            Cell::from(status.reconnects.to_string()),
+            Cell::from(status.last_error.clone()),
+        ])
+    });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Min(0),
+        ],
+    )
+    .header(
+        Row::new(vec![
+            "exchange",
+            "state",
+            "msg/s",
+            "reconnects",
+            "last error",
+        ])
+        .style(Style::default().add_modifier(Modifier::UNDERLINED)),
+    )
+    .block(block);
+    frame.render_widget(table, area);
+}
+
+// streams the exchange statuses to the UI, reconnecting until the UI quits or the server rejects the request.
+async fn stream_statuses(args: &Args, server: &Server, updates: UnboundedSender<Update>) {
+    let mut backoff = Backoff::new(
+        Duration::from_millis(crate::INITIAL_BACKOFF_MS),
+        Duration::from_millis(args.max_backoff_ms),
+    );
+
+    loop {
+        let result = async {
+            let channel = match connect(server).await {
+                Ok(channel) => channel,
+                Err(e) => return Ok(Ended::Disconnected(describe_error(&e))),
+            };
+            let mut stream = match OrderbookAggregatorClient::new(channel)
+                .exchange_status_updates(request(args, Empty {})?)
+                .await
+            {
+                Ok(response) => response.into_inner(),
+                Err(status) => return ended(status),
+            };
+            loop {
+                match stream.message().await {
+                    Ok(Some(statuses)) => {
+                        backoff.reset();
+                        if updates.send(Update::Statuses(statuses.exchanges)).is_err() {
+                            return Ok(Ended::Done);
+                        }
+                    }
+                    Ok(None) => return Ok(Ended::Disconnected("stream closed".to_string())),
+                    Err(status) => return ended(status),
+                }
+            }
+        }
+        .await;
+
+        match result {
+            Ok(Ended::Done) => return,
+            Ok(Ended::Disconnected(_)) => tokio::time::sleep(backoff.next()).await,
+            Err(e) => {
+                let _ = updates.send(Update::StatusUnavailable(e.to_string()));
+                return;
+            }
+        }
+    }
+}
+
+// sends a message when q, Esc or Ctrl-C is pressed. crossterm's events are read on their own thread as they block.
+fn read_keys(quit: UnboundedSender<()>) {
+    std::thread::spawn(move || {
+        while !quit.is_closed() {
+            if !event::poll(KEY_POLL_INTERVAL).unwrap_or(false) {
+                continue;
+            }
+            if let Ok(Event::Key(key)) = event::read() {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press
+                    && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+                {
+                    let _ = quit.send(());
+                }
+            }
+        }
+    });
+}
+
+/// shows the dashboard until the user quits.
+pub async fn run(
+    args: Args,
+    server_url: String,
+    server: Server,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (updates_tx, mut updates_rx) = unbounded_channel();
+    let (quit_tx, mut quit_rx) = unbounded_channel();
+    let mut dashboard = Dashboard::new(&server_url, args.depth as usize);
+    read_keys(quit_tx);
+
+    // the streams run alongside the UI, and send it their updates.
+    let mut summary_updates = Updates(updates_tx.clone());
+    let summaries = crate::run(&args, &server, &mut summary_updates);
+    let statuses = stream_statuses(&args, &server, updates_tx);
+    tokio::pin!(summaries, statuses);
+    let (mut summaries_ended, mut statuses_ended) = (false, false);
+
+    let mut terminal = ratatui::try_init()?;
+    let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+    let result = loop {
+        tokio::select! {
+            Some(update) = updates_rx.recv() => dashboard.update(update),
+            result = &mut summaries, if !summaries_ended => {
+                summaries_ended = true;
+                if let Err(e) = result {
+                    dashboard.update(Update::Failed(e.to_string()));
+                }
+            }
+            _ = &mut statuses, if !statuses_ended => statuses_ended = true,
+            _ = quit_rx.recv() => break Ok(()),
+            _ = redraw.tick() => {
+                if let Err(e) = terminal.draw(|frame| render(frame, &mut dashboard)) {
+                    break Err(e);
+                }
+            }
+        }
+    };
+    ratatui::restore();
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::*;
+
+    fn level(exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+        }
+    }
+
+    fn summary() -> Summary {
+        Summary {
+            spread: 1.0,
+            bids: vec![level("binance", 100.0, 1.0), level("bitstamp", 99.5, 2.0)],
+            asks: vec![level("binance", 101.0, 0.5), level("binance", 102.0, 3.0)],
+            sequence: 42,
+            dropped: 3,
+        }
+    }
+
+    fn status(exchange: &str, state: ConnectionState) -> ExchangeStatus {
+        ExchangeStatus {
+            exchange: exchange.to_string(),
+            state: state as i32,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_show_each_exchanges_best_bid_and_ask() {
+        let mut dashboard = Dashboard::new("http://[::1]:10000", 10);
+        dashboard.update(Update::Statuses(vec![status(
+            "kraken",
+            ConnectionState::BackingOff,
+        )]));
+        dashboard.update(Update::Summary(summary(), Instant::now()));
+
+        let best: Vec<_> = dashboard
+            .best_by_exchange()
+            .into_iter()
+            .map(|(exchange, bid, ask)| (exchange, bid.map(|l| l.price), ask.map(|l| l.price)))
+            .collect();
+        assert_eq!(
+            best,
+            vec![
+                ("kraken", None, None),
+                ("binance", Some(100.0), Some(101.0)),
+                ("bitstamp", Some(99.5), None),
+            ]
+        );
+        // colours are kept in the order the exchanges were seen.
+        assert_eq!(dashboard.colour("kraken"), PALETTE[0]);
+        assert_eq!(dashboard.colour("bitstamp"), PALETTE[2]);
+    }
+
+    #[test]
+    fn should_count_the_updates_in_the_last_second() {
+        let mut dashboard = Dashboard::new("http://[::1]:10000", 10);
+        let start = Instant::now();
+        for ms in [0, 400, 800, 1200] {
+            dashboard.update(Update::Summary(
+                summary(),
+                start + Duration::from_millis(ms),
+            ));
+        }
+
+        assert_eq!(
+            dashboard.updates_per_s(start + Duration::from_millis(1200)),
+            3
+        );
+        assert_eq!(
+            dashboard.updates_per_s(start + Duration::from_millis(2100)),
+            1
+        );
+        assert_eq!(dashboard.dropped, 12);
+    }
+
+    #[test]
+    fn should_render_the_ladder_and_statuses() {
+        let mut dashboard = Dashboard::new("http://[::1]:10000", 10);
+        dashboard.update(Update::Summary(summary(), Instant::now()));
+        dashboard.update(Update::Statuses(vec![status(
+            "binance",
+            ConnectionState::Streaming,
+        )]));
+        dashboard.update(Update::Disconnected(
+            "Unavailable: restarting, reconnecting in 250ms".to_string(),
+        ));
+
+        let mut terminal = Terminal::new(TestBackend::new(140, 20)).unwrap();
+        terminal
+            .draw(|frame| render(frame, &mut dashboard))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        // the text of the columns of each line.
+        let lines = |columns: std::ops::Range<u16>| -> Vec<String> {
+            (0..buffer.area.height)
+                .map(|y| columns.clone().map(|x| buffer[(x, y)].symbol()).collect())
+                .collect()
+        };
+        let line_with = |lines: &[String], text: &str| {
+            lines
+                .iter()
+                .position(|line| line.contains(text))
+                .unwrap_or_else(|| panic!("{:?} isn't shown:\n{}", text, lines.join("\n")))
+        };
+        let header = &lines(0..140)[0];
+        // below the header, the ladder is the left 55%.
+        let ladder = lines(0..77).split_off(1);
+        let exchanges = lines(77..140).split_off(1);
+
+        assert!(header.contains("#42  spread 1  1 updates/s  3 dropped"));
+        assert!(header.contains("reconnecting in 250ms"));
+        // the asks are above the spread, highest first, and the bids below it.
+        assert!(line_with(&ladder, "102") < line_with(&ladder, "101"));
+        assert!(line_with(&ladder, "101") < line_with(&ladder, "spread 1 "));
+        assert!(line_with(&ladder, "spread 1 ") < line_with(&ladder, "100"));
+        assert!(line_with(&ladder, "100") < line_with(&ladder, "99.5"));
+        assert_eq!(
+            buffer[(1, line_with(&ladder, "99.5") as u16 + 1)].fg,
+            dashboard.colour("bitstamp")
+        );
+        assert!(line_with(&exchanges, "bitstamp") < line_with(&exchanges, "exchange status"));
+        assert!(line_with(&exchanges, "exchange status") < line_with(&exchanges, "streaming"));
+    }
+}
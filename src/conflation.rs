@@ -0,0 +1,329 @@
+//! contains the per-client conflation of summaries.
+//! Each BookSummary client chooses how often it wants summaries (a minimum interval or a maximum rate)
+//! and what happens to updates published while it's waiting or too slow to keep up:
+//! LATEST only sends the most recent summary, QUEUE buffers summaries and drops the oldest when full.
+//! Either way, each summary sent reports how many summaries the client didn't receive since the previous one,
+//! using the gap in the summary sequence numbers.
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures_util::stream;
+use metrics::counter;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{broadcast, watch};
+use tokio::time::Instant;
+use tokio_stream::Stream;
+
+use crate::orderbook::{ConflationPolicy, SummaryRequest};
+use crate::result::Result;
+use crate::shared_summary::SharedSummary;
+
+// queue size used with QUEUE when the client doesn't request one.
+const DEFAULT_QUEUE_SIZE: usize = 16;
+// limits the memory a single client can hold on the server.
+// The broadcast of every summary holds this many, so QUEUE clients can be this far behind before they lag.
+pub const MAX_QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflation {
+    min_interval: Duration,
+    policy: ConflationPolicy,
+    queue_size: usize,
+}
+
+impl Conflation {
+    /// validates the client's request. The longer of the requested interval and rate is used.
+    pub fn from_request(request: &SummaryRequest) -> Result<Conflation> {
+        let policy = ConflationPolicy::try_from(request.policy)
+            .map_err(|_| format!("unknown policy: {}", request.policy))?;
+
+        if !request.max_updates_per_s.is_finite() || request.max_updates_per_s < 0.0 {
+            return Err("max_updates_per_s must be a positive number, or 0 for no limit".into());
+        }
+        let rate_interval = if request.max_updates_per_s > 0.0 {
+            Duration::from_secs_f64(1.0 / request.max_updates_per_s)
+        } else {
+            Duration::ZERO
+        };
+        let min_interval = rate_interval.max(Duration::from_millis(request.min_interval_ms));
+
+        let queue_size = match request.queue_size as usize {
+            0 => DEFAULT_QUEUE_SIZE,
+            size if size > MAX_QUEUE_SIZE => {
+                return Err(format!("queue_size must be at most {}", MAX_QUEUE_SIZE).into())
+            }
+            size => size,
+        };
+
+        Ok(Conflation {
+            min_interval,
+            policy,
+            queue_size,
+        })
+    }
+}
+
+/// the summaries sent to a BookSummary client.
+pub type SummaryStream =
+    Pin<Box<dyn Stream<Item = std::result::Result<SharedSummary, tonic::Status>> + Send>>;
+
+/// streams summaries to the client, conflated as requested. Summaries are only taken when the client is ready
+/// for the next one, so nothing is buffered for the client outside of its queue.
+/// LATEST clients read the watch, QUEUE clients subscribe to the broadcast of every summary.
+pub fn stream_summaries(
+    wrx: watch::Receiver<SharedSummary>,
+    summaries_tx: &broadcast::Sender<SharedSummary>,
+    conflation: Conflation,
+) -> SummaryStream {
+    match conflation.policy {
+        ConflationPolicy::Latest => Box::pin(send_latest(wrx, conflation.min_interval)),
+        ConflationPolicy::Queue => Box::pin(send_queued(
+            summaries_tx.subscribe(),
+            conflation.min_interval,
+            conflation.queue_size,
+        )),
+    }
+}
+
+// waits for the interval to pass and a summary to be published, then sends the latest.
+fn send_latest(
+    wrx: watch::Receiver<SharedSummary>,
+    min_interval: Duration,
+) -> impl Stream<Item = std::result::Result<SharedSummary, tonic::Status>> {
+    let state = (wrx, DropCounter::default(), Instant::now());
+    stream::unfold(state, move |(mut wrx, mut drops, next_send)| async move {
+        wait_until(next_send).await;
+        if let Err(e) = wrx.changed().await {
+            error!("Client stream error. Closing connection. {:?}", e);
+            return None;
+        }
+
+        let mut summary = wrx.borrow_and_update().clone();
+        drops.stamp(&mut summary);
+        Some((Ok(summary), (wrx, drops, Instant::now() + min_interval)))
+    })
+}
+
+// queues every summary, dropping the oldest when the queue is full, and sends them as the client and interval allow.
+fn send_queued(
+    brx: broadcast::Receiver<SharedSummary>,
+    min_interval: Duration,
+    queue_size: usize,
+) -> impl Stream<Item = std::result::Result<SharedSummary, tonic::Status>> {
+    let queue = SummaryQueue {
+        brx,
+        queue: VecDeque::with_capacity(queue_size),
+        queue_size,
+        drops: DropCounter::default(),
+        next_send: Instant::now(),
+        min_interval,
+    };
+    stream::unfold(queue, |mut queue| async move {
+        let summary = queue.next().await?;
+        Some((Ok(summary), queue))
+    })
+}
+
+// Summaries wait in the broadcast channel until the client is ready, and are moved into the queue then.
+// If the client falls behind the whole channel, the summaries it missed are reported as dropped like the
+// ones dropped from the queue (by the gap in the sequence numbers.)
+struct SummaryQueue {
+    brx: broadcast::Receiver<SharedSummary>,
+    queue: VecDeque<SharedSummary>,
+    queue_size: usize,
+    drops: DropCounter,
+    next_send: Instant,
+    min_interval: Duration,
+}
+
+impl SummaryQueue {
+    // returns the next summary for the client, or None once the publisher has gone.
+    async fn next(&mut self) -> Option<SharedSummary> {
+        wait_until(self.next_send).await;
+        while self.queue.is_empty() {
+            match self.brx.recv().await {
+                Ok(summary) => self.queue.push_back(summary),
+                Err(RecvError::Lagged(missed)) => lagged(missed),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+        loop {
+            match self.brx.try_recv() {
+                Ok(summary) => {
+                    if self.queue.len() == self.queue_size {
+                        self.queue.pop_front();
+                    }
+                    self.queue.push_back(summary);
+                }
+                Err(TryRecvError::Lagged(missed)) => lagged(missed),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+            }
+        }
+
+        let mut summary = self.queue.pop_front()?;
+        self.drops.stamp(&mut summary);
+        self.next_send = Instant::now() + self.min_interval;
+        Some(summary)
+    }
+}
+
+fn lagged(missed: u64) {
+    debug!("summary client lagged behind by {} summaries", missed);
+    counter!("clients.lagged_summaries", missed);
+}
+
+// sleeps until the deadline if it's in the future. The timer is avoided otherwise, as it adds latency even
+// when the deadline has passed.
+async fn wait_until(deadline: Instant) {
+    if deadline > Instant::now() {
+        tokio::time::sleep_until(deadline).await;
+    }
+}
+
+// counts the summaries a client didn't receive from the gaps in the sequence numbers.
+#[derive(Default)]
+struct DropCounter {
+    last_sequence: Option<u64>,
+}
+
+impl DropCounter {
+    fn stamp(&mut self, summary: &mut SharedSummary) {
+        summary.dropped = match self.last_sequence {
+            Some(last) => summary.sequence().saturating_sub(last + 1),
+            None => 0,
+        };
+        self.last_sequence = Some(summary.sequence());
+
+        if summary.dropped > 0 {
+            counter!("clients.dropped_summaries", summary.dropped);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::orderbook::Summary;
+
+    fn summary(sequence: u64) -> SharedSummary {
+        SharedSummary::new(Summary {
+            sequence,
+            ..Default::default()
+        })
+    }
+
+    fn conflation(policy: ConflationPolicy, min_interval_ms: u64, queue_size: u32) -> Conflation {
+        Conflation::from_request(&SummaryRequest {
+            min_interval_ms,
+            policy: policy as i32,
+            queue_size,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    async fn next(stream: &mut SummaryStream) -> SharedSummary {
+        stream.next().await.unwrap().unwrap()
+    }
+
+    #[test]
+    fn should_use_the_longer_of_the_interval_and_rate() {
+        let conflation = Conflation::from_request(&SummaryRequest {
+            min_interval_ms: 100,
+            max_updates_per_s: 2.0,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            conflation,
+            Conflation {
+                min_interval: Duration::from_millis(500),
+                policy: ConflationPolicy::Latest,
+                queue_size: DEFAULT_QUEUE_SIZE,
+            }
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_requests() {
+        let invalid = [
+            SummaryRequest {
+                policy: 5,
+                ..Default::default()
+            },
+            SummaryRequest {
+                max_updates_per_s: -1.0,
+                ..Default::default()
+            },
+            SummaryRequest {
+                queue_size: MAX_QUEUE_SIZE as u32 + 1,
+                ..Default::default()
+            },
+        ];
+
+        for request in invalid {
+            assert!(Conflation::from_request(&request).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_send_only_the_latest_summary_and_report_dropped_updates() {
+        let (wtx, wrx) = watch::channel(summary(0));
+        let (btx, _) = broadcast::channel(MAX_QUEUE_SIZE);
+        let mut stream = stream_summaries(wrx, &btx, conflation(ConflationPolicy::Latest, 50, 0));
+
+        wtx.send(summary(1)).unwrap();
+        assert_eq!(next(&mut stream).await.sequence(), 1);
+
+        // published within the interval, so only the last is sent.
+        for sequence in 2..=5 {
+            wtx.send(summary(sequence)).unwrap();
+        }
+
+        let received = next(&mut stream).await;
+        assert_eq!(received.sequence(), 5);
+        assert_eq!(received.dropped, 3);
+    }
+
+    #[tokio::test]
+    async fn should_queue_summaries_and_drop_the_oldest_when_full() {
+        let (btx, _) = broadcast::channel(MAX_QUEUE_SIZE);
+        let wrx = watch::channel(summary(0)).1;
+        let mut stream = stream_summaries(wrx, &btx, conflation(ConflationPolicy::Queue, 50, 2));
+
+        btx.send(summary(1)).unwrap();
+        assert_eq!(next(&mut stream).await.sequence(), 1);
+
+        // published as a burst while waiting for the interval.
+        for sequence in 2..=5 {
+            btx.send(summary(sequence)).unwrap();
+        }
+
+        let received = next(&mut stream).await;
+        assert_eq!((received.sequence(), received.dropped), (4, 2));
+        let received = next(&mut stream).await;
+        assert_eq!((received.sequence(), received.dropped), (5, 0));
+    }
+
+    #[tokio::test]
+    async fn should_report_summaries_missed_by_lagging_as_dropped() {
+        let (btx, _) = broadcast::channel(4);
+        let wrx = watch::channel(summary(0)).1;
+        let mut stream = stream_summaries(wrx, &btx, conflation(ConflationPolicy::Queue, 0, 16));
+
+        btx.send(summary(1)).unwrap();
+        assert_eq!(next(&mut stream).await.sequence(), 1);
+
+        // only the last 4 are still in the channel.
+        for sequence in 2..=9 {
+            btx.send(summary(sequence)).unwrap();
+        }
+
+        let received = next(&mut stream).await;
+        assert_eq!((received.sequence(), received.dropped), (6, 4));
+    }
+}
@@ -0,0 +1,158 @@
+//! contains the summaries of clients that are only entitled to some exchanges.
+//! They're built from the book rather than by filtering the consolidated summary: that only has the top levels across
+//! every exchange, so an exchange whose levels are all below the others' would be missing. The REST API builds its
+//! summaries from the book the same way, so a client gets the same book on every transport.
+//! Each set of exchanges is summarised and encoded once per update, and shared by every client entitled to that set.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{broadcast, watch};
+
+use crate::conflation::MAX_QUEUE_SIZE;
+use crate::orderbook::Summary;
+use crate::orderbook_aggregator::SharedBook;
+use crate::orderbook_data::OrderBookData;
+use crate::shared_summary::SharedSummary;
+
+/// the summaries of each set of exchanges clients are entitled to. Clients subscribe with their exchanges,
+/// and the aggregator publishes to every set after each update.
+#[derive(Clone, Default)]
+pub struct EntitledSummaries {
+    feeds: Arc<Mutex<HashMap<Vec<String>, SummaryFeed>>>,
+}
+
+// the summaries of a set of exchanges, like Feeds::summary_rx and Feeds::summaries_tx are for every exchange.
+struct SummaryFeed {
+    summary_tx: watch::Sender<SharedSummary>,
+    summaries_tx: broadcast::Sender<SharedSummary>,
+}
+
+impl EntitledSummaries {
+    /// returns the watch and broadcast of the summaries of the exchanges (in name order.)
+    /// The first client of a set starts it from the latest book.
+    pub fn subscribe(
+        &self,
+        exchanges: Vec<String>,
+        book: &SharedBook,
+    ) -> (
+        watch::Receiver<SharedSummary>,
+        broadcast::Sender<SharedSummary>,
+    ) {
+        let mut feeds = self.feeds.lock().expect("entitled summaries lock poisoned");
+        // the book is read while locked, so an update published after it can't miss the new set.
+        let feed = feeds.entry(exchanges).or_insert_with_key(|exchanges| {
+            let book = book.borrow().clone();
+            SummaryFeed {
+                summary_tx: watch::channel(summary(&book.data, exchanges, book.sequence)).0,
+                summaries_tx: broadcast::channel(MAX_QUEUE_SIZE).0,
+            }
+        });
+        (feed.summary_tx.subscribe(), feed.summaries_tx.clone())
+    }
+
+    /// publishes the summary of each set of exchanges, and drops the sets that no clients are subscribed to any more.
+    pub fn publish(&self, book: &OrderBookData, sequence: u64) {
+        let mut feeds = self.feeds.lock().expect("entitled summaries lock poisoned");
+        feeds.retain(|_, feed| {
+            feed.summary_tx.receiver_count() > 0 || feed.summaries_tx.receiver_count() > 0
+        });
+        for (exchanges, feed) in feeds.iter() {
+            let summary = summary(book, exchanges, sequence);
+            // sending only fails if no clients are queueing summaries.
+            let _ = feed.summaries_tx.send(summary.clone());
+            feed.summary_tx.send_replace(summary);
+        }
+    }
+}
+
+fn summary(book: &OrderBookData, exchanges: &[String], sequence: u64) -> SharedSummary {
+    SharedSummary::new(Summary {
+        sequence,
+        ..book.summary_for(|exchange| exchanges.iter().any(|allowed| allowed == exchange))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::OrderBookUpdate;
+    use crate::orderbook::Level;
+    use crate::orderbook_aggregator::BookSnapshot;
+
+    fn prices(levels: &[Level]) -> Vec<(&str, f64)> {
+        levels
+            .iter()
+            .map(|level| (level.exchange.as_str(), level.price))
+            .collect()
+    }
+
+    #[test]
+    fn should_summarise_exchanges_below_the_consolidated_top_levels() {
+        let mut book = OrderBookData::default();
+        // every one of binance's top 10 levels is better than bitstamp's.
+        book.update_exchange_data(OrderBookUpdate::from_prices(
+            "binance",
+            (0..10).map(|i| 100.0 - i as f64).collect(),
+            (0..10).map(|i| 101.0 + i as f64).collect(),
+        ));
+        book.update_exchange_data(OrderBookUpdate::from_prices(
+            "bitstamp",
+            vec![80.0, 79.0],
+            vec![120.0, 121.0],
+        ));
+        let shared_book = watch::channel(Arc::new(BookSnapshot {
+            data: book.snapshot(),
+            sequence: 2,
+        }))
+        .1;
+        let summaries = EntitledSummaries::default();
+
+        let (mut summary_rx, _) = summaries.subscribe(vec!["bitstamp".to_string()], &shared_book);
+        {
+            let shared = summary_rx.borrow_and_update();
+            let summary = shared.summary();
+            assert_eq!(summary.sequence, 2);
+            assert_eq!(summary.spread, 40.0);
+            assert_eq!(
+                prices(&summary.bids),
+                vec![("bitstamp", 80.0), ("bitstamp", 79.0)]
+            );
+            assert_eq!(
+                prices(&summary.asks),
+                vec![("bitstamp", 120.0), ("bitstamp", 121.0)]
+            );
+        }
+
+        book.update_exchange_data(OrderBookUpdate::from_prices(
+            "bitstamp",
+            vec![81.0],
+            vec![119.0],
+        ));
+        summaries.publish(&book, 3);
+        assert!(summary_rx.has_changed().unwrap());
+        let shared = summary_rx.borrow_and_update();
+        assert_eq!(shared.sequence(), 3);
+        assert_eq!(shared.summary().spread, 38.0);
+        assert_eq!(prices(&shared.summary().bids), vec![("bitstamp", 81.0)]);
+    }
+
+    #[test]
+    fn should_share_each_set_of_exchanges_until_its_clients_leave() {
+        let book = watch::channel(Arc::default()).1;
+        let summaries = EntitledSummaries::default();
+        let exchanges = || vec!["binance".to_string(), "bitstamp".to_string()];
+
+        let first = summaries.subscribe(exchanges(), &book);
+        let second = summaries.subscribe(exchanges(), &book);
+        assert_eq!(summaries.feeds.lock().unwrap().len(), 1);
+
+        drop(first);
+        summaries.publish(&OrderBookData::default(), 1);
+        assert_eq!(summaries.feeds.lock().unwrap().len(), 1);
+        assert_eq!(second.0.borrow().sequence(), 1);
+
+        drop(second);
+        summaries.publish(&OrderBookData::default(), 2);
+        assert!(summaries.feeds.lock().unwrap().is_empty());
+    }
+}
@@ -8,7 +8,7 @@ use serde::Deserialize;
 
 use crate::app_config::ExchangeConfig;
 use crate::exchange::{Exchange, OrderBookUpdate, WsError};
-use crate::orderbook::Level;
+use crate::orderbook::{Level, Trade};
 use crate::result::Result;
 
 pub(crate) const EXCHANGE_KEY: &str = "binance";
@@ -29,16 +29,16 @@ impl BinanceUpdate {
         for (price, amount) in &self.bids {
             bids.push(Level {
                 exchange: String::from(EXCHANGE_KEY),
-                price: f64::from_str(&*price)?,
-                amount: f64::from_str(&*amount)?,
+                price: f64::from_str(price)?,
+                amount: f64::from_str(amount)?,
             });
         }
 
         for (price, amount) in &self.asks {
             asks.push(Level {
                 exchange: String::from(EXCHANGE_KEY),
-                price: f64::from_str(&*price)?,
-                amount: f64::from_str(&*amount)?,
+                price: f64::from_str(price)?,
+                amount: f64::from_str(amount)?,
             });
         }
 
@@ -51,6 +51,35 @@ impl BinanceUpdate {
     }
 }
 
+#[derive(Deserialize, Debug)]
+// structure for json deserialization of the <pair>@trade stream
+struct BinanceTrade {
+    #[serde(rename = "t")]
+    trade_id: u64,
+    #[serde(rename = "p")]
+    price: String,
+    #[serde(rename = "q")]
+    amount: String,
+    #[serde(rename = "T")]
+    trade_time: u64,
+    // true if the buyer was the maker, ie the taker sold.
+    #[serde(rename = "m")]
+    buyer_is_maker: bool,
+}
+
+impl BinanceTrade {
+    fn to_trade(&self) -> Result<Trade> {
+        Ok(Trade {
+            exchange: String::from(EXCHANGE_KEY),
+            price: f64::from_str(&self.price)?,
+            amount: f64::from_str(&self.amount)?,
+            side: if self.buyer_is_maker { "sell" } else { "buy" }.to_string(),
+            timestamp_ms: self.trade_time,
+            trade_id: self.trade_id.to_string(),
+        })
+    }
+}
+
 pub struct Binance {
     pub(crate) exchange_config: ExchangeConfig,
 }
@@ -68,6 +97,16 @@ impl Exchange for Binance {
         &self.exchange_config
     }
 
+    fn validate_trades_subscription_reply(&self, bytes: Vec<u8>) -> Result<()> {
+        // replies are the same for any stream
+        self.validate_subscription_reply(bytes)
+    }
+
+    fn parse_trade(&self, bytes: Vec<u8>) -> Result<Trade> {
+        let parsed: BinanceTrade = serde_json::from_slice(&bytes)?;
+        parsed.to_trade()
+    }
+
     fn validate_subscription_reply(&self, bytes: Vec<u8>) -> Result<()> {
         let reply = String::from_utf8(bytes)?;
         if reply == "{\"result\":null,\"id\":1}" {
@@ -77,13 +116,10 @@ impl Exchange for Binance {
             );
             Ok(())
         } else {
-            Err(WsError::new(
-                format!(
-                    "Error subscribing to {}: response: {}",
-                    self.exchange_config.id, reply
-                )
-                .into(),
-            ))?
+            Err(WsError::new(format!(
+                "Error subscribing to {}: response: {}",
+                self.exchange_config.id, reply
+            )))?
         }
     }
 }
@@ -158,4 +194,37 @@ mod tests {
 
         println!("{:?}", orderbook_update);
     }
+
+    #[test]
+    fn should_parse_trade() {
+        let msg = r#"
+            {
+              "e": "trade",
+              "E": 1668182726940,
+              "s": "BTCUSDT",
+              "t": 2218212134,
+              "p": "16542.84000000",
+              "q": "0.08815000",
+              "b": 15390563120,
+              "a": 15390563098,
+              "T": 1668182726938,
+              "m": true,
+              "M": true
+            }
+"#;
+
+        let parsed: BinanceTrade = serde_json::from_str(msg).unwrap();
+
+        assert_eq!(
+            parsed.to_trade().unwrap(),
+            Trade {
+                exchange: "binance".to_string(),
+                price: 16542.84,
+                amount: 0.08815,
+                side: "sell".to_string(),
+                timestamp_ms: 1668182726938,
+                trade_id: "2218212134".to_string(),
+            }
+        );
+    }
 }
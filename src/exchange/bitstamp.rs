@@ -8,7 +8,7 @@ use serde::Deserialize;
 
 use crate::app_config::ExchangeConfig;
 use crate::exchange::{Exchange, OrderBookUpdate, WsError};
-use crate::orderbook::Level;
+use crate::orderbook::{Level, Trade};
 use crate::result::Result;
 pub(crate) const EXCHANGE_KEY: &str = "bitstamp";
 
@@ -34,16 +34,16 @@ impl BitstampUpdate {
         for (price, amount) in &self.data.bids {
             bids.push(Level {
                 exchange: String::from(EXCHANGE_KEY),
-                price: f64::from_str(&*price)?,
-                amount: f64::from_str(&*amount)?,
+                price: f64::from_str(price)?,
+                amount: f64::from_str(amount)?,
             });
         }
 
         for (price, amount) in &self.data.asks {
             asks.push(Level {
                 exchange: String::from(EXCHANGE_KEY),
-                price: f64::from_str(&*price)?,
-                amount: f64::from_str(&*amount)?,
+                price: f64::from_str(price)?,
+                amount: f64::from_str(amount)?,
             });
         }
 
@@ -56,6 +56,41 @@ impl BitstampUpdate {
     }
 }
 
+#[derive(Deserialize, Debug)]
+// structure for json deserialization of the live_trades_<pair> channel
+struct BitstampTrade {
+    data: TradeData,
+}
+
+#[derive(Deserialize, Debug)]
+struct TradeData {
+    id: u64,
+    price_str: String,
+    amount_str: String,
+    microtimestamp: String,
+    // 0 for buy, 1 for sell (the taker's side)
+    #[serde(rename = "type")]
+    trade_type: u8,
+}
+
+impl BitstampTrade {
+    fn to_trade(&self) -> Result<Trade> {
+        Ok(Trade {
+            exchange: String::from(EXCHANGE_KEY),
+            price: f64::from_str(&self.data.price_str)?,
+            amount: f64::from_str(&self.data.amount_str)?,
+            side: if self.data.trade_type == 0 {
+                "buy"
+            } else {
+                "sell"
+            }
+            .to_string(),
+            timestamp_ms: u64::from_str(&self.data.microtimestamp)? / 1000,
+            trade_id: self.data.id.to_string(),
+        })
+    }
+}
+
 pub struct Bitstamp {
     pub(crate) exchange_config: ExchangeConfig,
 }
@@ -79,10 +114,28 @@ impl Exchange for Bitstamp {
             Ok(())
         } else {
             Err(WsError::new(
-                format!("Error subscribing to {}: response: {}", self.exchange_config.id, reply).into(),
+                format!("Error subscribing to {}: response: {}", self.exchange_config.id, reply),
             ))?
         }
     }
+
+    fn validate_trades_subscription_reply(&self, bytes: Vec<u8>) -> Result<()> {
+        let reply = String::from_utf8(bytes)?;
+        if reply == "{\"event\":\"bts:subscription_succeeded\",\"channel\":\"live_trades_{{pair}}\",\"data\":{}}"
+            .replace("{{pair}}", self.exchange_config.spot_pair.to_lowercase().as_str()) {
+            debug!("[{}] - trades subscription response as expected: {}", self.exchange_config.id, reply);
+            Ok(())
+        } else {
+            Err(WsError::new(
+                format!("Error subscribing to {} trades: response: {}", self.exchange_config.id, reply),
+            ))?
+        }
+    }
+
+    fn parse_trade(&self, bytes: Vec<u8>) -> Result<Trade> {
+        let parsed: BitstampTrade = serde_json::from_slice(&bytes)?;
+        parsed.to_trade()
+    }
 }
 
 #[cfg(test)]
@@ -156,4 +209,40 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn should_parse_trade() {
+        let msg = r#"
+            {
+              "data": {
+                "id": 259006851,
+                "timestamp": "1668185357",
+                "amount": 0.0011,
+                "amount_str": "0.00110000",
+                "price": 17160,
+                "price_str": "17160",
+                "type": 1,
+                "microtimestamp": "1668185357162000",
+                "buy_order_id": 1549432357515264,
+                "sell_order_id": 1549432358416384
+              },
+              "channel": "live_trades_btcusdt",
+              "event": "trade"
+            }
+        "#;
+
+        let parsed: BitstampTrade = serde_json::from_str(msg).unwrap();
+
+        assert_eq!(
+            parsed.to_trade().unwrap(),
+            Trade {
+                exchange: "bitstamp".to_string(),
+                price: 17160.0,
+                amount: 0.0011,
+                side: "sell".to_string(),
+                timestamp_ms: 1668185357162,
+                trade_id: "259006851".to_string(),
+            }
+        );
+    }
 }
@@ -9,6 +9,7 @@ use futures_util::{SinkExt, StreamExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration, Instant};
 use tokio_tungstenite::tungstenite::Message::Pong;
 use tokio_tungstenite::{
@@ -18,12 +19,16 @@ use tokio_tungstenite::{
 use crate::app_config::ExchangeConfig;
 use crate::exchange::binance::Binance;
 use crate::exchange::bitstamp::Bitstamp;
-use crate::orderbook::Level;
+use crate::exchange_status::StatusReporter;
+use crate::orderbook::{Level, Trade};
 use crate::result::Result;
 
 mod binance;
 mod bitstamp;
 
+/// the ids of the exchanges that can be enabled in config. See build_exchange_from_config.
+pub const SUPPORTED_EXCHANGES: [&str; 2] = [binance::EXCHANGE_KEY, bitstamp::EXCHANGE_KEY];
+
 // We wait to avoid hammering the endpoint on retries. Contains the wait time before trying a connection. Should be in config...
 const SLEEP_MS: u64 = 250;
 
@@ -39,6 +44,36 @@ pub struct OrderBookUpdate {
     pub(crate) asks: Vec<Level>,
 }
 
+#[cfg(test)]
+impl OrderBookUpdate {
+    /// an update from the exchange with a level of amount 1 at each price.
+    pub fn from_prices(exchange: &str, bids: Vec<f64>, asks: Vec<f64>) -> OrderBookUpdate {
+        let levels = |prices: Vec<f64>| {
+            prices
+                .into_iter()
+                .map(|price| Level {
+                    exchange: exchange.to_string(),
+                    price,
+                    amount: 1.0,
+                })
+                .collect()
+        };
+        OrderBookUpdate {
+            ts: Instant::now(),
+            exchange: exchange.to_string(),
+            bids: levels(bids),
+            asks: levels(asks),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// the public channels that can be subscribed to on an exchange. Each channel uses its own connection.
+enum Channel {
+    OrderBook,
+    Trades,
+}
+
 #[async_trait]
 /// trait representing the specific implementation details needed for a specific exchange
 trait Exchange {
@@ -47,25 +82,53 @@ trait Exchange {
     fn exchange_config(&self) -> &ExchangeConfig;
 
     fn subscribe_msg(&self) -> String {
-        let pair = self.exchange_config().spot_pair.to_lowercase();
-        let msg = self
-            .exchange_config()
-            .subscription_message_template
-            .replace("{{pair}}", &pair);
-        info!("sub message {}", msg.clone());
-        msg
+        render_subscription_template(
+            &self.exchange_config().subscription_message_template,
+            &self.exchange_config().spot_pair,
+        )
     }
 
     fn validate_subscription_reply(&self, bytes: Vec<u8>) -> Result<()>;
 
-    fn empty_order_book_data(&self) -> OrderBookUpdate {
-        OrderBookUpdate {
-            ts: Instant::now(),
-            exchange: self.exchange_config().id.to_string(),
-            bids: vec![],
-            asks: vec![],
+    /// returns the trades subscription message, or an error if trades aren't configured for the exchange.
+    fn trades_subscribe_msg(&self) -> Result<String> {
+        match &self.exchange_config().trades {
+            Some(trades) => Ok(render_subscription_template(
+                &trades.subscription_message_template,
+                &self.exchange_config().spot_pair,
+            )),
+            None => Err(WsError::new(format!(
+                "trades are not configured for {}",
+                self.exchange_config().id
+            )))?,
         }
     }
+
+    /// exchanges that support trades should override this and parse_trade.
+    fn validate_trades_subscription_reply(&self, _bytes: Vec<u8>) -> Result<()> {
+        Err(WsError::new(format!(
+            "trades are not supported for {}",
+            self.exchange_config().id
+        )))?
+    }
+
+    fn parse_trade(&self, _bytes: Vec<u8>) -> Result<Trade> {
+        Err(WsError::new(format!(
+            "trades are not supported for {}",
+            self.exchange_config().id
+        )))?
+    }
+
+    fn empty_order_book_data(&self) -> OrderBookUpdate {
+        empty_order_book_update(&self.exchange_config().id)
+    }
+}
+
+// subscription messages are templates with the pair described as {{pair}}.
+fn render_subscription_template(template: &str, spot_pair: &str) -> String {
+    let msg = template.replace("{{pair}}", &spot_pair.to_lowercase());
+    info!("sub message {}", msg.clone());
+    msg
 }
 
 // TODOs:
@@ -76,10 +139,13 @@ trait Exchange {
 type WssStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 /// produces a thread to establish and manages connection/subscription to an exchange.
+/// The thread runs until it's aborted, eg when the exchange's config is reloaded.
+/// The connection's state is reported to the status reporter.
 pub fn create_exchange_ws_connection(
     exchange_config: ExchangeConfig,
     subscribers_tx: mpsc::Sender<OrderBookUpdate>,
-) {
+    status: StatusReporter,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
         // there are essentially two nested loops. If an error is encountered in the inner loop (handle_messages),
         // we can drop the connection, and let the connection be re-established.
@@ -100,18 +166,26 @@ pub fn create_exchange_ws_connection(
             );
             let exchange = Arc::new(build_exchange_from_config(&exchange_config).unwrap()); // will panic the app if can't build from config.
 
-            match connect_and_subscribe(&exchange_config, exchange.clone()).await {
+            status.connecting();
+            match connect_and_subscribe(&exchange_config, exchange.clone(), Channel::OrderBook)
+                .await
+            {
                 Ok(ws_stream) => {
-                    handle_messages(
+                    status.subscribed();
+                    let reason = handle_messages(
                         exchange_config.clone(),
+                        Duration::from_secs(exchange_config.receive_timeout_s),
                         &subscribers_tx,
-                        exchange.clone(),
+                        |bytes| exchange.parse_order_book_data(bytes),
+                        || status.message_received(),
                         ws_stream,
                     )
                     .await;
+                    status.backing_off(reason);
                 }
                 Err(e) => {
                     error!("Error connecting/subscribing... Will retry. {:?}", e);
+                    status.backing_off(e.to_string());
                 }
             };
 
@@ -128,17 +202,83 @@ pub fn create_exchange_ws_connection(
             );
             sleep(Duration::from_millis(SLEEP_MS)).await; // wait 100ms to avoid hammering a failing endpoint.
         }
-    });
+    })
 }
 
-/// connects to ws endpoint and subscribes to the pair
+/// an update that clears the exchange's order book, eg when its connection is stopped.
+pub fn empty_order_book_update(exchange_id: &str) -> OrderBookUpdate {
+    OrderBookUpdate {
+        ts: Instant::now(),
+        exchange: exchange_id.to_string(),
+        bids: vec![],
+        asks: vec![],
+    }
+}
+
+/// produces a thread to establish and manage a connection/subscription to an exchange's trades.
+/// This is a separate connection to the order book so a problem with trades won't clear the order book.
+/// Does nothing (and returns None) if trades aren't configured for the exchange.
+pub fn create_exchange_trades_ws_connection(
+    exchange_config: ExchangeConfig,
+    trades_tx: mpsc::Sender<Trade>,
+) -> Option<JoinHandle<()>> {
+    let trades_config = match &exchange_config.trades {
+        Some(trades_config) => trades_config.clone(),
+        None => return None,
+    };
+
+    Some(tokio::spawn(async move {
+        // same approach as the order book connection, but there's no state to clear on failure.
+        loop {
+            info!(
+                "starting exchange ws trades collection for: [{}]",
+                exchange_config.id
+            );
+            let exchange = Arc::new(build_exchange_from_config(&exchange_config).unwrap()); // will panic the app if can't build from config.
+
+            match connect_and_subscribe(&exchange_config, exchange.clone(), Channel::Trades).await {
+                Ok(ws_stream) => {
+                    handle_messages(
+                        exchange_config.clone(),
+                        Duration::from_secs(trades_config.receive_timeout_s),
+                        &trades_tx,
+                        |bytes| exchange.parse_trade(bytes),
+                        || {},
+                        ws_stream,
+                    )
+                    .await;
+                }
+                Err(e) => {
+                    error!(
+                        "Error connecting/subscribing to trades... Will retry. {:?}",
+                        e
+                    );
+                }
+            };
+
+            debug!(
+                "waiting {}ms before restarting trades connection to {}...",
+                SLEEP_MS, exchange_config.id
+            );
+            sleep(Duration::from_millis(SLEEP_MS)).await;
+        }
+    }))
+}
+
+/// connects to ws endpoint and subscribes to the channel for the pair
 async fn connect_and_subscribe(
     exchange_config: &ExchangeConfig,
     exchange: Arc<Box<dyn Exchange + Sync + Send>>,
+    channel: Channel,
 ) -> Result<WssStream> {
+    let subscribe_msg = match channel {
+        Channel::OrderBook => exchange.subscribe_msg(),
+        Channel::Trades => exchange.trades_subscribe_msg()?,
+    };
+
     let (mut ws_stream, _) = connect_async(exchange_config.endpoint.clone())
         .await
-        .map_err(|e| WsError::new(format!("error connecting to websocket: {:?}", e).into()))?;
+        .map_err(|e| WsError::new(format!("error connecting to websocket: {:?}", e)))?;
     info!(
         "WebSocket handshake has been successfully completed for {}",
         exchange_config.id.as_str()
@@ -146,9 +286,9 @@ async fn connect_and_subscribe(
 
     // subscribe
     ws_stream
-        .send(Message::text(exchange.subscribe_msg()))
+        .send(Message::text(subscribe_msg))
         .await
-        .map_err(|e| WsError::new(format!("error subscribing via websocket: {:?}", e).into()))?;
+        .map_err(|e| WsError::new(format!("error subscribing via websocket: {:?}", e)))?;
 
     // Get the reply message. If anything not as expected, we just continue the loop w/ a delay.
     match ws_stream.next().await {
@@ -157,14 +297,16 @@ async fn connect_and_subscribe(
                 "error getting next subscription message...".into(),
             )))?;
         }
-        Some(Ok(msg)) => {
-            exchange.validate_subscription_reply(msg.into_data())?;
-        }
+        Some(Ok(msg)) => match channel {
+            Channel::OrderBook => exchange.validate_subscription_reply(msg.into_data())?,
+            Channel::Trades => exchange.validate_trades_subscription_reply(msg.into_data())?,
+        },
         Some(e) => {
             e.map_err(|e| {
-                WsError::new(
-                    format!("something went wrong connecting/subscribing...: {:?}", e).into(),
-                )
+                WsError::new(format!(
+                    "something went wrong connecting/subscribing...: {:?}",
+                    e
+                ))
             })?;
         }
     }
@@ -173,26 +315,28 @@ async fn connect_and_subscribe(
 }
 
 /// handle messages will loop and stream messages received.
-async fn handle_messages(
+/// Each message is parsed and sent to the subscribers (and on_message is called.) If a message can't be parsed, the connection is dropped.
+/// Returns the reason the connection was dropped.
+async fn handle_messages<T, F, M>(
     exchange_config: ExchangeConfig,
-    subscribers_tx: &Sender<OrderBookUpdate>,
-    exchange: Arc<Box<dyn Exchange + Sync + Send>>,
+    receive_timeout: Duration,
+    subscribers_tx: &Sender<T>,
+    parse: F,
+    on_message: M,
     mut ws_stream: WssStream,
-) {
+) -> String
+where
+    T: std::fmt::Debug,
+    F: Fn(Vec<u8>) -> Result<T>,
+    M: Fn(),
+{
     loop {
         // inner loop will process any input received.
-        let exchange = exchange.clone();
-
         // It can take a long time to detect a failure, so we reset the connection if nothing is coming over the wire. https://github.com/snapview/tungstenite-rs/issues/225
         // this will cause connections to be terminated and exchange data to be dropped. Could try using ws ping frames instead to ensure it's alive.
         // eg bitstamp may not have an order book change in 1s and that be valid, but binance should send every n ms.
         // This is configurable per exchange to handle the different implementation details.
-        match tokio::time::timeout(
-            Duration::from_secs(exchange_config.receive_timeout_s),
-            ws_stream.next(),
-        )
-        .await
-        {
+        match tokio::time::timeout(receive_timeout, ws_stream.next()).await {
             // We explicitly handle ping frames and reply w/ a pong frame (binance will disconnect after 10m if not handled)
             Ok(Some(Ok(msg))) if msg.is_ping() => {
                 info!(
@@ -204,27 +348,47 @@ async fn handle_messages(
                         "error sending ping reply to {}... Will retry disconnect/retry.",
                         exchange_config.id.as_str()
                     );
-                    break;
+                    return "error sending ping reply".to_string();
                 }
             }
             Ok(Some(Ok(msg))) => {
-                match exchange.parse_order_book_data(msg.into_data().clone()) {
-                    Ok(order_book_update) => {
+                match parse(msg.into_data()) {
+                    Ok(parsed) => {
+                        on_message();
                         // can possibly spawn this instead of awaiting, but need to ensure order.
                         subscribers_tx
-                            .send(order_book_update)
+                            .send(parsed)
                             .await
                             .expect("unexpected error sending to channel. Panic!");
                     }
                     Err(e) => {
-                        error!("Restarting connection as we couldn't parse an orderbook update for {}!: {:?}", exchange_config.id.as_str(), e);
-                        break;
+                        error!(
+                            "Restarting connection as we couldn't parse an update for {}!: {:?}",
+                            exchange_config.id.as_str(),
+                            e
+                        );
+                        return format!("couldn't parse an update: {}", e);
                     }
                 }
             }
-            e => {
+            Err(_) => {
+                error!(
+                    "nothing received from {} within {:?}... Will restart.",
+                    exchange_config.id.as_str(),
+                    receive_timeout
+                );
+                return format!("nothing received within {:?}", receive_timeout);
+            }
+            Ok(None) => {
+                error!(
+                    "{} closed the connection... Will restart.",
+                    exchange_config.id.as_str()
+                );
+                return "connection closed".to_string();
+            }
+            Ok(Some(Err(e))) => {
                 error!("exchange connection error... Will restart. {:?}", e);
-                break;
+                return format!("connection error: {}", e);
             }
         }
     }
@@ -244,9 +408,10 @@ fn build_exchange_from_config(
         // bybit::EXCHANGE_KEY => Ok(Box::new(Bybit {
         //     exchange_config: exchange_config.clone(),
         // })),
-        id => Err(WsError::new(
-            format!("error in configuration: unknown exchange id: {}", id).into(),
-        ))?,
+        id => Err(WsError::new(format!(
+            "error in configuration: unknown exchange id: {}",
+            id
+        )))?,
     }
 }
 
@@ -0,0 +1,275 @@
+//! contains the tracking of each exchange's connection status.
+//! The connection loop for each exchange (see create_exchange_ws_connection) reports its state through a StatusReporter,
+//! so clients can tell whether an exchange is missing from the summary because it's disconnected (and cleared)
+//! or because it has no liquidity.
+//! Statuses are published to a watch when the state changes, and periodically so the message rates are current.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use crate::orderbook::{ConnectionState, ExchangeStatus, ExchangeStatuses};
+
+// the status of an exchange, shared by the tracker and the exchange's reporter.
+// The reporter is called for every message, so it only uses atomics unless the state changes or there's an error.
+#[derive(Default)]
+struct Entry {
+    state: AtomicI32,
+    last_message_ms: AtomicU64,
+    reconnects: AtomicU64,
+    // total messages received.
+    messages: AtomicU64,
+    // whether there's been a connection attempt, so later attempts are counted as reconnects.
+    attempted: AtomicBool,
+    last_error: Mutex<String>,
+    // set when the exchange is removed, so a connection that's still stopping doesn't publish.
+    removed: AtomicBool,
+}
+
+impl Entry {
+    fn status(&self, exchange: &str, messages_per_s: f64) -> ExchangeStatus {
+        ExchangeStatus {
+            exchange: exchange.to_string(),
+            state: self.state.load(Ordering::Relaxed),
+            last_message_ms: self.last_message_ms.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+            messages_per_s,
+        }
+    }
+}
+
+struct Tracked {
+    entry: Arc<Entry>,
+    // the total messages when the rate was last calculated.
+    rate_messages: u64,
+    messages_per_s: f64,
+}
+
+/// tracks the status of every exchange and publishes them to a watch.
+#[derive(Clone)]
+pub struct StatusTracker {
+    exchanges: Arc<Mutex<HashMap<String, Tracked>>>,
+    status_tx: Arc<watch::Sender<ExchangeStatuses>>,
+}
+
+impl StatusTracker {
+    pub fn new() -> (StatusTracker, watch::Receiver<ExchangeStatuses>) {
+        let (status_tx, status_rx) = watch::channel(ExchangeStatuses::default());
+        let tracker = StatusTracker {
+            exchanges: Arc::new(Mutex::new(HashMap::new())),
+            status_tx: Arc::new(status_tx),
+        };
+        (tracker, status_rx)
+    }
+
+    /// returns a reporter for the exchange's connection loop. The exchange is published straight away,
+    /// as connecting. Any previous reporter for the exchange stops reporting.
+    pub fn reporter(&self, exchange: &str) -> StatusReporter {
+        let entry = Arc::new(Entry::default());
+        let previous = self.exchanges.lock().unwrap().insert(
+            exchange.to_string(),
+            Tracked {
+                entry: entry.clone(),
+                rate_messages: 0,
+                messages_per_s: 0.0,
+            },
+        );
+        if let Some(previous) = previous {
+            previous.entry.removed.store(true, Ordering::Relaxed);
+        }
+        self.publish();
+
+        StatusReporter {
+            tracker: self.clone(),
+            entry,
+        }
+    }
+
+    /// stops reporting the exchange's status, eg when it's disabled.
+    pub fn remove(&self, exchange: &str) {
+        if let Some(tracked) = self.exchanges.lock().unwrap().remove(exchange) {
+            tracked.entry.removed.store(true, Ordering::Relaxed);
+        }
+        self.publish();
+    }
+
+    /// spawns a process that updates the message rates and publishes the statuses every interval.
+    pub fn start(&self, interval: Duration) {
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut last_tick = Instant::now();
+            loop {
+                ticker.tick().await;
+                tracker.update_rates(last_tick.elapsed());
+                last_tick = Instant::now();
+                tracker.publish();
+            }
+        });
+    }
+
+    fn update_rates(&self, elapsed: Duration) {
+        if elapsed.is_zero() {
+            return;
+        }
+        for tracked in self.exchanges.lock().unwrap().values_mut() {
+            let total = tracked.entry.messages.load(Ordering::Relaxed);
+            let messages = total - tracked.rate_messages;
+            tracked.messages_per_s = messages as f64 / elapsed.as_secs_f64();
+            tracked.rate_messages = total;
+        }
+    }
+
+    fn publish(&self) {
+        let mut exchanges: Vec<ExchangeStatus> = self
+            .exchanges
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(exchange, tracked)| tracked.entry.status(exchange, tracked.messages_per_s))
+            .collect();
+        exchanges.sort_by(|a, b| a.exchange.cmp(&b.exchange));
+
+        // sending only fails if there are no receivers.
+        let _ = self.status_tx.send(ExchangeStatuses { exchanges });
+    }
+}
+
+/// reports the status of an exchange's connection.
+#[derive(Clone)]
+pub struct StatusReporter {
+    tracker: StatusTracker,
+    entry: Arc<Entry>,
+}
+
+impl StatusReporter {
+    pub fn connecting(&self) {
+        if self.entry.attempted.swap(true, Ordering::Relaxed) {
+            self.entry.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+        self.set_state(ConnectionState::Connecting);
+    }
+
+    pub fn subscribed(&self) {
+        self.set_state(ConnectionState::Subscribed);
+    }
+
+    pub fn message_received(&self) {
+        self.entry
+            .last_message_ms
+            .store(now_ms(), Ordering::Relaxed);
+        self.entry.messages.fetch_add(1, Ordering::Relaxed);
+        self.set_state(ConnectionState::Streaming);
+    }
+
+    /// the connection failed or was dropped, and will be retried after a delay.
+    pub fn backing_off(&self, error: String) {
+        *self.entry.last_error.lock().unwrap() = error;
+        self.set_state(ConnectionState::BackingOff);
+    }
+
+    // the statuses are only published when the state changes, and not once the exchange is removed.
+    fn set_state(&self, state: ConnectionState) {
+        let previous = self.entry.state.swap(state as i32, Ordering::Relaxed);
+        if previous != state as i32 && !self.entry.removed.load(Ordering::Relaxed) {
+            self.tracker.publish();
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status_rx: &watch::Receiver<ExchangeStatuses>, exchange: &str) -> ExchangeStatus {
+        status_rx
+            .borrow()
+            .exchanges
+            .iter()
+            .find(|s| s.exchange == exchange)
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn should_publish_state_changes() {
+        let (tracker, status_rx) = StatusTracker::new();
+        let reporter = tracker.reporter("binance");
+
+        reporter.connecting();
+        assert_eq!(
+            status(&status_rx, "binance").state,
+            ConnectionState::Connecting as i32
+        );
+
+        reporter.subscribed();
+        reporter.message_received();
+        let streaming = status(&status_rx, "binance");
+        assert_eq!(streaming.state, ConnectionState::Streaming as i32);
+        assert!(streaming.last_message_ms > 0);
+
+        reporter.backing_off("receive timeout".to_string());
+        reporter.connecting();
+        let reconnecting = status(&status_rx, "binance");
+        assert_eq!(reconnecting.state, ConnectionState::Connecting as i32);
+        assert_eq!(reconnecting.reconnects, 1);
+        assert_eq!(reconnecting.last_error, "receive timeout");
+    }
+
+    #[test]
+    fn should_calculate_message_rates() {
+        let (tracker, status_rx) = StatusTracker::new();
+        let reporter = tracker.reporter("binance");
+
+        for _ in 0..10 {
+            reporter.message_received();
+        }
+        tracker.update_rates(Duration::from_secs(2));
+        tracker.publish();
+        assert_eq!(status(&status_rx, "binance").messages_per_s, 5.0);
+
+        tracker.update_rates(Duration::from_secs(1));
+        tracker.publish();
+        assert_eq!(status(&status_rx, "binance").messages_per_s, 0.0);
+    }
+
+    #[test]
+    fn should_remove_exchanges() {
+        let (tracker, status_rx) = StatusTracker::new();
+        tracker.reporter("binance").connecting();
+        tracker.reporter("bitstamp").connecting();
+
+        tracker.remove("binance");
+
+        let exchanges: Vec<String> = status_rx
+            .borrow()
+            .exchanges
+            .iter()
+            .map(|s| s.exchange.clone())
+            .collect();
+        assert_eq!(exchanges, vec!["bitstamp"]);
+    }
+
+    #[test]
+    fn should_ignore_reporters_of_removed_exchanges() {
+        let (tracker, status_rx) = StatusTracker::new();
+        let reporter = tracker.reporter("binance");
+
+        tracker.remove("binance");
+        reporter.connecting();
+        reporter.message_received();
+
+        assert!(status_rx.borrow().exchanges.is_empty());
+    }
+}
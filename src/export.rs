@@ -0,0 +1,626 @@
+//! orderbook-rs export tool - writes order book snapshots to CSV or Parquet for ML training pipelines.
+//! It can either stream live summaries from a running server, or convert a previously recorded CSV export.
+//!
+//! Each snapshot is flattened to one row per level (timestamp, exchange, side, level index, price, amount).
+//! The consolidated features of the snapshot (spread, mid price, depth etc) are repeated on each row
+//! so the files can be loaded straight into a dataframe without any joins.
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{Parser, ValueEnum};
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArrayType, DataType, DoubleType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::orderbook_aggregator_client::*;
+use crate::orderbook::*;
+
+pub mod orderbook {
+    tonic::include_proto!("orderbook");
+}
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// Parquet rows are buffered and written in row groups of this size.
+const ROW_GROUP_SIZE: usize = 10_000;
+
+// Parquet schema matching SnapshotRow.
+const SNAPSHOT_PARQUET_SCHEMA: &str = "
+message snapshot {
+    REQUIRED INT64 timestamp_us (TIMESTAMP(MICROS,true));
+    REQUIRED BYTE_ARRAY exchange (UTF8);
+    REQUIRED BYTE_ARRAY side (UTF8);
+    REQUIRED INT32 level;
+    REQUIRED DOUBLE price;
+    REQUIRED DOUBLE amount;
+    REQUIRED DOUBLE spread;
+    REQUIRED DOUBLE mid_price;
+    REQUIRED DOUBLE best_bid;
+    REQUIRED DOUBLE best_ask;
+    REQUIRED DOUBLE bid_depth;
+    REQUIRED DOUBLE ask_depth;
+    REQUIRED DOUBLE imbalance;
+}";
+
+// Parquet schema matching CandleRow.
+const CANDLE_PARQUET_SCHEMA: &str = "
+message candle {
+    REQUIRED BYTE_ARRAY source (UTF8);
+    REQUIRED INT64 interval_s;
+    REQUIRED INT64 open_time_ms (TIMESTAMP(MILLIS,true));
+    REQUIRED DOUBLE open;
+    REQUIRED DOUBLE high;
+    REQUIRED DOUBLE low;
+    REQUIRED DOUBLE close;
+    REQUIRED DOUBLE volume;
+    REQUIRED INT32 trades;
+    REQUIRED DOUBLE vwap;
+}";
+
+#[derive(Parser, Debug)]
+#[command(about = "Exports order book snapshots to CSV or Parquet")]
+struct Args {
+    /// file to write the export to.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// format of the output file.
+    #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// a recorded CSV export to convert. If not set, summaries are streamed from the server.
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// the kind of rows in the recorded CSV export.
+    #[arg(long, value_enum, default_value_t = Rows::Snapshots)]
+    rows: Rows,
+
+    /// also write the candles streamed from the server to this file (in the same format.)
+    #[arg(long)]
+    candles_output: Option<PathBuf>,
+
+    /// address of the server to stream summaries from.
+    #[arg(long, default_value = "http://[::1]:10000")]
+    addr: String,
+
+    /// stop after this many summaries have been exported. Streams until interrupted if not set.
+    #[arg(short, long)]
+    count: Option<u64>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+enum Format {
+    Csv,
+    Parquet,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+enum Rows {
+    Snapshots,
+    Candles,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// a single level of a consolidated order book snapshot along with the snapshot's features.
+struct SnapshotRow {
+    // microseconds since the unix epoch that the snapshot was received.
+    timestamp_us: i64,
+    exchange: String,
+    // "bid" or "ask"
+    side: String,
+    // position of the level in the consolidated book (0 is the best price)
+    level: i32,
+    price: f64,
+    amount: f64,
+    // consolidated features. Prices are 0.0 if a side of the book is empty (same as the Summary spread.)
+    spread: f64,
+    mid_price: f64,
+    best_bid: f64,
+    best_ask: f64,
+    bid_depth: f64,
+    ask_depth: f64,
+    // (bid_depth - ask_depth) / (bid_depth + ask_depth), in the range [-1, 1]
+    imbalance: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// an OHLCV candle, persisted alongside the snapshots.
+struct CandleRow {
+    // exchange id, or "consolidated"
+    source: String,
+    interval_s: i64,
+    // start of the interval in milliseconds since the unix epoch.
+    open_time_ms: i64,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: f64,
+    trades: i32,
+    vwap: f64,
+}
+
+impl From<Candle> for CandleRow {
+    fn from(candle: Candle) -> CandleRow {
+        CandleRow {
+            source: candle.source,
+            interval_s: candle.interval_s as i64,
+            open_time_ms: candle.open_time_ms as i64,
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            volume: candle.volume,
+            trades: candle.trades as i32,
+            vwap: candle.vwap,
+        }
+    }
+}
+
+/// flattens a summary into one row per level.
+fn rows_from_summary(timestamp_us: i64, summary: &Summary) -> Vec<SnapshotRow> {
+    let best_bid = summary.bids.first().map(|l| l.price).unwrap_or(0.0);
+    let best_ask = summary.asks.first().map(|l| l.price).unwrap_or(0.0);
+    let mid_price = if summary.bids.is_empty() || summary.asks.is_empty() {
+        0.0
+    } else {
+        (best_bid + best_ask) / 2.0
+    };
+
+    let bid_depth: f64 = summary.bids.iter().map(|l| l.amount).sum();
+    let ask_depth: f64 = summary.asks.iter().map(|l| l.amount).sum();
+    let imbalance = if bid_depth + ask_depth > 0.0 {
+        (bid_depth - ask_depth) / (bid_depth + ask_depth)
+    } else {
+        0.0
+    };
+
+    let sides = [("bid", &summary.bids), ("ask", &summary.asks)];
+
+    sides
+        .iter()
+        .flat_map(|(side, levels)| {
+            levels
+                .iter()
+                .enumerate()
+                .map(move |(i, level)| SnapshotRow {
+                    timestamp_us,
+                    exchange: level.exchange.clone(),
+                    side: side.to_string(),
+                    level: i as i32,
+                    price: level.price,
+                    amount: level.amount,
+                    spread: summary.spread,
+                    mid_price,
+                    best_bid,
+                    best_ask,
+                    bid_depth,
+                    ask_depth,
+                    imbalance,
+                })
+        })
+        .collect()
+}
+
+/// a row type that can be exported. CSV columns are the struct fields,
+/// and the parquet schema's columns must be in the same order.
+trait ExportRow: Serialize + DeserializeOwned + Clone + 'static {
+    const PARQUET_SCHEMA: &'static str;
+
+    /// writes the values of the column at index (see PARQUET_SCHEMA) for all rows.
+    fn write_parquet_column(
+        column: &mut SerializedColumnWriter,
+        index: usize,
+        rows: &[Self],
+    ) -> Result<()>;
+}
+
+impl ExportRow for SnapshotRow {
+    const PARQUET_SCHEMA: &'static str = SNAPSHOT_PARQUET_SCHEMA;
+
+    fn write_parquet_column(
+        column: &mut SerializedColumnWriter,
+        index: usize,
+        rows: &[Self],
+    ) -> Result<()> {
+        match index {
+            0 => write_column::<Int64Type, _>(column, rows, |r| r.timestamp_us),
+            1 => write_column::<ByteArrayType, _>(column, rows, |r| r.exchange.as_str().into()),
+            2 => write_column::<ByteArrayType, _>(column, rows, |r| r.side.as_str().into()),
+            3 => write_column::<Int32Type, _>(column, rows, |r| r.level),
+            4 => write_column::<DoubleType, _>(column, rows, |r| r.price),
+            5 => write_column::<DoubleType, _>(column, rows, |r| r.amount),
+            6 => write_column::<DoubleType, _>(column, rows, |r| r.spread),
+            7 => write_column::<DoubleType, _>(column, rows, |r| r.mid_price),
+            8 => write_column::<DoubleType, _>(column, rows, |r| r.best_bid),
+            9 => write_column::<DoubleType, _>(column, rows, |r| r.best_ask),
+            10 => write_column::<DoubleType, _>(column, rows, |r| r.bid_depth),
+            11 => write_column::<DoubleType, _>(column, rows, |r| r.ask_depth),
+            12 => write_column::<DoubleType, _>(column, rows, |r| r.imbalance),
+            i => Err(format!("unexpected parquet column index: {}", i).into()),
+        }
+    }
+}
+
+impl ExportRow for CandleRow {
+    const PARQUET_SCHEMA: &'static str = CANDLE_PARQUET_SCHEMA;
+
+    fn write_parquet_column(
+        column: &mut SerializedColumnWriter,
+        index: usize,
+        rows: &[Self],
+    ) -> Result<()> {
+        match index {
+            0 => write_column::<ByteArrayType, _>(column, rows, |r| r.source.as_str().into()),
+            1 => write_column::<Int64Type, _>(column, rows, |r| r.interval_s),
+            2 => write_column::<Int64Type, _>(column, rows, |r| r.open_time_ms),
+            3 => write_column::<DoubleType, _>(column, rows, |r| r.open),
+            4 => write_column::<DoubleType, _>(column, rows, |r| r.high),
+            5 => write_column::<DoubleType, _>(column, rows, |r| r.low),
+            6 => write_column::<DoubleType, _>(column, rows, |r| r.close),
+            7 => write_column::<DoubleType, _>(column, rows, |r| r.volume),
+            8 => write_column::<Int32Type, _>(column, rows, |r| r.trades),
+            9 => write_column::<DoubleType, _>(column, rows, |r| r.vwap),
+            i => Err(format!("unexpected parquet column index: {}", i).into()),
+        }
+    }
+}
+
+// writes the value of a column for all rows.
+fn write_column<T: DataType, R>(
+    column: &mut SerializedColumnWriter,
+    rows: &[R],
+    value: impl Fn(&R) -> T::T,
+) -> Result<()> {
+    let values: Vec<T::T> = rows.iter().map(value).collect();
+    column.typed::<T>().write_batch(&values, None, None)?;
+    Ok(())
+}
+
+/// writes rows to a file. close must be called to ensure everything is flushed.
+trait RowWriter<R> {
+    fn write(&mut self, rows: &[R]) -> Result<()>;
+
+    fn close(self: Box<Self>) -> Result<()>;
+}
+
+struct CsvRowWriter {
+    writer: csv::Writer<File>,
+}
+
+impl CsvRowWriter {
+    fn new(path: &Path) -> Result<CsvRowWriter> {
+        Ok(CsvRowWriter {
+            writer: csv::Writer::from_path(path)?,
+        })
+    }
+}
+
+impl<R: ExportRow> RowWriter<R> for CsvRowWriter {
+    fn write(&mut self, rows: &[R]) -> Result<()> {
+        for row in rows {
+            self.writer.serialize(row)?;
+        }
+        Ok(())
+    }
+
+    fn close(mut self: Box<Self>) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+struct ParquetRowWriter<R> {
+    writer: SerializedFileWriter<File>,
+    // rows are buffered until there are enough for a row group.
+    buffer: Vec<R>,
+}
+
+impl<R: ExportRow> ParquetRowWriter<R> {
+    fn new(path: &Path) -> Result<ParquetRowWriter<R>> {
+        let schema = Arc::new(parse_message_type(R::PARQUET_SCHEMA)?);
+        let props = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+        let writer = SerializedFileWriter::new(File::create(path)?, schema, props)?;
+
+        Ok(ParquetRowWriter {
+            writer,
+            buffer: Vec::with_capacity(ROW_GROUP_SIZE),
+        })
+    }
+
+    fn flush_row_group(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut row_group = self.writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            R::write_parquet_column(&mut column, index, &self.buffer)?;
+            column.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<R: ExportRow> RowWriter<R> for ParquetRowWriter<R> {
+    fn write(&mut self, rows: &[R]) -> Result<()> {
+        self.buffer.extend_from_slice(rows);
+        if self.buffer.len() >= ROW_GROUP_SIZE {
+            self.flush_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn close(mut self: Box<Self>) -> Result<()> {
+        self.flush_row_group()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+fn create_writer<R: ExportRow>(format: Format, path: &Path) -> Result<Box<dyn RowWriter<R>>> {
+    match format {
+        Format::Csv => Ok(Box::new(CsvRowWriter::new(path)?)),
+        Format::Parquet => Ok(Box::new(ParquetRowWriter::new(path)?)),
+    }
+}
+
+/// converts a recorded CSV export into the output format.
+fn convert_recorded<R: ExportRow>(input: &Path, format: Format, output: &Path) -> Result<u64> {
+    let mut reader = csv::Reader::from_path(input)?;
+    let mut writer = create_writer::<R>(format, output)?;
+    let mut rows = 0;
+
+    for row in reader.deserialize() {
+        let row: R = row?;
+        writer.write(&[row])?;
+        rows += 1;
+    }
+
+    writer.close()?;
+    Ok(rows)
+}
+
+/// streams summaries (and candles, if a writer is provided) from the server into the writers
+/// until the count of summaries is reached or ctrl-c is received.
+async fn export_live(
+    addr: String,
+    count: Option<u64>,
+    snapshot_writer: &mut dyn RowWriter<SnapshotRow>,
+    mut candle_writer: Option<&mut dyn RowWriter<CandleRow>>,
+) -> Result<u64> {
+    let mut client = OrderbookAggregatorClient::connect(addr).await?;
+    let mut summaries_stream = client
+        .book_summary(tonic::Request::new(SummaryRequest::default()))
+        .await?
+        .into_inner();
+    let mut candles_stream = match candle_writer {
+        Some(_) => Some(
+            client
+                .candles(tonic::Request::new(Empty {}))
+                .await?
+                .into_inner(),
+        ),
+        None => None,
+    };
+
+    let mut summaries = 0;
+    while count.is_none_or(|count| summaries < count) {
+        tokio::select! {
+            summary = summaries_stream.message() => match summary? {
+                None => break,
+                Some(summary) => {
+                    let timestamp_us = SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as i64;
+                    snapshot_writer.write(&rows_from_summary(timestamp_us, &summary))?;
+                    summaries += 1;
+                }
+            },
+            Some(candle) = next_candle(&mut candles_stream) => {
+                if let Some(writer) = candle_writer.as_mut() {
+                    writer.write(&[CandleRow::from(candle?)])?;
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    Ok(summaries)
+}
+
+// returns the next candle from the stream, or None if there's no stream or it has ended.
+async fn next_candle(
+    stream: &mut Option<tonic::Streaming<Candle>>,
+) -> Option<std::result::Result<Candle, tonic::Status>> {
+    stream.as_mut()?.message().await.transpose()
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    match &args.input {
+        Some(input) => {
+            let rows = match args.rows {
+                Rows::Snapshots => {
+                    convert_recorded::<SnapshotRow>(input, args.format, &args.output)?
+                }
+                Rows::Candles => convert_recorded::<CandleRow>(input, args.format, &args.output)?,
+            };
+            println!("Converted {} rows from {:?}", rows, input);
+        }
+        None => {
+            let mut snapshot_writer = create_writer(args.format, &args.output)?;
+            let mut candle_writer = match &args.candles_output {
+                Some(path) => Some(create_writer(args.format, path)?),
+                None => None,
+            };
+
+            let summaries = export_live(
+                args.addr.clone(),
+                args.count,
+                snapshot_writer.as_mut(),
+                candle_writer
+                    .as_mut()
+                    .map(|writer| writer.as_mut() as &mut dyn RowWriter<CandleRow>),
+            )
+            .await?;
+            println!("Exported {} summaries from {}", summaries, args.addr);
+
+            snapshot_writer.close()?;
+            if let Some(candle_writer) = candle_writer {
+                candle_writer.close()?;
+            }
+        }
+    }
+
+    println!("Wrote {:?} export to {:?}", args.format, args.output);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    use super::*;
+
+    fn level(exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+        }
+    }
+
+    fn sample_summary() -> Summary {
+        Summary {
+            spread: 2.0,
+            bids: vec![level("binance", 10.0, 3.0), level("bitstamp", 9.0, 1.0)],
+            asks: vec![level("bitstamp", 12.0, 2.0)],
+            ..Default::default()
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("orderbooks-rs-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn should_flatten_summary_with_consolidated_features() {
+        let rows = rows_from_summary(1_000, &sample_summary());
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[1],
+            SnapshotRow {
+                timestamp_us: 1_000,
+                exchange: "bitstamp".to_string(),
+                side: "bid".to_string(),
+                level: 1,
+                price: 9.0,
+                amount: 1.0,
+                spread: 2.0,
+                mid_price: 11.0,
+                best_bid: 10.0,
+                best_ask: 12.0,
+                bid_depth: 4.0,
+                ask_depth: 2.0,
+                imbalance: 2.0 / 6.0,
+            }
+        );
+        assert_eq!(rows[2].side, "ask");
+        assert_eq!(rows[2].level, 0);
+    }
+
+    #[test]
+    fn should_not_produce_a_mid_price_for_a_one_sided_book() {
+        let mut summary = sample_summary();
+        summary.asks.clear();
+
+        let rows = rows_from_summary(1_000, &summary);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].mid_price, 0.0);
+        assert_eq!(rows[0].best_ask, 0.0);
+        assert_eq!(rows[0].imbalance, 1.0);
+    }
+
+    #[test]
+    fn should_convert_recorded_csv_to_parquet() -> Result<()> {
+        let csv_path = temp_path("snapshots.csv");
+        let parquet_path = temp_path("snapshots.parquet");
+        let rows = rows_from_summary(1_000, &sample_summary());
+
+        let mut csv_writer = create_writer(Format::Csv, &csv_path)?;
+        csv_writer.write(&rows)?;
+        csv_writer.close()?;
+
+        let converted = convert_recorded::<SnapshotRow>(&csv_path, Format::Parquet, &parquet_path)?;
+
+        assert_eq!(converted, 3);
+
+        let reader = SerializedFileReader::new(File::open(&parquet_path)?)?;
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+
+        let first = reader.get_row_iter(None)?.next().unwrap()?;
+        assert_eq!(first.get_timestamp_micros(0)?, 1_000);
+        assert_eq!(first.get_string(1)?, "binance");
+        assert_eq!(first.get_string(2)?, "bid");
+        assert_eq!(first.get_int(3)?, 0);
+        assert_eq!(first.get_double(4)?, 10.0);
+        assert_eq!(first.get_double(12)?, 2.0 / 6.0);
+
+        std::fs::remove_file(csv_path)?;
+        std::fs::remove_file(parquet_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_candles_to_parquet() -> Result<()> {
+        let parquet_path = temp_path("candles.parquet");
+        let candle = Candle {
+            source: "consolidated".to_string(),
+            interval_s: 60,
+            open_time_ms: 60_000,
+            open: 100.0,
+            high: 105.0,
+            low: 95.0,
+            close: 101.0,
+            volume: 3.0,
+            trades: 2,
+            vwap: 102.0,
+        };
+
+        let mut writer = create_writer(Format::Parquet, &parquet_path)?;
+        writer.write(&[CandleRow::from(candle)])?;
+        writer.close()?;
+
+        let reader = SerializedFileReader::new(File::open(&parquet_path)?)?;
+        let first = reader.get_row_iter(None)?.next().unwrap()?;
+        assert_eq!(first.get_string(0)?, "consolidated");
+        assert_eq!(first.get_long(1)?, 60);
+        assert_eq!(first.get_timestamp_millis(2)?, 60_000);
+        assert_eq!(first.get_double(6)?, 101.0);
+        assert_eq!(first.get_int(8)?, 2);
+
+        std::fs::remove_file(parquet_path)?;
+        Ok(())
+    }
+}
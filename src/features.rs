@@ -0,0 +1,304 @@
+//! contains the feature engineering for ML agents.
+//! A FeatureCalculator turns each consolidated Summary from OrderBookData into a fixed-layout feature vector,
+//! so clients don't need to recompute the same features from the raw levels.
+//! The layout (feature names by index) only depends on the enabled exchanges, so it's fixed for the life of the server.
+use std::collections::VecDeque;
+
+use crate::orderbook::{Features, Level, Summary};
+
+// Imbalances and depth ratios are calculated over the top n levels of each side.
+const DEPTH_LEVELS: [usize; 3] = [1, 5, 10];
+
+// Rolling returns are the log return of the mid price over the last n updates.
+// Note: updates are irregular in time, so these are "update time" rather than clock time returns.
+const RETURN_HORIZONS: [usize; 3] = [1, 10, 100];
+
+/// calculates feature vectors from consolidated order book summaries.
+pub struct FeatureCalculator {
+    // per-exchange features are laid out in this order.
+    exchanges: Vec<String>,
+    // most recent mid prices, newest at the back. Holds enough history for the longest return horizon.
+    mid_prices: VecDeque<f64>,
+}
+
+impl FeatureCalculator {
+    pub fn new(exchanges: Vec<String>) -> FeatureCalculator {
+        FeatureCalculator {
+            exchanges,
+            mid_prices: VecDeque::with_capacity(max_horizon() + 1),
+        }
+    }
+
+    /// returns the name of each feature in the vector, by index.
+    pub fn layout(&self) -> Vec<String> {
+        let mut names: Vec<String> = [
+            "mid_price",
+            "microprice",
+            "weighted_mid",
+            "spread",
+            "spread_bps",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+
+        for n in DEPTH_LEVELS {
+            names.push(format!("imbalance_top{}", n));
+        }
+        for n in DEPTH_LEVELS {
+            names.push(format!("depth_ratio_top{}", n));
+        }
+        for n in RETURN_HORIZONS {
+            names.push(format!("return_{}", n));
+        }
+        for exchange in &self.exchanges {
+            names.push(format!("{}_bid_share", exchange));
+            names.push(format!("{}_ask_share", exchange));
+        }
+
+        names
+    }
+
+    /// calculates the features for a new summary and records the mid price for rolling returns.
+    /// Features that can't be calculated (eg a side of the book is empty) are 0.0.
+    pub fn update(&mut self, summary: &Summary) -> Features {
+        let (best_bid, best_ask) = match (summary.bids.first(), summary.asks.first()) {
+            (Some(bid), Some(ask)) => (bid, ask),
+            _ => {
+                // no mid price, so there's nothing to calculate returns against either.
+                self.mid_prices.clear();
+                return Features {
+                    values: vec![0.0; self.layout().len()],
+                };
+            }
+        };
+
+        let mid_price = (best_bid.price + best_ask.price) / 2.0;
+
+        self.mid_prices.push_back(mid_price);
+        if self.mid_prices.len() > max_horizon() + 1 {
+            self.mid_prices.pop_front();
+        }
+
+        let mut values = vec![
+            mid_price,
+            microprice(best_bid, best_ask),
+            weighted_mid(&summary.bids, &summary.asks),
+            summary.spread,
+            summary.spread / mid_price * 10_000.0,
+        ];
+
+        for n in DEPTH_LEVELS {
+            let (bid_depth, ask_depth) = (depth(&summary.bids, n), depth(&summary.asks, n));
+            values.push(ratio(bid_depth - ask_depth, bid_depth + ask_depth));
+        }
+        for n in DEPTH_LEVELS {
+            values.push(ratio(depth(&summary.bids, n), depth(&summary.asks, n)));
+        }
+        for n in RETURN_HORIZONS {
+            values.push(self.log_return(n));
+        }
+        for exchange in &self.exchanges {
+            values.push(exchange_share(&summary.bids, exchange));
+            values.push(exchange_share(&summary.asks, exchange));
+        }
+
+        Features { values }
+    }
+
+    // log return of the latest mid price against the mid price n updates ago.
+    fn log_return(&self, n: usize) -> f64 {
+        let len = self.mid_prices.len();
+        if len <= n {
+            return 0.0;
+        }
+        (self.mid_prices[len - 1] / self.mid_prices[len - 1 - n]).ln()
+    }
+}
+
+fn max_horizon() -> usize {
+    RETURN_HORIZONS.iter().copied().max().unwrap_or(0)
+}
+
+// mid price weighted by the size on the opposite side of the top of book.
+fn microprice(best_bid: &Level, best_ask: &Level) -> f64 {
+    let size = best_bid.amount + best_ask.amount;
+    if size <= 0.0 {
+        return (best_bid.price + best_ask.price) / 2.0;
+    }
+    (best_bid.price * best_ask.amount + best_ask.price * best_bid.amount) / size
+}
+
+// same idea as the microprice, but using the volume weighted price and depth of all levels on each side.
+fn weighted_mid(bids: &[Level], asks: &[Level]) -> f64 {
+    let (bid_depth, ask_depth) = (depth(bids, bids.len()), depth(asks, asks.len()));
+    if bid_depth <= 0.0 || ask_depth <= 0.0 {
+        return 0.0;
+    }
+    let bid_vwap = notional(bids) / bid_depth;
+    let ask_vwap = notional(asks) / ask_depth;
+    (bid_vwap * ask_depth + ask_vwap * bid_depth) / (bid_depth + ask_depth)
+}
+
+// total amount of the top n levels.
+fn depth(levels: &[Level], n: usize) -> f64 {
+    levels.iter().take(n).map(|l| l.amount).sum()
+}
+
+fn notional(levels: &[Level]) -> f64 {
+    levels.iter().map(|l| l.price * l.amount).sum()
+}
+
+// share of the amount quoted at the best price of a side (the touch) provided by the exchange.
+// Several exchanges can quote the best price, so it's 1.0 when the exchange is alone at the touch.
+fn exchange_share(levels: &[Level], exchange: &str) -> f64 {
+    let best_price = match levels.first() {
+        Some(best) => best.price,
+        None => return 0.0,
+    };
+    let touch: Vec<&Level> = levels
+        .iter()
+        .take_while(|l| l.price == best_price)
+        .collect();
+    let exchange_amount = touch
+        .iter()
+        .filter(|l| l.exchange == exchange)
+        .map(|l| l.amount)
+        .sum();
+    let touch_amount = touch.iter().map(|l| l.amount).sum();
+    ratio(exchange_amount, touch_amount)
+}
+
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator == 0.0 {
+        0.0
+    } else {
+        numerator / denominator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+        }
+    }
+
+    fn summary(bid: f64, ask: f64) -> Summary {
+        Summary {
+            spread: ask - bid,
+            bids: vec![
+                level("binance", bid, 3.0),
+                level("bitstamp", bid - 1.0, 1.0),
+            ],
+            asks: vec![
+                level("bitstamp", ask, 1.0),
+                level("binance", ask + 1.0, 1.0),
+            ],
+            ..Default::default()
+        }
+    }
+
+    // looks up a feature value by name to keep the tests readable.
+    fn feature(calculator: &FeatureCalculator, features: &Features, name: &str) -> f64 {
+        let index = calculator.layout().iter().position(|n| n == name).unwrap();
+        features.values[index]
+    }
+
+    #[test]
+    fn should_produce_a_vector_matching_the_layout() {
+        let mut calculator =
+            FeatureCalculator::new(vec!["binance".to_string(), "bitstamp".to_string()]);
+
+        let features = calculator.update(&summary(100.0, 102.0));
+
+        assert_eq!(features.values.len(), calculator.layout().len());
+        assert_eq!(calculator.layout().last().unwrap(), "bitstamp_ask_share");
+    }
+
+    #[test]
+    fn should_calculate_top_of_book_features() {
+        let mut calculator = FeatureCalculator::new(vec!["binance".to_string()]);
+
+        let features = calculator.update(&summary(100.0, 102.0));
+
+        assert_eq!(feature(&calculator, &features, "mid_price"), 101.0);
+        // (100 * 1 + 102 * 3) / 4
+        assert_eq!(feature(&calculator, &features, "microprice"), 101.5);
+        assert_eq!(feature(&calculator, &features, "spread"), 2.0);
+        assert_eq!(
+            feature(&calculator, &features, "spread_bps"),
+            2.0 / 101.0 * 10_000.0
+        );
+        // (3 - 1) / (3 + 1)
+        assert_eq!(feature(&calculator, &features, "imbalance_top1"), 0.5);
+        // (4 - 2) / (4 + 2)
+        assert_eq!(feature(&calculator, &features, "imbalance_top5"), 2.0 / 6.0);
+        assert_eq!(feature(&calculator, &features, "depth_ratio_top5"), 2.0);
+        assert_eq!(feature(&calculator, &features, "binance_bid_share"), 1.0);
+        assert_eq!(feature(&calculator, &features, "binance_ask_share"), 0.0);
+    }
+
+    #[test]
+    fn should_share_the_touch_between_exchanges_quoting_the_best_price() {
+        let mut calculator =
+            FeatureCalculator::new(vec!["binance".to_string(), "bitstamp".to_string()]);
+
+        let mut book = summary(100.0, 102.0);
+        book.bids.insert(1, level("bitstamp", 100.0, 1.0));
+        let features = calculator.update(&book);
+
+        // the bitstamp level below the touch doesn't count.
+        assert_eq!(feature(&calculator, &features, "binance_bid_share"), 0.75);
+        assert_eq!(feature(&calculator, &features, "bitstamp_bid_share"), 0.25);
+        assert_eq!(feature(&calculator, &features, "bitstamp_ask_share"), 1.0);
+    }
+
+    #[test]
+    fn should_calculate_weighted_mid_from_all_levels() {
+        let mut calculator = FeatureCalculator::new(vec![]);
+
+        let features = calculator.update(&summary(100.0, 102.0));
+
+        // bid vwap = (300 + 99) / 4, ask vwap = (102 + 103) / 2
+        let (bid_vwap, ask_vwap) = (399.0 / 4.0, 205.0 / 2.0);
+        assert_eq!(
+            feature(&calculator, &features, "weighted_mid"),
+            (bid_vwap * 2.0 + ask_vwap * 4.0) / 6.0
+        );
+    }
+
+    #[test]
+    fn should_calculate_rolling_returns_once_there_is_enough_history() {
+        let mut calculator = FeatureCalculator::new(vec![]);
+
+        let features = calculator.update(&summary(100.0, 102.0));
+        assert_eq!(feature(&calculator, &features, "return_1"), 0.0);
+
+        let features = calculator.update(&summary(110.0, 112.0));
+        assert_eq!(
+            feature(&calculator, &features, "return_1"),
+            (111.0_f64 / 101.0).ln()
+        );
+        assert_eq!(feature(&calculator, &features, "return_10"), 0.0);
+    }
+
+    #[test]
+    fn should_zero_features_and_reset_returns_for_a_one_sided_book() {
+        let mut calculator = FeatureCalculator::new(vec!["binance".to_string()]);
+        calculator.update(&summary(100.0, 102.0));
+
+        let mut one_sided = summary(100.0, 102.0);
+        one_sided.asks.clear();
+        let features = calculator.update(&one_sided);
+        assert!(features.values.iter().all(|v| *v == 0.0));
+
+        let features = calculator.update(&summary(110.0, 112.0));
+        assert_eq!(feature(&calculator, &features, "return_1"), 0.0);
+    }
+}
@@ -0,0 +1,865 @@
+//! contains the FIX 4.4 acceptor, for legacy systems that only consume FIX.
+//! Clients connect to server.fix.addr and log on (35=A) with TargetCompID (56) set to server.fix.sender_comp_id.
+//! When auth is configured the Password (554) is the client's token, and like gRPC clients they're limited by
+//! their entitlements (see auth.rs.) Market data needs the BookSummary entitlement.
+//! The acceptor speaks plain TCP, so the token is sent in the clear: put TLS (eg stunnel) in front of it when auth is on.
+//! Sessions aren't persisted, so both sides start from MsgSeqNum 1: the Logon must reset the sequence numbers
+//! (141=Y) or have MsgSeqNum 1. For the same reason nothing is resent - the book has moved on by then, so a
+//! ResendRequest (35=2) is answered with a SequenceReset (35=4) to the next sequence number, and gaps in the
+//! client's sequence numbers are skipped.
+//! A MarketDataRequest (35=V) for the pair is answered with a snapshot (35=W) of the consolidated book, each level
+//! identified by its exchange in MDMkt (275.) Subscriptions (263=1) are then sent a full refresh (35=W, 265=0) or an
+//! incremental refresh (35=X, 265=1) when their levels change (see Feeds::summaries.)
+//! Requests that can't be served are rejected (35=Y) with the reason.
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Instant};
+use tonic::metadata::MetadataMap;
+
+use crate::auth::{authorize_rpc, Authenticator, Entitlements};
+use crate::fix_message::*;
+use crate::orderbook::{Level, Summary};
+use crate::orderbook_aggregator::Feeds;
+use crate::orderbook_data::TOP_N;
+use crate::result::Result;
+use crate::shared_summary::SharedSummary;
+
+// clients are disconnected if they don't log on this soon after connecting.
+const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEARTBEAT_INTERVAL_S: u64 = 300;
+// how often the heartbeats are checked.
+const HEARTBEAT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// what's shared by every session.
+pub struct Acceptor {
+    feeds: Feeds,
+    authenticator: Authenticator,
+    // the pair the server streams, which is the only symbol.
+    pair: String,
+    sender_comp_id: String,
+}
+
+impl Acceptor {
+    pub fn new(
+        feeds: Feeds,
+        authenticator: Authenticator,
+        pair: &str,
+        sender_comp_id: &str,
+    ) -> Acceptor {
+        Acceptor {
+            feeds,
+            authenticator,
+            pair: pair.to_string(),
+            sender_comp_id: sender_comp_id.to_string(),
+        }
+    }
+}
+
+/// spawns a process that accepts FIX connections on the listener.
+pub fn serve(listener: TcpListener, acceptor: Acceptor) {
+    let acceptor = Arc::new(acceptor);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = run_session(stream, peer, acceptor).await {
+                            debug!("FIX client {} disconnected: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => error!("couldn't accept FIX connection: {}", e),
+            }
+        }
+    });
+}
+
+// a market data subscription, or a snapshot request.
+struct Subscription {
+    id: String,
+    depth: usize,
+    bids: bool,
+    asks: bool,
+    incremental: bool,
+    // the levels last sent.
+    last_bids: Vec<Level>,
+    last_asks: Vec<Level>,
+}
+
+impl Subscription {
+    fn levels(&self, included: bool, levels: &[Level]) -> Vec<Level> {
+        if included {
+            levels.iter().take(self.depth).cloned().collect()
+        } else {
+            vec![]
+        }
+    }
+
+    // returns the full refresh of the subscription's levels.
+    fn snapshot(&mut self, pair: &str, summary: &Summary) -> Message {
+        self.last_bids = self.levels(self.bids, &summary.bids);
+        self.last_asks = self.levels(self.asks, &summary.asks);
+
+        let mut message = Message::new("W")
+            .with(MD_REQ_ID, &self.id)
+            .with(SYMBOL, pair)
+            .with(NO_MD_ENTRIES, self.last_bids.len() + self.last_asks.len());
+        for (entry_type, levels) in [("0", &self.last_bids), ("1", &self.last_asks)] {
+            for (position, level) in levels.iter().enumerate() {
+                message.push(MD_ENTRY_TYPE, entry_type);
+                message.push(MD_ENTRY_PX, level.price);
+                message.push(MD_ENTRY_SIZE, level.amount);
+                message.push(MD_MKT, &level.exchange);
+                message.push(MD_ENTRY_POSITION_NO, position + 1);
+            }
+        }
+        message
+    }
+
+    // returns the refresh to send for the summary, or None if the subscription's levels haven't changed.
+    fn update(&mut self, pair: &str, summary: &Summary) -> Option<Message> {
+        let bids = self.levels(self.bids, &summary.bids);
+        let asks = self.levels(self.asks, &summary.asks);
+        if bids == self.last_bids && asks == self.last_asks {
+            return None;
+        }
+        if !self.incremental {
+            return Some(self.snapshot(pair, summary));
+        }
+
+        let mut entries = level_changes("0", &self.last_bids, &bids);
+        entries.extend(level_changes("1", &self.last_asks, &asks));
+
+        let mut message = Message::new("X")
+            .with(MD_REQ_ID, &self.id)
+            .with(NO_MD_ENTRIES, entries.len());
+        for (action, entry_type, level) in entries {
+            message.push(MD_UPDATE_ACTION, action);
+            message.push(MD_ENTRY_TYPE, entry_type);
+            message.push(SYMBOL, pair);
+            message.push(MD_ENTRY_PX, level.price);
+            if action != "2" {
+                message.push(MD_ENTRY_SIZE, level.amount);
+            }
+            message.push(MD_MKT, &level.exchange);
+        }
+        self.last_bids = bids;
+        self.last_asks = asks;
+        Some(message)
+    }
+}
+
+// returns the incremental refresh entries (MDUpdateAction, MDEntryType, level) from the old levels to the new.
+// Levels are identified by their exchange and price.
+fn level_changes<'a>(
+    entry_type: &'a str,
+    old: &'a [Level],
+    new: &'a [Level],
+) -> Vec<(&'static str, &'a str, Level)> {
+    let same_level = |a: &Level, b: &Level| a.exchange == b.exchange && a.price == b.price;
+
+    let deleted = old
+        .iter()
+        .filter(|o| !new.iter().any(|n| same_level(o, n)))
+        .map(|o| ("2", entry_type, o.clone()));
+    let added_or_changed = new
+        .iter()
+        .filter_map(|n| match old.iter().find(|o| same_level(o, n)) {
+            None => Some(("0", entry_type, n.clone())),
+            Some(o) if o.amount != n.amount => Some(("1", entry_type, n.clone())),
+            Some(_) => None,
+        });
+    deleted.chain(added_or_changed).collect()
+}
+
+// a logged on client.
+struct Session {
+    sender_comp_id: String,
+    target_comp_id: String,
+    pair: String,
+    entitlements: Entitlements,
+    heartbeat_interval: Duration,
+    // the sequence numbers of the next message sent and received.
+    next_sent: u64,
+    next_received: u64,
+    subscriptions: Vec<Subscription>,
+}
+
+impl Session {
+    // checks the Logon, returning the session or the reason it's refused.
+    fn logon(acceptor: &Acceptor, logon: &Message) -> std::result::Result<Session, String> {
+        if logon.msg_type != "A" {
+            return Err(format!(
+                "expected a Logon (35=A), received 35={}",
+                logon.msg_type
+            ));
+        }
+        let target_comp_id = logon
+            .get(SENDER_COMP_ID)
+            .filter(|id| !id.is_empty())
+            .ok_or("SenderCompID (49) is missing")?;
+        if logon.get(TARGET_COMP_ID) != Some(acceptor.sender_comp_id.as_str()) {
+            return Err(format!(
+                "unknown TargetCompID (56): {} (expected {})",
+                logon.get(TARGET_COMP_ID).unwrap_or_default(),
+                acceptor.sender_comp_id
+            ));
+        }
+        if !matches!(logon.get(ENCRYPT_METHOD), None | Some("0")) {
+            return Err("only EncryptMethod 0 (none) is supported".to_string());
+        }
+        let heartbeat_interval = logon
+            .get(HEART_BT_INT)
+            .and_then(|interval| interval.parse::<u64>().ok())
+            .filter(|interval| (1..=MAX_HEARTBEAT_INTERVAL_S).contains(interval))
+            .ok_or(format!(
+                "HeartBtInt (108) must be between 1 and {} seconds",
+                MAX_HEARTBEAT_INTERVAL_S
+            ))?;
+        let sequence = logon
+            .get(MSG_SEQ_NUM)
+            .and_then(|sequence| sequence.parse::<u64>().ok())
+            .ok_or("MsgSeqNum (34) is missing")?;
+        if logon.get(RESET_SEQ_NUM_FLAG) != Some("Y") && sequence != 1 {
+            return Err(
+                "sessions aren't persisted, so the Logon must reset the sequence numbers (141=Y)"
+                    .to_string(),
+            );
+        }
+
+        // the password is the bearer token.
+        let mut metadata = MetadataMap::new();
+        if let Some(value) = logon
+            .get(PASSWORD)
+            .and_then(|password| format!("Bearer {}", password).parse().ok())
+        {
+            metadata.insert("authorization", value);
+        }
+        let entitlements = acceptor
+            .authenticator
+            .entitlements(&metadata)
+            .map_err(|status| status.message().to_string())?;
+
+        Ok(Session {
+            sender_comp_id: acceptor.sender_comp_id.clone(),
+            target_comp_id: target_comp_id.to_string(),
+            pair: acceptor.pair.clone(),
+            entitlements,
+            heartbeat_interval: Duration::from_secs(heartbeat_interval),
+            next_sent: 1,
+            next_received: sequence + 1,
+            subscriptions: vec![],
+        })
+    }
+
+    fn logon_reply(&self, logon: &Message) -> Message {
+        let mut reply = Message::new("A")
+            .with(ENCRYPT_METHOD, 0)
+            .with(HEART_BT_INT, self.heartbeat_interval.as_secs());
+        if logon.get(RESET_SEQ_NUM_FLAG) == Some("Y") {
+            reply.push(RESET_SEQ_NUM_FLAG, "Y");
+        }
+        reply
+    }
+
+    // adds the header to the message, and returns it encoded.
+    fn encode(&mut self, mut message: Message) -> Vec<u8> {
+        let header = header(&self.sender_comp_id, &self.target_comp_id, self.next_sent);
+        message.fields.splice(0..0, header);
+        self.next_sent += 1;
+        message.encode()
+    }
+
+    // handles a message from the client, and returns the replies and whether to disconnect after sending them.
+    fn handle(&mut self, message: &Message, summary: &SharedSummary) -> (Vec<Message>, bool) {
+        let sequence = match message
+            .get(MSG_SEQ_NUM)
+            .and_then(|sequence| sequence.parse::<u64>().ok())
+        {
+            Some(sequence) => sequence,
+            None => return (vec![logout("MsgSeqNum (34) is missing")], true),
+        };
+        // a SequenceReset sets the next sequence number, whatever its own is.
+        if message.msg_type == "4" {
+            if let Some(next) = message
+                .get(NEW_SEQ_NO)
+                .and_then(|next| next.parse::<u64>().ok())
+            {
+                self.next_received = self.next_received.max(next);
+            }
+            return (vec![], false);
+        }
+        if sequence < self.next_received {
+            // a message that's been resent can be ignored.
+            if message.get(POSS_DUP_FLAG) == Some("Y") {
+                return (vec![], false);
+            }
+            let reason = format!(
+                "MsgSeqNum too low, expecting {} but received {}",
+                self.next_received, sequence
+            );
+            return (vec![logout(&reason)], true);
+        }
+        if sequence > self.next_received {
+            debug!(
+                "FIX client {} skipped from {} to {}",
+                self.target_comp_id, self.next_received, sequence
+            );
+        }
+        self.next_received = sequence + 1;
+
+        match message.msg_type.as_str() {
+            "0" | "3" => (vec![], false),
+            "1" => {
+                let heartbeat = Message::new("0")
+                    .with(TEST_REQ_ID, message.get(TEST_REQ_ID).unwrap_or_default());
+                (vec![heartbeat], false)
+            }
+            // the reset is sent next, so the sequence number after it is the one after next_sent.
+            "2" => (
+                vec![Message::new("4")
+                    .with(GAP_FILL_FLAG, "N")
+                    .with(NEW_SEQ_NO, self.next_sent + 1)],
+                false,
+            ),
+            "5" => (vec![Message::new("5")], true),
+            "V" => (self.market_data_request(message, summary), false),
+            // a second Logon is a protocol error, so the session ends rather than carrying on in an unknown state.
+            "A" => (vec![logout("already logged on")], true),
+            msg_type => (
+                vec![reject(
+                    message,
+                    sequence,
+                    11,
+                    &format!("unsupported MsgType: {}", msg_type),
+                )],
+                false,
+            ),
+        }
+    }
+
+    // returns the snapshot, or the rejection, and subscribes the client if it asked for updates.
+    fn market_data_request(&mut self, request: &Message, summary: &SharedSummary) -> Vec<Message> {
+        let id = match request.get(MD_REQ_ID) {
+            Some(id) => id.to_string(),
+            None => {
+                let sequence = self.next_received - 1;
+                return vec![reject(request, sequence, 1, "MDReqID (262) is missing")
+                    .with(REF_TAG_ID, MD_REQ_ID)];
+            }
+        };
+        let rejection = |reason: u32, text: &str| {
+            vec![Message::new("Y")
+                .with(MD_REQ_ID, &id)
+                .with(MD_REQ_REJ_REASON, reason)
+                .with(TEXT, text)]
+        };
+
+        let streaming = match request.get(SUBSCRIPTION_REQUEST_TYPE) {
+            Some("0") => false,
+            Some("1") => true,
+            Some("2") => {
+                let subscriptions = self.subscriptions.len();
+                self.subscriptions
+                    .retain(|subscription| subscription.id != id);
+                if self.subscriptions.len() == subscriptions {
+                    return rejection(0, "unknown MDReqID");
+                }
+                return vec![];
+            }
+            _ => return rejection(4, "SubscriptionRequestType (263) must be 0, 1 or 2"),
+        };
+        if self
+            .subscriptions
+            .iter()
+            .any(|subscription| subscription.id == id)
+        {
+            return rejection(1, "duplicate MDReqID");
+        }
+        let symbols = request.get_all(SYMBOL);
+        if symbols != [self.pair.as_str()] {
+            return rejection(
+                0,
+                &format!(
+                    "unknown symbol: {} (the server streams {})",
+                    symbols.join(","),
+                    self.pair
+                ),
+            );
+        }
+        if let Err(status) = authorize_rpc(&self.entitlements, "BookSummary") {
+            return rejection(3, status.message());
+        }
+        // the full book (0) is the levels the server has.
+        let depth = match request.get(MARKET_DEPTH).map(str::parse::<usize>) {
+            None | Some(Ok(0)) => TOP_N,
+            Some(Ok(depth)) => depth.min(TOP_N),
+            Some(Err(_)) => return rejection(5, "invalid MarketDepth (264)"),
+        };
+        let incremental = match request.get(MD_UPDATE_TYPE) {
+            Some("1") if streaming => true,
+            None | Some("0") | Some("1") => false,
+            _ => return rejection(6, "MDUpdateType (265) must be 0 or 1"),
+        };
+        if request.get(AGGREGATED_BOOK) == Some("Y") {
+            return rejection(
+                7,
+                "the book isn't aggregated by price, each exchange's levels are separate (266=N)",
+            );
+        }
+        let entry_types = request.get_all(MD_ENTRY_TYPE);
+        if entry_types.is_empty() || entry_types.iter().any(|t| *t != "0" && *t != "1") {
+            return rejection(8, "only bids (269=0) and offers (269=1) are supported");
+        }
+
+        let mut subscription = Subscription {
+            id,
+            depth,
+            bids: entry_types.contains(&"0"),
+            asks: entry_types.contains(&"1"),
+            incremental,
+            last_bids: vec![],
+            last_asks: vec![],
+        };
+        let snapshot = subscription.snapshot(&self.pair, summary.summary());
+        if streaming {
+            self.subscriptions.push(subscription);
+        }
+        vec![snapshot]
+    }
+
+    // returns the refreshes to send to the subscriptions for the summary.
+    fn book_updates(&mut self, summary: &SharedSummary) -> Vec<Message> {
+        self.subscriptions
+            .iter_mut()
+            .filter_map(|subscription| subscription.update(&self.pair, summary.summary()))
+            .collect()
+    }
+}
+
+fn header(sender_comp_id: &str, target_comp_id: &str, sequence: u64) -> Vec<(u32, String)> {
+    vec![
+        (SENDER_COMP_ID, sender_comp_id.to_string()),
+        (TARGET_COMP_ID, target_comp_id.to_string()),
+        (MSG_SEQ_NUM, sequence.to_string()),
+        (
+            SENDING_TIME,
+            chrono::Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string(),
+        ),
+    ]
+}
+
+fn logout(text: &str) -> Message {
+    Message::new("5").with(TEXT, text)
+}
+
+// a session level rejection of the message.
+fn reject(message: &Message, sequence: u64, reason: u32, text: &str) -> Message {
+    Message::new("3")
+        .with(REF_SEQ_NUM, sequence)
+        .with(REF_MSG_TYPE, &message.msg_type)
+        .with(SESSION_REJECT_REASON, reason)
+        .with(TEXT, text)
+}
+
+// reads the next message, skipping garbled ones. Returns None if the client disconnected.
+async fn read_message(stream: &mut TcpStream, buf: &mut BytesMut) -> Result<Option<Message>> {
+    loop {
+        match decode(buf) {
+            Ok(Some(message)) => return Ok(Some(message)),
+            Ok(None) => {
+                if stream.read_buf(buf).await? == 0 {
+                    return Ok(None);
+                }
+            }
+            Err(DecodeError::Checksum) => debug!("ignoring FIX message with an invalid checksum"),
+            Err(e) => return Err(e.to_string().into()),
+        }
+    }
+}
+
+async fn run_session(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    acceptor: Arc<Acceptor>,
+) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(4096);
+    let logon = match timeout(LOGON_TIMEOUT, read_message(&mut stream, &mut buf)).await {
+        Ok(Ok(Some(logon))) => logon,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => return Err("didn't log on".into()),
+    };
+    let mut session = match Session::logon(&acceptor, &logon) {
+        Ok(session) => session,
+        Err(reason) => {
+            let target_comp_id = logon.get(SENDER_COMP_ID).unwrap_or_default();
+            let mut message = logout(&reason);
+            message
+                .fields
+                .splice(0..0, header(&acceptor.sender_comp_id, target_comp_id, 1));
+            stream.write_all(&message.encode()).await?;
+            return Err(format!("logon refused: {}", reason).into());
+        }
+    };
+    info!(
+        "FIX client {} logged on as {} ({})",
+        peer, session.target_comp_id, session.entitlements.client
+    );
+
+    let mut summary_rx = acceptor.feeds.summaries(&session.entitlements).0;
+    let mut heartbeats = tokio::time::interval(HEARTBEAT_CHECK_INTERVAL);
+    let mut last_sent = Instant::now();
+    let mut last_received = Instant::now();
+    let mut test_request_sent = false;
+    let mut outgoing = vec![session.logon_reply(&logon)];
+
+    loop {
+        let mut disconnect = false;
+        for message in outgoing.drain(..) {
+            let encoded = session.encode(message);
+            stream.write_all(&encoded).await?;
+            last_sent = Instant::now();
+        }
+
+        tokio::select! {
+            message = read_message(&mut stream, &mut buf) => {
+                let message = match message? {
+                    Some(message) => message,
+                    None => return Ok(()),
+                };
+                last_received = Instant::now();
+                test_request_sent = false;
+                let summary = summary_rx.borrow().clone();
+                (outgoing, disconnect) = session.handle(&message, &summary);
+            }
+            Ok(_) = summary_rx.changed(), if !session.subscriptions.is_empty() => {
+                let summary = summary_rx.borrow_and_update().clone();
+                outgoing = session.book_updates(&summary);
+            }
+            _ = heartbeats.tick() => {
+                let interval = session.heartbeat_interval;
+                if test_request_sent && last_received.elapsed() > interval * 2 {
+                    return Err("heartbeat timeout".into());
+                }
+                // the client's heartbeat is late (allowing for transmission time), so check it's still there.
+                if !test_request_sent && last_received.elapsed() > interval + interval / 5 {
+                    outgoing.push(Message::new("1").with(TEST_REQ_ID, "heartbeat"));
+                    test_request_sent = true;
+                } else if last_sent.elapsed() >= interval {
+                    outgoing.push(Message::new("0"));
+                }
+            }
+        }
+
+        if disconnect {
+            for message in outgoing.drain(..) {
+                let encoded = session.encode(message);
+                stream.write_all(&encoded).await?;
+            }
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::orderbook::ExchangeStatuses;
+
+    // the acceptor only reads the repeating groups' fields, not their counts.
+    const NO_RELATED_SYM: u32 = 146;
+    const NO_MD_ENTRY_TYPES: u32 = 267;
+
+    fn summary(
+        sequence: u64,
+        bids: &[(&str, f64, f64)],
+        asks: &[(&str, f64, f64)],
+    ) -> SharedSummary {
+        let levels = |levels: &[(&str, f64, f64)]| {
+            levels
+                .iter()
+                .map(|(exchange, price, amount)| Level {
+                    exchange: exchange.to_string(),
+                    price: *price,
+                    amount: *amount,
+                })
+                .collect()
+        };
+        SharedSummary::new(Summary {
+            spread: 0.0,
+            bids: levels(bids),
+            asks: levels(asks),
+            sequence,
+            dropped: 0,
+        })
+    }
+
+    fn session(entitlements: Entitlements) -> Session {
+        Session {
+            sender_comp_id: "ORDERBOOKS".to_string(),
+            target_comp_id: "CLIENT".to_string(),
+            pair: "BTCUSDT".to_string(),
+            entitlements,
+            heartbeat_interval: Duration::from_secs(30),
+            next_sent: 1,
+            next_received: 2,
+            subscriptions: vec![],
+        }
+    }
+
+    // a MarketDataRequest for the bids and offers of the symbol.
+    fn request(id: &str, symbol: &str, subscription_type: &str, update_type: &str) -> Message {
+        Message::new("V")
+            .with(MD_REQ_ID, id)
+            .with(SUBSCRIPTION_REQUEST_TYPE, subscription_type)
+            .with(MARKET_DEPTH, 2)
+            .with(MD_UPDATE_TYPE, update_type)
+            .with(NO_MD_ENTRY_TYPES, 2)
+            .with(MD_ENTRY_TYPE, 0)
+            .with(MD_ENTRY_TYPE, 1)
+            .with(NO_RELATED_SYM, 1)
+            .with(SYMBOL, symbol)
+    }
+
+    fn entries(message: &Message) -> Vec<Vec<&str>> {
+        // each entry starts at its MDUpdateAction (X) or MDEntryType (W.)
+        let start = if message.msg_type == "X" {
+            MD_UPDATE_ACTION
+        } else {
+            MD_ENTRY_TYPE
+        };
+        let mut entries: Vec<Vec<&str>> = vec![];
+        for (tag, value) in &message.fields {
+            if *tag == start {
+                entries.push(vec![]);
+            }
+            if let Some(entry) = entries.last_mut() {
+                entry.push(value);
+            }
+        }
+        entries
+    }
+
+    #[test]
+    fn should_send_snapshots_with_each_levels_exchange() {
+        let mut session = session(Entitlements::unrestricted());
+        let book = summary(
+            1,
+            &[
+                ("binance", 100.0, 1.0),
+                ("bitstamp", 99.5, 2.0),
+                ("binance", 99.0, 3.0),
+            ],
+            &[("bitstamp", 101.0, 0.5)],
+        );
+
+        let replies = session.market_data_request(&request("1", "BTCUSDT", "0", "0"), &book);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].msg_type, "W");
+        assert_eq!(replies[0].get(MD_REQ_ID), Some("1"));
+        assert_eq!(replies[0].get(NO_MD_ENTRIES), Some("3"));
+        assert_eq!(
+            entries(&replies[0]),
+            vec![
+                vec!["0", "100", "1", "binance", "1"],
+                vec!["0", "99.5", "2", "bitstamp", "2"],
+                vec!["1", "101", "0.5", "bitstamp", "1"],
+            ]
+        );
+        // a snapshot request doesn't subscribe.
+        assert!(session.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn should_reject_requests_that_cant_be_served() {
+        let book = SharedSummary::default();
+        let rejection = |entitlements: Entitlements, request: Message| {
+            let replies = session(entitlements).market_data_request(&request, &book);
+            assert_eq!(replies[0].msg_type, "Y");
+            replies[0].get(MD_REQ_REJ_REASON).unwrap().to_string()
+        };
+        let all = Entitlements::unrestricted;
+        let trades_only =
+            Entitlements::new("research", Some(vec!["Trades".to_string()]), None, None);
+
+        assert_eq!(rejection(all(), request("1", "ETHBTC", "1", "0")), "0");
+        assert_eq!(
+            rejection(trades_only, request("1", "BTCUSDT", "1", "0")),
+            "3"
+        );
+        assert_eq!(rejection(all(), request("1", "BTCUSDT", "5", "0")), "4");
+        assert_eq!(
+            rejection(
+                all(),
+                request("1", "BTCUSDT", "1", "0").with(AGGREGATED_BOOK, "Y")
+            ),
+            "7"
+        );
+        assert_eq!(
+            rejection(
+                all(),
+                request("1", "BTCUSDT", "1", "0").with(MD_ENTRY_TYPE, 2)
+            ),
+            "8"
+        );
+        assert_eq!(rejection(all(), request("1", "BTCUSDT", "2", "0")), "0");
+    }
+
+    #[test]
+    fn should_log_out_clients_that_log_on_again() {
+        let mut session = session(Entitlements::unrestricted());
+        let logon = Message::new("A").with(MSG_SEQ_NUM, 2);
+
+        let (replies, disconnect) = session.handle(&logon, &SharedSummary::default());
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].msg_type, "5");
+        assert!(disconnect);
+    }
+
+    #[test]
+    fn should_send_incremental_refreshes_when_levels_change() {
+        let mut session = session(Entitlements::unrestricted());
+        let book = summary(1, &[("binance", 100.0, 1.0), ("bitstamp", 99.0, 2.0)], &[]);
+        session.market_data_request(&request("1", "BTCUSDT", "1", "1"), &book);
+        assert_eq!(
+            session.book_updates(&summary(
+                2,
+                &[("binance", 100.0, 1.0), ("bitstamp", 99.0, 2.0)],
+                &[]
+            )),
+            vec![]
+        );
+
+        let updates = session.book_updates(&summary(
+            3,
+            &[
+                ("binance", 100.0, 1.5),
+                ("bitstamp", 99.5, 1.0),
+                ("bitstamp", 99.0, 2.0),
+            ],
+            &[("binance", 101.0, 1.0)],
+        ));
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].msg_type, "X");
+        assert_eq!(updates[0].get(NO_MD_ENTRIES), Some("4"));
+        // the depth is 2, so bitstamp's 99.0 bid drops out of the book.
+        assert_eq!(
+            entries(&updates[0]),
+            vec![
+                vec!["2", "0", "BTCUSDT", "99", "bitstamp"],
+                vec!["1", "0", "BTCUSDT", "100", "1.5", "binance"],
+                vec!["0", "0", "BTCUSDT", "99.5", "1", "bitstamp"],
+                vec!["0", "1", "BTCUSDT", "101", "1", "binance"],
+            ]
+        );
+
+        let unsubscribe = session.market_data_request(&request("1", "BTCUSDT", "2", "1"), &book);
+        assert!(unsubscribe.is_empty());
+        assert!(session.subscriptions.is_empty());
+    }
+
+    // sends the message from the client with the header.
+    async fn send(stream: &mut TcpStream, mut message: Message, sequence: u64) {
+        message
+            .fields
+            .splice(0..0, header("CLIENT", "ORDERBOOKS", sequence));
+        stream.write_all(&message.encode()).await.unwrap();
+    }
+
+    async fn receive(stream: &mut TcpStream, buf: &mut BytesMut) -> Message {
+        read_message(stream, buf).await.unwrap().unwrap()
+    }
+
+    async fn connect(summary_rx: watch::Receiver<SharedSummary>) -> TcpStream {
+        let feeds = Feeds::from_watches(
+            summary_rx,
+            watch::channel(ExchangeStatuses::default()).1,
+            watch::channel(Arc::default()).1,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(
+            listener,
+            Acceptor::new(
+                feeds,
+                Authenticator::new(None, "BTCUSDT"),
+                "BTCUSDT",
+                "ORDERBOOKS",
+            ),
+        );
+        TcpStream::connect(addr).await.unwrap()
+    }
+
+    fn logon() -> Message {
+        Message::new("A")
+            .with(ENCRYPT_METHOD, 0)
+            .with(HEART_BT_INT, 30)
+            .with(RESET_SEQ_NUM_FLAG, "Y")
+    }
+
+    #[tokio::test]
+    async fn should_stream_the_book_to_a_session() {
+        let (summary_tx, summary_rx) = watch::channel(summary(1, &[("binance", 100.0, 1.0)], &[]));
+        let mut stream = connect(summary_rx).await;
+        let mut buf = BytesMut::new();
+
+        send(&mut stream, logon(), 1).await;
+        let reply = receive(&mut stream, &mut buf).await;
+        assert_eq!(reply.msg_type, "A");
+        assert_eq!(reply.get(MSG_SEQ_NUM), Some("1"));
+        assert_eq!(reply.get(TARGET_COMP_ID), Some("CLIENT"));
+        assert_eq!(reply.get(RESET_SEQ_NUM_FLAG), Some("Y"));
+
+        send(&mut stream, request("book", "BTCUSDT", "1", "0"), 2).await;
+        let snapshot = receive(&mut stream, &mut buf).await;
+        assert_eq!(snapshot.msg_type, "W");
+        assert_eq!(snapshot.get(MSG_SEQ_NUM), Some("2"));
+        assert_eq!(snapshot.get(MD_MKT), Some("binance"));
+
+        summary_tx
+            .send(summary(2, &[("bitstamp", 100.5, 1.0)], &[]))
+            .unwrap();
+        let refresh = receive(&mut stream, &mut buf).await;
+        assert_eq!(refresh.msg_type, "W");
+        assert_eq!(refresh.get(MD_MKT), Some("bitstamp"));
+
+        send(&mut stream, Message::new("1").with(TEST_REQ_ID, "ping"), 3).await;
+        let heartbeat = receive(&mut stream, &mut buf).await;
+        assert_eq!(heartbeat.msg_type, "0");
+        assert_eq!(heartbeat.get(TEST_REQ_ID), Some("ping"));
+
+        send(&mut stream, Message::new("5"), 4).await;
+        assert_eq!(receive(&mut stream, &mut buf).await.msg_type, "5");
+        assert!(read_message(&mut stream, &mut buf).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn should_refuse_logons_for_other_comp_ids() {
+        let mut stream = connect(watch::channel(SharedSummary::default()).1).await;
+        let mut buf = BytesMut::new();
+
+        let mut message = logon();
+        message
+            .fields
+            .splice(0..0, header("CLIENT", "SOMEONE_ELSE", 1));
+        stream.write_all(&message.encode()).await.unwrap();
+
+        let logout = receive(&mut stream, &mut buf).await;
+        assert_eq!(logout.msg_type, "5");
+        assert_eq!(
+            logout.get(TEXT),
+            Some("unknown TargetCompID (56): SOMEONE_ELSE (expected ORDERBOOKS)")
+        );
+        assert!(read_message(&mut stream, &mut buf).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,248 @@
+//! contains the FIX 4.4 tag=value encoding used by the FIX acceptor (see fix.rs.)
+//! Each message is `8=FIX.4.4|9=<body length>|35=<msg type>|<fields>|10=<checksum>|`, where | is SOH (0x01),
+//! the body length counts the bytes from 35= up to 10=, and the checksum is the sum of the bytes before 10= mod 256.
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+use bytes::BytesMut;
+
+pub const SOH: u8 = 0x01;
+const BEGIN_STRING: &[u8] = b"8=FIX.4.4\x01";
+// longer messages are rejected rather than buffered, the acceptor only receives small session and request messages.
+const MAX_BODY_LEN: usize = 16 * 1024;
+// 10=NNN and SOH.
+const TRAILER_LEN: usize = 7;
+
+// the tags the acceptor uses.
+pub const MSG_SEQ_NUM: u32 = 34;
+pub const MSG_TYPE: u32 = 35;
+pub const NEW_SEQ_NO: u32 = 36;
+pub const POSS_DUP_FLAG: u32 = 43;
+pub const REF_SEQ_NUM: u32 = 45;
+pub const SENDER_COMP_ID: u32 = 49;
+pub const SENDING_TIME: u32 = 52;
+pub const SYMBOL: u32 = 55;
+pub const TARGET_COMP_ID: u32 = 56;
+pub const TEXT: u32 = 58;
+pub const ENCRYPT_METHOD: u32 = 98;
+pub const HEART_BT_INT: u32 = 108;
+pub const TEST_REQ_ID: u32 = 112;
+pub const GAP_FILL_FLAG: u32 = 123;
+pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+pub const MD_REQ_ID: u32 = 262;
+pub const SUBSCRIPTION_REQUEST_TYPE: u32 = 263;
+pub const MARKET_DEPTH: u32 = 264;
+pub const MD_UPDATE_TYPE: u32 = 265;
+pub const AGGREGATED_BOOK: u32 = 266;
+pub const NO_MD_ENTRIES: u32 = 268;
+pub const MD_ENTRY_TYPE: u32 = 269;
+pub const MD_ENTRY_PX: u32 = 270;
+pub const MD_ENTRY_SIZE: u32 = 271;
+pub const MD_MKT: u32 = 275;
+pub const MD_UPDATE_ACTION: u32 = 279;
+pub const MD_REQ_REJ_REASON: u32 = 281;
+pub const MD_ENTRY_POSITION_NO: u32 = 290;
+pub const REF_TAG_ID: u32 = 371;
+pub const REF_MSG_TYPE: u32 = 372;
+pub const SESSION_REJECT_REASON: u32 = 373;
+pub const PASSWORD: u32 = 554;
+
+/// a message's type and fields, without the header fields that frame it (8, 9 and 10.)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub msg_type: String,
+    pub fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    pub fn new(msg_type: &str) -> Message {
+        Message {
+            msg_type: msg_type.to_string(),
+            fields: vec![],
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl Display) -> Message {
+        self.push(tag, value);
+        self
+    }
+
+    pub fn push(&mut self, tag: u32, value: impl Display) {
+        self.fields.push((tag, value.to_string()));
+    }
+
+    /// returns the value of the first field with the tag.
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// returns the values of every field with the tag, eg from a repeating group.
+    pub fn get_all(&self, tag: u32) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|(t, _)| *t == tag)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = vec![];
+        write!(body, "35={}\x01", self.msg_type).expect("writes to a vec succeed");
+        for (tag, value) in &self.fields {
+            write!(body, "{}={}\x01", tag, value).expect("writes to a vec succeed");
+        }
+
+        let mut message = format!("8=FIX.4.4\x019={}\x01", body.len()).into_bytes();
+        message.extend(body);
+        let checksum = checksum(&message);
+        write!(message, "10={:03}\x01", checksum).expect("writes to a vec succeed");
+        message
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    // the message was framed but its checksum is wrong, so it's skipped (FIX ignores garbled messages.)
+    Checksum,
+    // the stream isn't FIX 4.4, and the connection can't continue.
+    Framing(String),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Checksum => write!(f, "invalid checksum"),
+            DecodeError::Framing(reason) => write!(f, "invalid message: {}", reason),
+        }
+    }
+}
+
+/// removes the first message from the buffer, or returns None if it hasn't all been received yet.
+pub fn decode(buf: &mut BytesMut) -> Result<Option<Message>, DecodeError> {
+    let prefix_len = buf.len().min(BEGIN_STRING.len());
+    if buf[..prefix_len] != BEGIN_STRING[..prefix_len] {
+        return Err(DecodeError::Framing("expected 8=FIX.4.4".to_string()));
+    }
+
+    let length_start = BEGIN_STRING.len() + 2;
+    let length_end = match buf.iter().skip(length_start).position(|b| *b == SOH) {
+        Some(position) => length_start + position,
+        None if buf.len() < length_start + 8 => return Ok(None),
+        None => return Err(DecodeError::Framing("expected 9=<body length>".to_string())),
+    };
+    if &buf[BEGIN_STRING.len()..length_start] != b"9=" {
+        return Err(DecodeError::Framing("expected 9=<body length>".to_string()));
+    }
+    let body_len = std::str::from_utf8(&buf[length_start..length_end])
+        .ok()
+        .and_then(|len| len.parse::<usize>().ok())
+        .filter(|len| *len <= MAX_BODY_LEN)
+        .ok_or_else(|| DecodeError::Framing("invalid body length".to_string()))?;
+
+    let body_start = length_end + 1;
+    let body_end = body_start + body_len;
+    if buf.len() < body_end + TRAILER_LEN {
+        return Ok(None);
+    }
+    let trailer = &buf[body_end..body_end + TRAILER_LEN];
+    if &trailer[..3] != b"10=" || trailer[TRAILER_LEN - 1] != SOH {
+        return Err(DecodeError::Framing(
+            "expected 10=<checksum> after the body".to_string(),
+        ));
+    }
+    let valid = std::str::from_utf8(&trailer[3..6])
+        .ok()
+        .and_then(|sum| sum.parse::<u8>().ok())
+        == Some(checksum(&buf[..body_end]));
+
+    let frame = buf.split_to(body_end + TRAILER_LEN);
+    if !valid {
+        return Err(DecodeError::Checksum);
+    }
+    parse_body(&frame[body_start..body_end]).map(Some)
+}
+
+fn parse_body(body: &[u8]) -> Result<Message, DecodeError> {
+    let mut fields = body
+        .strip_suffix(&[SOH])
+        .ok_or_else(|| DecodeError::Framing("the body must end with SOH".to_string()))?
+        .split(|b| *b == SOH)
+        .map(|field| {
+            let field = std::str::from_utf8(field)
+                .map_err(|_| DecodeError::Framing("fields must be utf-8".to_string()))?;
+            let (tag, value) = field
+                .split_once('=')
+                .and_then(|(tag, value)| Some((tag.parse::<u32>().ok()?, value)))
+                .ok_or_else(|| DecodeError::Framing(format!("invalid field: {}", field)))?;
+            Ok((tag, value.to_string()))
+        })
+        .collect::<Result<Vec<_>, DecodeError>>()?;
+
+    if fields.first().map(|(tag, _)| *tag) != Some(MSG_TYPE) {
+        return Err(DecodeError::Framing(
+            "expected 35=<msg type> after the body length".to_string(),
+        ));
+    }
+    let (_, msg_type) = fields.remove(0);
+    Ok(Message { msg_type, fields })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a heartbeat from a FIX engine, with | for SOH.
+    const HEARTBEAT: &str =
+        "8=FIX.4.4|9=59|35=0|49=CLIENT|56=ORDERBOOKS|34=2|52=20240101-12:00:00.000|10=113|";
+
+    fn soh(message: &str) -> Vec<u8> {
+        message.replace('|', "\x01").into_bytes()
+    }
+
+    #[test]
+    fn should_encode_messages() {
+        let heartbeat = Message::new("0")
+            .with(SENDER_COMP_ID, "CLIENT")
+            .with(TARGET_COMP_ID, "ORDERBOOKS")
+            .with(MSG_SEQ_NUM, 2)
+            .with(SENDING_TIME, "20240101-12:00:00.000");
+
+        assert_eq!(heartbeat.encode(), soh(HEARTBEAT));
+    }
+
+    #[test]
+    fn should_decode_messages_as_they_arrive() {
+        let bytes = soh(HEARTBEAT);
+        let mut buf = BytesMut::from(&bytes[..20]);
+        assert_eq!(decode(&mut buf), Ok(None));
+
+        buf.extend_from_slice(&bytes[20..]);
+        buf.extend_from_slice(&bytes[..10]);
+        let heartbeat = decode(&mut buf).unwrap().unwrap();
+        assert_eq!(heartbeat.msg_type, "0");
+        assert_eq!(heartbeat.get(MSG_SEQ_NUM), Some("2"));
+        assert_eq!(heartbeat.get(TARGET_COMP_ID), Some("ORDERBOOKS"));
+        // the start of the next message is left in the buffer.
+        assert_eq!(buf.len(), 10);
+        assert_eq!(decode(&mut buf), Ok(None));
+    }
+
+    #[test]
+    fn should_skip_garbled_messages() {
+        let mut buf = BytesMut::from(&soh(&HEARTBEAT.replace("10=113", "10=114"))[..]);
+        buf.extend_from_slice(&soh(HEARTBEAT));
+
+        assert_eq!(decode(&mut buf), Err(DecodeError::Checksum));
+        assert!(decode(&mut buf).unwrap().is_some());
+
+        let mut buf = BytesMut::from(&b"GET / HTTP/1.1\r\n"[..]);
+        assert!(matches!(decode(&mut buf), Err(DecodeError::Framing(_))));
+    }
+}
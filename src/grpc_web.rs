@@ -0,0 +1,139 @@
+//! contains the gRPC-Web support, so browsers can call the rpcs (eg BookSummary) without a proxy like Envoy.
+//! When [server.grpc_web] is configured, the gRPC server also accepts HTTP/1.1, and gRPC-Web requests are
+//! translated to gRPC by tonic-web. Other requests are passed through, so gRPC clients are unaffected.
+//! Browsers check cross-origin requests with a CORS preflight, which is only allowed for the configured origins.
+//! Clients authenticate with the authorization header like gRPC clients (see auth.rs.)
+//! Note: browsers can't stream requests, so only unary and server streaming rpcs work over gRPC-Web.
+use std::time::Duration;
+
+use tonic::codegen::http::{HeaderName, HeaderValue, Method};
+use tonic_web::GrpcWebLayer;
+use tower::layer::util::{Identity, Stack};
+use tower::ServiceBuilder;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::app_config::GrpcWebConfig;
+
+// how long browsers may cache the preflight response.
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+// the request headers used by gRPC-Web clients, and the trailers they read from the response.
+const ALLOW_HEADERS: [&str; 5] = [
+    "authorization",
+    "content-type",
+    "grpc-timeout",
+    "x-grpc-web",
+    "x-user-agent",
+];
+const EXPOSE_HEADERS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+
+pub type GrpcWeb = ServiceBuilder<Stack<GrpcWebLayer, Stack<CorsLayer, Identity>>>;
+
+/// returns the layer that handles CORS and translates gRPC-Web requests, for the gRPC server.
+pub fn layer(config: &GrpcWebConfig) -> GrpcWeb {
+    ServiceBuilder::new()
+        .layer(cors_layer(config))
+        .layer(GrpcWebLayer::new())
+}
+
+fn cors_layer(config: &GrpcWebConfig) -> CorsLayer {
+    let allow_origin = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.allowed_origins.iter().map(|origin| {
+            HeaderValue::from_str(origin).expect("origins are checked when the config is validated")
+        }))
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::POST])
+        .allow_headers(ALLOW_HEADERS.map(HeaderName::from_static))
+        .expose_headers(EXPOSE_HEADERS.map(HeaderName::from_static))
+        .max_age(PREFLIGHT_MAX_AGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use hyper::{Body, Client, Request, Response};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+
+    use super::*;
+
+    const ORIGIN: &str = "https://trading.example.com";
+
+    // serves the health service with gRPC-Web, on a random port.
+    async fn serve() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (_reporter, health_svc) = tonic_health::server::health_reporter();
+        let config = GrpcWebConfig {
+            allowed_origins: vec![ORIGIN.to_string()],
+        };
+
+        tokio::spawn(
+            Server::builder()
+                .accept_http1(true)
+                .layer(layer(&config))
+                .add_service(health_svc)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        addr
+    }
+
+    async fn send(request: Request<Body>) -> Response<Body> {
+        Client::new().request(request).await.unwrap()
+    }
+
+    fn preflight(addr: SocketAddr, origin: &str) -> Request<Body> {
+        Request::options(format!("http://{}/grpc.health.v1.Health/Check", addr))
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "authorization,x-grpc-web")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_only_allow_configured_origins() {
+        let addr = serve().await;
+
+        let allowed = send(preflight(addr, ORIGIN)).await;
+        assert_eq!(allowed.headers()["access-control-allow-origin"], ORIGIN);
+        assert!(allowed.headers()["access-control-allow-headers"]
+            .to_str()
+            .unwrap()
+            .contains("authorization"));
+
+        let other = send(preflight(addr, "https://example.org")).await;
+        assert!(!other.headers().contains_key("access-control-allow-origin"));
+    }
+
+    #[tokio::test]
+    async fn should_translate_grpc_web_requests() {
+        let addr = serve().await;
+
+        // an empty HealthCheckRequest, in a gRPC-Web frame (an uncompressed flag and a zero length.)
+        let request = Request::post(format!("http://{}/grpc.health.v1.Health/Check", addr))
+            .header("origin", ORIGIN)
+            .header("content-type", "application/grpc-web+proto")
+            .header("x-grpc-web", "1")
+            .body(Body::from(vec![0u8, 0, 0, 0, 0]))
+            .unwrap();
+        let response = send(request).await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/grpc-web+proto"
+        );
+        assert_eq!(response.headers()["access-control-allow-origin"], ORIGIN);
+        // the trailers are sent at the end of the body.
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("grpc-status:0"));
+    }
+}
@@ -0,0 +1,88 @@
+//! contains the standard grpc.health.v1 health checks, for load balancers.
+//! The server (service "") and the OrderbookAggregator service are reported as SERVING while at least
+//! server.min_streaming_exchanges exchanges are streaming, and NOT_SERVING otherwise.
+//! The exchange statuses come from the StatusTracker (see exchange_status.rs.)
+use tokio::sync::watch;
+use tonic::server::NamedService;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::orderbook::{ConnectionState, ExchangeStatuses};
+use crate::orderbook_aggregator::OrderbookSummaryPublisher;
+use crate::orderbook_service::orderbook_aggregator_server::OrderbookAggregatorServer;
+
+// the overall health of the server, as defined by the health checking protocol.
+const SERVER: &str = "";
+
+/// spawns a process that updates the health statuses whenever an exchange's status changes.
+pub fn report_health(
+    mut reporter: HealthReporter,
+    mut status_rx: watch::Receiver<ExchangeStatuses>,
+    min_streaming_exchanges: usize,
+) {
+    tokio::spawn(async move {
+        let mut last_status = None;
+        loop {
+            let status = serving_status(&status_rx.borrow(), min_streaming_exchanges);
+            if last_status != Some(status) {
+                info!("health status: {:?}", status);
+                for service in [
+                    SERVER,
+                    <OrderbookAggregatorServer<OrderbookSummaryPublisher> as NamedService>::NAME,
+                ] {
+                    reporter.set_service_status(service, status).await;
+                }
+                last_status = Some(status);
+            }
+
+            if status_rx.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn serving_status(statuses: &ExchangeStatuses, min_streaming_exchanges: usize) -> ServingStatus {
+    let streaming = statuses
+        .exchanges
+        .iter()
+        .filter(|status| status.state == ConnectionState::Streaming as i32)
+        .count();
+
+    if streaming >= min_streaming_exchanges {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::ExchangeStatus;
+
+    fn statuses(states: &[ConnectionState]) -> ExchangeStatuses {
+        ExchangeStatuses {
+            exchanges: states
+                .iter()
+                .map(|state| ExchangeStatus {
+                    state: *state as i32,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn should_serve_when_enough_exchanges_are_streaming() {
+        let one_streaming = statuses(&[ConnectionState::Streaming, ConnectionState::BackingOff]);
+
+        assert_eq!(serving_status(&one_streaming, 1), ServingStatus::Serving);
+        assert_eq!(serving_status(&one_streaming, 2), ServingStatus::NotServing);
+        assert_eq!(
+            serving_status(&statuses(&[ConnectionState::Subscribed]), 1),
+            ServingStatus::NotServing
+        );
+        assert_eq!(serving_status(&statuses(&[]), 0), ServingStatus::Serving);
+    }
+}
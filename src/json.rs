@@ -0,0 +1,149 @@
+//! contains the JSON representations of the messages, for gateways that don't use protobuf.
+//! Enums are names (eg "streaming") rather than numbers, so they're readable without the proto file.
+use serde::Serialize;
+
+use crate::orderbook::{ConnectionState, ExchangeStatus, ExchangeStatuses, Level, Summary};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SummaryJson {
+    pub pair: String,
+    pub sequence: u64,
+    pub spread: f64,
+    pub bids: Vec<LevelJson>,
+    pub asks: Vec<LevelJson>,
+}
+
+impl SummaryJson {
+    /// the summary with only the top depth levels on each side.
+    pub fn new(pair: &str, summary: &Summary, depth: usize) -> SummaryJson {
+        let levels = |levels: &[Level]| levels.iter().take(depth).map(LevelJson::from).collect();
+        SummaryJson {
+            pair: pair.to_string(),
+            sequence: summary.sequence,
+            spread: summary.spread,
+            bids: levels(&summary.bids),
+            asks: levels(&summary.asks),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpreadJson {
+    pub pair: String,
+    pub sequence: u64,
+    // None if either side of the book is empty.
+    pub spread: Option<f64>,
+    pub best_bid: Option<LevelJson>,
+    pub best_ask: Option<LevelJson>,
+}
+
+impl SpreadJson {
+    /// the best bid and ask of the summary, and the spread between them.
+    pub fn new(pair: &str, summary: &Summary) -> SpreadJson {
+        let best_bid = summary.bids.first();
+        let best_ask = summary.asks.first();
+        SpreadJson {
+            pair: pair.to_string(),
+            sequence: summary.sequence,
+            spread: best_bid.and(best_ask).map(|_| summary.spread),
+            best_bid: best_bid.map(LevelJson::from),
+            best_ask: best_ask.map(LevelJson::from),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LevelJson {
+    pub exchange: String,
+    pub price: f64,
+    pub amount: f64,
+}
+
+impl From<&Level> for LevelJson {
+    fn from(level: &Level) -> LevelJson {
+        LevelJson {
+            exchange: level.exchange.clone(),
+            price: level.price,
+            amount: level.amount,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExchangeStatusJson {
+    pub exchange: String,
+    pub state: String,
+    pub last_message_ms: u64,
+    pub reconnects: u64,
+    pub last_error: String,
+    pub messages_per_s: f64,
+}
+
+impl From<&ExchangeStatus> for ExchangeStatusJson {
+    fn from(status: &ExchangeStatus) -> ExchangeStatusJson {
+        let state = ConnectionState::try_from(status.state)
+            .map(|state| state.as_str_name().to_lowercase())
+            .unwrap_or_else(|_| format!("unknown ({})", status.state));
+        ExchangeStatusJson {
+            exchange: status.exchange.clone(),
+            state,
+            last_message_ms: status.last_message_ms,
+            reconnects: status.reconnects,
+            last_error: status.last_error.clone(),
+            messages_per_s: status.messages_per_s,
+        }
+    }
+}
+
+pub fn exchange_statuses(statuses: &ExchangeStatuses) -> Vec<ExchangeStatusJson> {
+    statuses
+        .exchanges
+        .iter()
+        .map(ExchangeStatusJson::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn should_serialize_summary_to_depth() {
+        let level = |exchange: &str, price: f64| Level {
+            exchange: exchange.to_string(),
+            price,
+            amount: 2.0,
+        };
+        let summary = Summary {
+            spread: 1.0,
+            bids: vec![level("binance", 100.0), level("bitstamp", 99.0)],
+            asks: vec![level("bitstamp", 101.0), level("binance", 102.0)],
+            sequence: 3,
+            dropped: 0,
+        };
+
+        assert_eq!(
+            serde_json::to_value(SummaryJson::new("BTCUSDT", &summary, 1)).unwrap(),
+            json!({
+                "pair": "BTCUSDT",
+                "sequence": 3,
+                "spread": 1.0,
+                "bids": [{"exchange": "binance", "price": 100.0, "amount": 2.0}],
+                "asks": [{"exchange": "bitstamp", "price": 101.0, "amount": 2.0}],
+            })
+        );
+    }
+
+    #[test]
+    fn should_name_connection_states() {
+        let status = ExchangeStatus {
+            exchange: "binance".to_string(),
+            state: ConnectionState::BackingOff as i32,
+            ..Default::default()
+        };
+
+        assert_eq!(ExchangeStatusJson::from(&status).state, "backing_off");
+    }
+}
@@ -0,0 +1,4 @@
+//! contains the code shared by the server and the consumers of its feeds (see examples/.)
+pub mod multicast_packet;
+pub mod result;
+pub mod shm_book;
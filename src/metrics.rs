@@ -1,4 +1,6 @@
 //! Sets up metrics + exporter
+use std::net::SocketAddr;
+
 use metrics::gauge;
 use metrics_exporter_prometheus::PrometheusBuilder;
 
@@ -6,12 +8,12 @@ use metrics_exporter_prometheus::PrometheusBuilder;
 // This can be used for alerting if services die (eg gauge = 0 means nothing running!)
 const RUNNING_GAUGE: &str = "running";
 
-// starts the prometheus exporter and registers the service.
-// metrics can be seen at localhost:9000
-pub fn start_server_and_register() {
-    info!("starting metrics server @ 0.0.0.0:9000");
+// starts the prometheus exporter on the address and registers the service.
+// metrics can be seen at eg localhost:9000/metrics
+pub fn start_server_and_register(addr: SocketAddr) {
+    info!("starting metrics server @ {}", addr);
 
-    let builder = PrometheusBuilder::new();
+    let builder = PrometheusBuilder::new().with_http_listener(addr);
     builder
         .install()
         .expect("failed to install metric recorder/exporter");
@@ -0,0 +1,416 @@
+//! contains the UDP multicast feed, for low latency consumers on the same network as the server.
+//! Each consolidated summary is sent to the multicast group as an update with the levels that changed since the
+//! previous packet, and a snapshot of the whole book is sent periodically, and when a new exchange is seen.
+//! The packets are in a compact binary format with sequence numbers (see multicast_packet.rs.)
+//! UDP doesn't guarantee delivery, so consumers that see a gap in the sequence numbers can request the lost packets
+//! again, or a snapshot of the current book, over TCP at the retransmit address. The latest packets are kept for this.
+//! Summaries are read from Feeds::summary_rx (see orderbook_aggregator.rs.)
+//! Note: the feed is for trusted networks. Neither the packets nor the retransmit requests are authenticated, so they
+//! bypass [auth] and carry every exchange's levels. The retransmit address is on the loopback interface by default.
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use metrics::counter;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::watch;
+
+use crate::app_config::MulticastConfig;
+use crate::orderbook::{Level, Summary};
+use crate::result::Result;
+use crate::shared_summary::SharedSummary;
+use orderbooks_rs::multicast_packet::{
+    LevelChange, Packet, PacketLevel, Request, Side, REQUEST_LEN,
+};
+
+/// starts publishing the summaries to the multicast group, and serving retransmit requests.
+pub async fn start(
+    config: &MulticastConfig,
+    summary_rx: watch::Receiver<SharedSummary>,
+) -> Result<()> {
+    let socket = multicast_socket(config)?;
+    let listener = TcpListener::bind(config.retransmit_addr).await?;
+    let feed = Arc::new(Mutex::new(Feed::new(config.retransmit_buffer)));
+
+    serve_retransmits(listener, feed.clone());
+    tokio::spawn(publish(
+        socket,
+        config.group_addr,
+        Duration::from_millis(config.snapshot_interval_ms),
+        summary_rx,
+        feed,
+    ));
+    Ok(())
+}
+
+fn multicast_socket(config: &MulticastConfig) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_multicast_ttl_v4(config.ttl)?;
+    // consumers on the same host receive the packets too.
+    socket.set_multicast_loop_v4(true)?;
+    let interface = config.interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+    if config.interface.is_some() {
+        socket.set_multicast_if_v4(&interface)?;
+    }
+    socket.bind(&SocketAddr::new(interface.into(), 0).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+async fn publish(
+    socket: UdpSocket,
+    group_addr: SocketAddr,
+    snapshot_interval: Duration,
+    mut summary_rx: watch::Receiver<SharedSummary>,
+    feed: Arc<Mutex<Feed>>,
+) {
+    let mut snapshots = tokio::time::interval(snapshot_interval);
+
+    loop {
+        let packet = tokio::select! {
+            changed = summary_rx.changed() => {
+                if changed.is_err() {
+                    error!("summary watch closed. Stopping the multicast feed.");
+                    return;
+                }
+                let summary = summary_rx.borrow_and_update().clone();
+                // the lock is released here, rather than at the end of the select.
+                let packet = feed.lock().unwrap().update(summary.summary());
+                packet
+            }
+            _ = snapshots.tick() => Some(feed.lock().unwrap().snapshot()),
+        };
+
+        if let Some(packet) = packet {
+            match socket.send_to(&packet, group_addr).await {
+                Ok(_) => counter!("multicast.packets_sent", 1),
+                Err(e) => {
+                    counter!("multicast.send_errors", 1);
+                    warn!("couldn't send multicast packet to {}: {}", group_addr, e);
+                }
+            }
+        }
+    }
+}
+
+// the state of the feed: the book as of the last packet, and the latest packets for retransmission.
+struct Feed {
+    // the sequence number of the last packet.
+    sequence: u64,
+    summary_sequence: u64,
+    // the levels refer to exchanges by their index in this list. Exchanges are only ever added.
+    exchanges: Vec<String>,
+    bids: Vec<PacketLevel>,
+    asks: Vec<PacketLevel>,
+    sent: VecDeque<(u64, Bytes)>,
+    retransmit_buffer: usize,
+}
+
+impl Feed {
+    fn new(retransmit_buffer: usize) -> Feed {
+        Feed {
+            sequence: 0,
+            summary_sequence: 0,
+            exchanges: vec![],
+            bids: vec![],
+            asks: vec![],
+            sent: VecDeque::new(),
+            retransmit_buffer,
+        }
+    }
+
+    // returns the packet for the summary, or None if the book hasn't changed.
+    // A snapshot is returned instead of an update if the summary has an exchange that hasn't been seen before.
+    fn update(&mut self, summary: &Summary) -> Option<Bytes> {
+        let mut new_exchange = false;
+        for level in summary.bids.iter().chain(&summary.asks) {
+            if !self.exchanges.contains(&level.exchange) {
+                self.exchanges.push(level.exchange.clone());
+                new_exchange = true;
+            }
+        }
+        let bids = self.levels(&summary.bids);
+        let asks = self.levels(&summary.asks);
+        self.summary_sequence = summary.sequence;
+
+        let mut changes = level_changes(Side::Bid, &self.bids, &bids);
+        changes.append(&mut level_changes(Side::Ask, &self.asks, &asks));
+        self.bids = bids;
+        self.asks = asks;
+
+        if new_exchange {
+            return Some(self.snapshot());
+        }
+        if changes.is_empty() {
+            return None;
+        }
+        self.sequence += 1;
+        Some(self.keep(Packet::Update {
+            sequence: self.sequence,
+            summary_sequence: self.summary_sequence,
+            changes,
+        }))
+    }
+
+    // returns a new snapshot packet.
+    fn snapshot(&mut self) -> Bytes {
+        self.sequence += 1;
+        self.keep(self.current())
+    }
+
+    // returns a snapshot of the book as of the last packet, for consumers that request one.
+    fn current(&self) -> Packet {
+        Packet::Snapshot {
+            sequence: self.sequence,
+            summary_sequence: self.summary_sequence,
+            exchanges: self.exchanges.clone(),
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+        }
+    }
+
+    // keeps the packet for retransmission, and returns it encoded.
+    fn keep(&mut self, packet: Packet) -> Bytes {
+        let encoded = packet.encode();
+        if self.sent.len() == self.retransmit_buffer {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((packet.sequence(), encoded.clone()));
+        encoded
+    }
+
+    // returns the packets that are still kept from and to the sequence numbers.
+    fn retransmit(&self, from: u64, to: u64) -> Vec<Bytes> {
+        self.sent
+            .iter()
+            .filter(|(sequence, _)| (from..=to).contains(sequence))
+            .map(|(_, packet)| packet.clone())
+            .collect()
+    }
+
+    fn levels(&self, levels: &[Level]) -> Vec<PacketLevel> {
+        levels
+            .iter()
+            .map(|level| PacketLevel {
+                exchange: self
+                    .exchanges
+                    .iter()
+                    .position(|exchange| exchange == &level.exchange)
+                    .expect("exchanges are added before their levels")
+                    as u8,
+                price: level.price,
+                amount: level.amount,
+            })
+            .collect()
+    }
+}
+
+// returns the changes from the old levels to the new: levels that were removed have an amount of 0.
+fn level_changes(side: Side, old: &[PacketLevel], new: &[PacketLevel]) -> Vec<LevelChange> {
+    let same_level =
+        |a: &PacketLevel, b: &PacketLevel| a.exchange == b.exchange && a.price == b.price;
+    let change = |level: &PacketLevel, amount: f64| LevelChange {
+        side,
+        exchange: level.exchange,
+        price: level.price,
+        amount,
+    };
+
+    let removed = old
+        .iter()
+        .filter(|o| !new.iter().any(|n| same_level(o, n)))
+        .map(|o| change(o, 0.0));
+    let changed = new
+        .iter()
+        .filter(|n| !old.contains(n))
+        .map(|n| change(n, n.amount));
+    removed.chain(changed).collect()
+}
+
+fn serve_retransmits(listener: TcpListener, feed: Arc<Mutex<Feed>>) {
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let feed = feed.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_retransmits(stream, feed).await {
+                            debug!("retransmit client {} error: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => error!("couldn't accept retransmit connection: {}", e),
+            }
+        }
+    });
+}
+
+// replies to each request on the connection until the consumer closes it.
+async fn handle_retransmits(mut stream: TcpStream, feed: Arc<Mutex<Feed>>) -> Result<()> {
+    let mut request = [0; REQUEST_LEN];
+    loop {
+        match stream.read_exact(&mut request).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        let packets = match Request::decode(&request)? {
+            Request::Retransmit { from, to } => feed.lock().unwrap().retransmit(from, to),
+            Request::Snapshot => vec![feed.lock().unwrap().current().encode()],
+        };
+        counter!("multicast.retransmitted_packets", packets.len() as u64);
+
+        let mut reply = BytesMut::new();
+        for packet in packets {
+            reply.put_u16_le(packet.len() as u16);
+            reply.put_slice(&packet);
+        }
+        reply.put_u16_le(0);
+        stream.write_all(&reply).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use orderbooks_rs::multicast_packet::{Apply, Book, BookLevel};
+
+    use super::*;
+
+    fn summary(sequence: u64, bids: &[(&str, f64, f64)], asks: &[(&str, f64, f64)]) -> Summary {
+        let levels = |levels: &[(&str, f64, f64)]| {
+            levels
+                .iter()
+                .map(|(exchange, price, amount)| Level {
+                    exchange: exchange.to_string(),
+                    price: *price,
+                    amount: *amount,
+                })
+                .collect::<Vec<Level>>()
+        };
+        Summary {
+            spread: 0.0,
+            bids: levels(bids),
+            asks: levels(asks),
+            sequence,
+            dropped: 0,
+        }
+    }
+
+    fn book_levels(levels: &[Level]) -> Vec<BookLevel> {
+        levels
+            .iter()
+            .map(|level| BookLevel {
+                exchange: level.exchange.clone(),
+                price: level.price,
+                amount: level.amount,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_publish_changes_that_rebuild_the_book() {
+        let mut feed = Feed::new(16);
+        let mut book = Book::default();
+        let summaries = [
+            summary(1, &[("binance", 100.0, 1.0)], &[("binance", 101.0, 1.0)]),
+            // bitstamp is new, so a snapshot is sent.
+            summary(
+                2,
+                &[("bitstamp", 100.5, 2.0), ("binance", 100.0, 1.0)],
+                &[("binance", 101.0, 1.0)],
+            ),
+            summary(
+                3,
+                &[("bitstamp", 100.5, 1.0), ("binance", 99.0, 3.0)],
+                &[("bitstamp", 100.8, 1.0), ("binance", 101.0, 1.0)],
+            ),
+        ];
+
+        for summary in &summaries {
+            let packet = Packet::decode(&feed.update(summary).unwrap()).unwrap();
+            assert_eq!(book.apply(packet), Apply::Applied);
+            assert_eq!(book.bids(), book_levels(&summary.bids));
+            assert_eq!(book.asks(), book_levels(&summary.asks));
+        }
+
+        // nothing is sent if the book hasn't changed.
+        assert_eq!(feed.update(&summaries[2]), None);
+        assert_eq!(feed.sequence, 3);
+    }
+
+    #[test]
+    fn should_only_send_changed_levels() {
+        let mut feed = Feed::new(16);
+        feed.update(&summary(
+            1,
+            &[("binance", 100.0, 1.0), ("binance", 99.0, 1.0)],
+            &[],
+        ));
+
+        let update = feed.update(&summary(
+            2,
+            &[("binance", 100.0, 2.0), ("binance", 98.0, 1.0)],
+            &[],
+        ));
+        match Packet::decode(&update.unwrap()).unwrap() {
+            Packet::Update {
+                sequence,
+                summary_sequence,
+                changes,
+            } => {
+                assert_eq!((sequence, summary_sequence), (2, 2));
+                let changes: Vec<(f64, f64)> =
+                    changes.iter().map(|c| (c.price, c.amount)).collect();
+                assert_eq!(changes, vec![(99.0, 0.0), (100.0, 2.0), (98.0, 1.0)]);
+            }
+            packet => panic!("expected an update, got {:?}", packet),
+        }
+    }
+
+    // sends the request, and returns the packets in the reply.
+    async fn request(stream: &mut TcpStream, request: Request) -> Vec<Packet> {
+        stream.write_all(&request.encode()).await.unwrap();
+        let mut packets = vec![];
+        loop {
+            let len = stream.read_u16_le().await.unwrap() as usize;
+            if len == 0 {
+                return packets;
+            }
+            let mut packet = vec![0; len];
+            stream.read_exact(&mut packet).await.unwrap();
+            packets.push(Packet::decode(&packet).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_retransmit_kept_packets() {
+        // only the last 2 packets are kept.
+        let mut feed = Feed::new(2);
+        for sequence in 1..=3 {
+            let price = 100.0 + sequence as f64;
+            feed.update(&summary(sequence, &[("binance", price, 1.0)], &[]));
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve_retransmits(listener, Arc::new(Mutex::new(feed)));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let sequences: Vec<u64> = request(&mut stream, Request::Retransmit { from: 1, to: 3 })
+            .await
+            .iter()
+            .map(Packet::sequence)
+            .collect();
+        assert_eq!(sequences, vec![2, 3]);
+
+        let snapshot = request(&mut stream, Request::Snapshot).await;
+        let mut book = Book::default();
+        book.apply(snapshot[0].clone());
+        assert_eq!(book.sequence(), Some(3));
+        assert_eq!(book.bids()[0].price, 103.0);
+    }
+}
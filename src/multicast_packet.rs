@@ -0,0 +1,451 @@
+//! contains the binary format of the multicast feed (see multicast.rs), and a Book that consumers can apply it to.
+//! Everything is little endian. Each packet is a header followed by a snapshot or an update:
+//!   header:   magic "OB", version u8, kind u8 (1 snapshot, 2 update), sequence u64, summary sequence u64
+//!   snapshot: exchange count u8, then each exchange name (length u8, utf-8), bid count u8, ask count u8,
+//!             then each bid and ask: exchange index u8, price f64, amount f64
+//!   update:   change count u16, then each change: side u8 (0 bid, 1 ask), exchange index u8, price f64, amount f64
+//! Levels are identified by their side, exchange and price. A change sets the level's amount, and 0 removes it.
+//! A snapshot replaces the book (and the exchange names), and updates apply from the next sequence number.
+//!
+//! Retransmit requests are sent over TCP (17 bytes): kind u8 (1 retransmit, 2 snapshot), from u64, to u64.
+//! The reply is each packet prefixed by its length (u16), then a length of 0.
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::result::Result;
+
+const MAGIC: [u8; 2] = *b"OB";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 20;
+const SNAPSHOT: u8 = 1;
+const UPDATE: u8 = 2;
+const LEVEL_LEN: usize = 17;
+const CHANGE_LEN: usize = 18;
+
+pub const REQUEST_LEN: usize = 17;
+const RETRANSMIT_REQUEST: u8 = 1;
+const SNAPSHOT_REQUEST: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+/// a level in a snapshot. The exchange is an index into the snapshot's exchange names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketLevel {
+    pub exchange: u8,
+    pub price: f64,
+    pub amount: f64,
+}
+
+/// a change to a level in an update. An amount of 0 removes the level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelChange {
+    pub side: Side,
+    pub exchange: u8,
+    pub price: f64,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Snapshot {
+        sequence: u64,
+        summary_sequence: u64,
+        exchanges: Vec<String>,
+        bids: Vec<PacketLevel>,
+        asks: Vec<PacketLevel>,
+    },
+    Update {
+        sequence: u64,
+        summary_sequence: u64,
+        changes: Vec<LevelChange>,
+    },
+}
+
+impl Packet {
+    pub fn sequence(&self) -> u64 {
+        match self {
+            Packet::Snapshot { sequence, .. } | Packet::Update { sequence, .. } => *sequence,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(HEADER_LEN + 64 * CHANGE_LEN);
+        buf.put_slice(&MAGIC);
+        buf.put_u8(VERSION);
+        match self {
+            Packet::Snapshot {
+                sequence,
+                summary_sequence,
+                exchanges,
+                bids,
+                asks,
+            } => {
+                buf.put_u8(SNAPSHOT);
+                buf.put_u64_le(*sequence);
+                buf.put_u64_le(*summary_sequence);
+                buf.put_u8(exchanges.len() as u8);
+                for exchange in exchanges {
+                    buf.put_u8(exchange.len() as u8);
+                    buf.put_slice(exchange.as_bytes());
+                }
+                buf.put_u8(bids.len() as u8);
+                buf.put_u8(asks.len() as u8);
+                for level in bids.iter().chain(asks) {
+                    buf.put_u8(level.exchange);
+                    buf.put_f64_le(level.price);
+                    buf.put_f64_le(level.amount);
+                }
+            }
+            Packet::Update {
+                sequence,
+                summary_sequence,
+                changes,
+            } => {
+                buf.put_u8(UPDATE);
+                buf.put_u64_le(*sequence);
+                buf.put_u64_le(*summary_sequence);
+                buf.put_u16_le(changes.len() as u16);
+                for change in changes {
+                    buf.put_u8(match change.side {
+                        Side::Bid => 0,
+                        Side::Ask => 1,
+                    });
+                    buf.put_u8(change.exchange);
+                    buf.put_f64_le(change.price);
+                    buf.put_f64_le(change.amount);
+                }
+            }
+        }
+        buf.freeze()
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Packet> {
+        need(buf, HEADER_LEN)?;
+        if buf[..2] != MAGIC || buf[2] != VERSION {
+            return Err("not a version 1 order book packet".into());
+        }
+        buf.advance(3);
+        let kind = buf.get_u8();
+        let sequence = buf.get_u64_le();
+        let summary_sequence = buf.get_u64_le();
+
+        match kind {
+            SNAPSHOT => {
+                need(buf, 1)?;
+                let mut exchanges = vec![];
+                for _ in 0..buf.get_u8() {
+                    need(buf, 1)?;
+                    let len = buf.get_u8() as usize;
+                    need(buf, len)?;
+                    exchanges.push(String::from_utf8(buf[..len].to_vec())?);
+                    buf.advance(len);
+                }
+                need(buf, 2)?;
+                let (bid_count, ask_count) = (buf.get_u8() as usize, buf.get_u8() as usize);
+                need(buf, (bid_count + ask_count) * LEVEL_LEN)?;
+                let mut level = || PacketLevel {
+                    exchange: buf.get_u8(),
+                    price: buf.get_f64_le(),
+                    amount: buf.get_f64_le(),
+                };
+                let bids = (0..bid_count).map(|_| level()).collect();
+                let asks = (0..ask_count).map(|_| level()).collect();
+                Ok(Packet::Snapshot {
+                    sequence,
+                    summary_sequence,
+                    exchanges,
+                    bids,
+                    asks,
+                })
+            }
+            UPDATE => {
+                need(buf, 2)?;
+                let count = buf.get_u16_le() as usize;
+                need(buf, count * CHANGE_LEN)?;
+                let mut changes = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let side = match buf.get_u8() {
+                        0 => Side::Bid,
+                        1 => Side::Ask,
+                        side => return Err(format!("unknown side: {}", side).into()),
+                    };
+                    changes.push(LevelChange {
+                        side,
+                        exchange: buf.get_u8(),
+                        price: buf.get_f64_le(),
+                        amount: buf.get_f64_le(),
+                    });
+                }
+                Ok(Packet::Update {
+                    sequence,
+                    summary_sequence,
+                    changes,
+                })
+            }
+            kind => Err(format!("unknown packet kind: {}", kind).into()),
+        }
+    }
+}
+
+fn need(buf: &[u8], len: usize) -> Result<()> {
+    if buf.len() < len {
+        return Err("packet is truncated".into());
+    }
+    Ok(())
+}
+
+/// a request sent to the retransmit address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    // the packets from and to the sequence numbers (inclusive.) Packets that are no longer kept are skipped.
+    Retransmit { from: u64, to: u64 },
+    // a snapshot of the current book, whose sequence number is the last packet published.
+    Snapshot,
+}
+
+impl Request {
+    pub fn encode(&self) -> [u8; REQUEST_LEN] {
+        let (kind, from, to) = match self {
+            Request::Retransmit { from, to } => (RETRANSMIT_REQUEST, *from, *to),
+            Request::Snapshot => (SNAPSHOT_REQUEST, 0, 0),
+        };
+        let mut request = [0; REQUEST_LEN];
+        let mut buf = &mut request[..];
+        buf.put_u8(kind);
+        buf.put_u64_le(from);
+        buf.put_u64_le(to);
+        request
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Request> {
+        need(buf, REQUEST_LEN)?;
+        let kind = buf.get_u8();
+        let from = buf.get_u64_le();
+        let to = buf.get_u64_le();
+        match kind {
+            RETRANSMIT_REQUEST => Ok(Request::Retransmit { from, to }),
+            SNAPSHOT_REQUEST => Ok(Request::Snapshot),
+            kind => Err(format!("unknown request kind: {}", kind).into()),
+        }
+    }
+}
+
+/// a level of the Book, with the exchange's name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookLevel {
+    pub exchange: String,
+    pub price: f64,
+    pub amount: f64,
+}
+
+/// what happened when a packet was applied to the Book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Apply {
+    Applied,
+    // the packet was already applied, or is from before the book's snapshot.
+    Stale,
+    // packets were lost. The consumer should request them, or a snapshot.
+    Gap { expected: u64, received: u64 },
+    // updates can't be applied until there's been a snapshot.
+    NeedSnapshot,
+}
+
+/// the consolidated book, built from the packets of the feed.
+#[derive(Debug, Default)]
+pub struct Book {
+    // the sequence number of the last packet applied, None until a snapshot has been.
+    sequence: Option<u64>,
+    exchanges: Vec<String>,
+    bids: Vec<PacketLevel>,
+    asks: Vec<PacketLevel>,
+}
+
+impl Book {
+    pub fn sequence(&self) -> Option<u64> {
+        self.sequence
+    }
+
+    pub fn apply(&mut self, packet: Packet) -> Apply {
+        match (packet, self.sequence) {
+            (Packet::Snapshot { sequence, .. }, Some(last)) if sequence <= last => Apply::Stale,
+            (
+                Packet::Snapshot {
+                    sequence,
+                    exchanges,
+                    bids,
+                    asks,
+                    ..
+                },
+                _,
+            ) => {
+                self.sequence = Some(sequence);
+                self.exchanges = exchanges;
+                self.bids = bids;
+                self.asks = asks;
+                Apply::Applied
+            }
+            (Packet::Update { .. }, None) => Apply::NeedSnapshot,
+            (Packet::Update { sequence, .. }, Some(last)) if sequence <= last => Apply::Stale,
+            (Packet::Update { sequence, .. }, Some(last)) if sequence > last + 1 => Apply::Gap {
+                expected: last + 1,
+                received: sequence,
+            },
+            (
+                Packet::Update {
+                    sequence, changes, ..
+                },
+                _,
+            ) => {
+                for change in changes {
+                    let levels = match change.side {
+                        Side::Bid => &mut self.bids,
+                        Side::Ask => &mut self.asks,
+                    };
+                    levels.retain(|level| {
+                        level.exchange != change.exchange || level.price != change.price
+                    });
+                    if change.amount > 0.0 {
+                        levels.push(PacketLevel {
+                            exchange: change.exchange,
+                            price: change.price,
+                            amount: change.amount,
+                        });
+                    }
+                }
+                self.sequence = Some(sequence);
+                Apply::Applied
+            }
+        }
+    }
+
+    /// the bids, best (highest) first.
+    pub fn bids(&self) -> Vec<BookLevel> {
+        let mut bids = self.levels(&self.bids);
+        bids.sort_by(|a, b| {
+            b.price
+                .total_cmp(&a.price)
+                .then(b.amount.total_cmp(&a.amount))
+        });
+        bids
+    }
+
+    /// the asks, best (lowest) first.
+    pub fn asks(&self) -> Vec<BookLevel> {
+        let mut asks = self.levels(&self.asks);
+        asks.sort_by(|a, b| a.price.total_cmp(&b.price));
+        asks
+    }
+
+    fn levels(&self, levels: &[PacketLevel]) -> Vec<BookLevel> {
+        levels
+            .iter()
+            .map(|level| BookLevel {
+                exchange: self
+                    .exchanges
+                    .get(level.exchange as usize)
+                    .cloned()
+                    .unwrap_or_default(),
+                price: level.price,
+                amount: level.amount,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(sequence: u64) -> Packet {
+        Packet::Snapshot {
+            sequence,
+            summary_sequence: 40,
+            exchanges: vec!["binance".to_string(), "bitstamp".to_string()],
+            bids: vec![PacketLevel {
+                exchange: 1,
+                price: 100.0,
+                amount: 2.0,
+            }],
+            asks: vec![PacketLevel {
+                exchange: 0,
+                price: 101.0,
+                amount: 1.5,
+            }],
+        }
+    }
+
+    fn update(sequence: u64, changes: Vec<LevelChange>) -> Packet {
+        Packet::Update {
+            sequence,
+            summary_sequence: 41,
+            changes,
+        }
+    }
+
+    fn change(side: Side, exchange: u8, price: f64, amount: f64) -> LevelChange {
+        LevelChange {
+            side,
+            exchange,
+            price,
+            amount,
+        }
+    }
+
+    #[test]
+    fn should_encode_and_decode_packets() {
+        let packets = [
+            snapshot(7),
+            update(8, vec![change(Side::Ask, 1, 100.5, 0.0)]),
+        ];
+        for packet in packets {
+            assert_eq!(Packet::decode(&packet.encode()).unwrap(), packet);
+        }
+
+        let encoded = update(8, vec![change(Side::Bid, 0, 99.0, 1.0)]).encode();
+        assert_eq!(encoded.len(), HEADER_LEN + 2 + CHANGE_LEN);
+        assert!(Packet::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(Packet::decode(b"not a packet at all!").is_err());
+
+        for request in [Request::Retransmit { from: 3, to: 9 }, Request::Snapshot] {
+            assert_eq!(Request::decode(&request.encode()).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn should_apply_packets_in_sequence() {
+        let mut book = Book::default();
+        assert_eq!(book.apply(update(7, vec![])), Apply::NeedSnapshot);
+        assert_eq!(book.apply(snapshot(7)), Apply::Applied);
+
+        let changes = vec![
+            change(Side::Bid, 1, 100.0, 0.0),
+            change(Side::Bid, 0, 100.5, 3.0),
+            change(Side::Ask, 0, 101.0, 0.5),
+        ];
+        assert_eq!(book.apply(update(8, changes)), Apply::Applied);
+        assert_eq!(
+            book.bids(),
+            vec![BookLevel {
+                exchange: "binance".to_string(),
+                price: 100.5,
+                amount: 3.0,
+            }]
+        );
+        assert_eq!(book.asks()[0].amount, 0.5);
+
+        assert_eq!(book.apply(update(8, vec![])), Apply::Stale);
+        assert_eq!(
+            book.apply(update(10, vec![])),
+            Apply::Gap {
+                expected: 9,
+                received: 10
+            }
+        );
+        // a later snapshot recovers from the gap.
+        assert_eq!(book.apply(snapshot(11)), Apply::Applied);
+        assert_eq!(book.sequence(), Some(11));
+    }
+}
@@ -1,105 +1,585 @@
 //! Contains the details for the async processes that receive and publish updates.
 //! See OrderBookData for merging updates and producing summary.
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use metrics::histogram;
+use metrics::{counter, histogram};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc;
-use tokio::sync::watch;
+use tokio::sync::{broadcast, watch, Mutex};
 use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
 
+use crate::auth::{authorize, require_all_exchanges, Entitlements};
+use crate::candles::{CandleBuilder, CONSOLIDATED};
+use crate::conflation::{stream_summaries, Conflation, SummaryStream};
+use crate::entitled_summaries::EntitledSummaries;
 use crate::exchange::OrderBookUpdate;
-use crate::orderbook::orderbook_aggregator_server::OrderbookAggregator;
-use crate::orderbook::Summary;
+use crate::features::FeatureCalculator;
+use crate::orderbook::{
+    Candle, ExchangeStatuses, FeatureLayoutReply, Features, ReloadReply, SpoofingAlert, Summary,
+    SummaryRequest, Trade,
+};
 use crate::orderbook_data::OrderBookData;
+use crate::orderbook_service::orderbook_aggregator_server::OrderbookAggregator;
+use crate::reload::ConfigReloader;
+use crate::shared_summary::SharedSummary;
+use crate::spoofing::SpoofingDetector;
 
 pub struct OrderbookSummaryPublisher {
+    feeds: Feeds,
+    // the pair the server streams.
+    pair: String,
+    // the name of each feature in the feature vectors.
+    feature_layout: Vec<String>,
+    // reloads the exchange settings on request.
+    reloader: Arc<Mutex<ConfigReloader>>,
+}
+
+/// the receiving ends of everything that's published to clients. Each client subscribes to the feeds it needs.
+#[derive(Clone)]
+pub struct Feeds {
     // receives new order book data from exchanges
-    // sends the updated summary to a watch for clients
-    watch_rx: watch::Receiver<Summary>,
+    // sends the updated summary to a watch for clients. It's encoded once and shared by all clients.
+    // A watch only holds the latest summary, so a client or feed that falls behind skips to the latest rather
+    // than falling further behind. The BookSummary rpc, the other transports and the feeds all read from it.
+    pub summary_rx: watch::Receiver<SharedSummary>,
+    // every summary is broadcast too, for clients that queue summaries rather than only taking the latest.
+    pub summaries_tx: broadcast::Sender<SharedSummary>,
+    // feature vectors calculated from each summary.
+    pub features_rx: watch::Receiver<Features>,
+    // alerts are events rather than state, so they're broadcast. Each client subscribes when it connects.
+    pub alerts_tx: broadcast::Sender<SpoofingAlert>,
+    // consolidated public trades from all exchanges.
+    pub trades_tx: broadcast::Sender<Trade>,
+    // candles are broadcast as they close.
+    pub candles_tx: broadcast::Sender<Candle>,
+    // the connection status of each exchange.
+    pub status_rx: watch::Receiver<ExchangeStatuses>,
+    // snapshots of the order book data the summaries are produced from, for views the summary can't provide
+    // (see rest.rs.)
+    pub book: SharedBook,
+    // the summaries of clients that are only entitled to some exchanges (see summaries.)
+    pub entitled_summaries: EntitledSummaries,
+}
+
+impl Feeds {
+    /// returns the watch and broadcast of the summaries the client is entitled to: the consolidated summaries,
+    /// or summaries of just the exchanges it's entitled to (see entitled_summaries.rs.) Either watch behaves like summary_rx.
+    pub fn summaries(
+        &self,
+        entitlements: &Entitlements,
+    ) -> (
+        watch::Receiver<SharedSummary>,
+        broadcast::Sender<SharedSummary>,
+    ) {
+        match entitlements.exchanges() {
+            None => (self.summary_rx.clone(), self.summaries_tx.clone()),
+            Some(exchanges) => self.entitled_summaries.subscribe(exchanges, &self.book),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Feeds {
+    /// feeds from the watches a test needs. Nothing is ever sent on the broadcasts.
+    pub fn from_watches(
+        summary_rx: watch::Receiver<SharedSummary>,
+        status_rx: watch::Receiver<ExchangeStatuses>,
+        book: SharedBook,
+    ) -> Feeds {
+        Feeds {
+            summary_rx,
+            summaries_tx: broadcast::channel(1).0,
+            features_rx: watch::channel(Features::default()).1,
+            alerts_tx: broadcast::channel(1).0,
+            trades_tx: broadcast::channel(1).0,
+            candles_tx: broadcast::channel(1).0,
+            status_rx,
+            book,
+            entitled_summaries: EntitledSummaries::default(),
+        }
+    }
+}
+
+/// a snapshot of the order book data after an update, and the sequence number of the summary produced from it.
+#[derive(Default)]
+pub struct BookSnapshot {
+    pub data: OrderBookData,
+    pub sequence: u64,
+}
+
+/// the latest book snapshot. It's replaced after every update, so readers take the Arc and release the watch.
+pub type SharedBook = watch::Receiver<Arc<BookSnapshot>>;
+
+// how often candles are checked to see if their interval has ended.
+const CANDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// the senders for everything the publisher produces. Clients receive from the other ends.
+pub struct PublisherSenders {
+    pub summary_tx: watch::Sender<SharedSummary>,
+    pub summaries_tx: broadcast::Sender<SharedSummary>,
+    pub features_tx: watch::Sender<Features>,
+    pub alerts_tx: broadcast::Sender<SpoofingAlert>,
+    pub trades_tx: broadcast::Sender<Trade>,
+    pub candles_tx: broadcast::Sender<Candle>,
+    pub book_tx: watch::Sender<Arc<BookSnapshot>>,
+    pub entitled_summaries: EntitledSummaries,
 }
 
 impl OrderbookSummaryPublisher {
-    pub fn new(watch_rx: watch::Receiver<Summary>) -> OrderbookSummaryPublisher {
-        OrderbookSummaryPublisher { watch_rx }
+    pub fn new(
+        feeds: Feeds,
+        pair: &str,
+        feature_layout: Vec<String>,
+        reloader: Arc<Mutex<ConfigReloader>>,
+    ) -> OrderbookSummaryPublisher {
+        OrderbookSummaryPublisher {
+            feeds,
+            pair: pair.to_string(),
+            feature_layout,
+            reloader,
+        }
     }
 
     // Spawns a process to receive OrderBookUpdates and merge them into OrderBookData which can then produce a merged view of orderbooks.
     // It will send the updated merged order book to the watch, which the clients then receive.
+    // The feature vector for each summary is calculated and sent to the features watch too.
+    // Level changes are checked for spoofing and any alerts are broadcast.
+    // Trades received from exchanges are broadcast as they arrive.
+    // Mid prices and trades are built into candles, which are broadcast as they close.
     pub async fn start(
         mut exchange_rx: mpsc::Receiver<OrderBookUpdate>,
-        watch_tx: watch::Sender<Summary>,
+        mut trades_rx: mpsc::Receiver<Trade>,
+        feature_calculator: FeatureCalculator,
+        spoofing_detector: SpoofingDetector,
+        candle_builder: CandleBuilder,
+        senders: PublisherSenders,
     ) {
         tokio::spawn(async move {
-            let mut orderbook_data = OrderBookData::default();
+            let mut aggregator = Aggregator {
+                book: OrderBookData::default(),
+                sequence: 0,
+                feature_calculator,
+                spoofing_detector,
+                candle_builder,
+                senders,
+            };
+            let mut candle_check = tokio::time::interval(CANDLE_CHECK_INTERVAL);
 
             loop {
-                match exchange_rx.recv().await {
-                    None => debug!("empty exchange update received on exchange channel"),
-                    Some(orderbook_update) => {
-                        let now = Instant::now();
+                tokio::select! {
+                    _ = candle_check.tick() => {
+                        let closed = aggregator.candle_builder.close_expired(now_ms());
+                        aggregator.publish_candles(closed);
+                    }
+                    Some(trade) = trades_rx.recv() => aggregator.handle_trade(trade),
+                    update = exchange_rx.recv() => match update {
+                        None => debug!("empty exchange update received on exchange channel"),
+                        Some(orderbook_update) => aggregator.handle_update(orderbook_update),
+                    },
+                }
+            }
+        });
+    }
+}
+
+// holds the state of the process spawned by OrderbookSummaryPublisher::start.
+struct Aggregator {
+    book: OrderBookData,
+    // the sequence number of the last summary.
+    sequence: u64,
+    feature_calculator: FeatureCalculator,
+    spoofing_detector: SpoofingDetector,
+    candle_builder: CandleBuilder,
+    senders: PublisherSenders,
+}
 
-                        let instant = orderbook_update.ts.clone();
-                        let exchange = orderbook_update.exchange.clone();
+impl Aggregator {
+    fn handle_update(&mut self, orderbook_update: OrderBookUpdate) {
+        let now = Instant::now();
 
-                        orderbook_data.update_exchange_data(orderbook_update);
+        let instant = orderbook_update.ts;
+        let exchange = orderbook_update.exchange.clone();
 
-                        histogram!("orderbook_merge.time_taken_s", now.elapsed().as_secs_f64());
+        let level_changes = self.book.update_exchange_data(orderbook_update);
+        self.sequence += 1;
+        let summary = Summary {
+            sequence: self.sequence,
+            ..self.book.summary()
+        };
+        let exchange_mid_price = self.book.exchange_mid_price(&exchange);
 
-                        let summary = orderbook_data.summary();
-                        watch_tx
-                            .send(summary)
-                            .expect("something went wrong publishing watch...");
+        histogram!("orderbook_merge.time_taken_s", now.elapsed().as_secs_f64());
 
-                        histogram!(
-                            format!("exchange.{}.time_taken_s", exchange),
-                            instant.elapsed().as_secs_f64()
-                        ); // Would be nice if exchange was a dimension on the metric so you'd get the rollup.
-                    }
+        for alert in self.spoofing_detector.check(&level_changes) {
+            info!("spoofing alert: {:?}", alert);
+            counter!(format!("exchange.{}.spoofing_alerts", exchange), 1);
+            // sending only fails if no clients are subscribed to alerts.
+            let _ = self.senders.alerts_tx.send(alert);
+        }
+
+        let features = self.feature_calculator.update(&summary);
+
+        let now_ms = now_ms();
+        let mut closed = vec![];
+        if let Some(mid_price) = exchange_mid_price {
+            closed.append(
+                &mut self
+                    .candle_builder
+                    .update_price(&exchange, mid_price, now_ms),
+            );
+        }
+        if let (Some(bid), Some(ask)) = (summary.bids.first(), summary.asks.first()) {
+            let mid_price = (bid.price + ask.price) / 2.0;
+            closed.append(
+                &mut self
+                    .candle_builder
+                    .update_price(CONSOLIDATED, mid_price, now_ms),
+            );
+        }
+        self.publish_candles(closed);
+
+        // readers of the book get a snapshot, so they never hold up the next update.
+        self.senders.book_tx.send_replace(Arc::new(BookSnapshot {
+            data: self.book.snapshot(),
+            sequence: self.sequence,
+        }));
+        let summary = SharedSummary::new(summary);
+        // sending only fails if no clients are queueing summaries.
+        let _ = self.senders.summaries_tx.send(summary.clone());
+        self.senders
+            .summary_tx
+            .send(summary)
+            .expect("something went wrong publishing watch...");
+        // after the book snapshot, which clients subscribing to a new set of exchanges start from.
+        self.senders
+            .entitled_summaries
+            .publish(&self.book, self.sequence);
+        self.senders
+            .features_tx
+            .send(features)
+            .expect("something went wrong publishing features watch...");
+
+        histogram!(
+            format!("exchange.{}.time_taken_s", exchange),
+            instant.elapsed().as_secs_f64()
+        ); // Would be nice if exchange was a dimension on the metric so you'd get the rollup.
+    }
+
+    fn handle_trade(&mut self, trade: Trade) {
+        counter!(format!("exchange.{}.trades", trade.exchange), 1);
+
+        self.spoofing_detector
+            .record_trade(&trade, tokio::time::Instant::now());
+
+        let closed = self.candle_builder.add_trade(&trade, now_ms());
+        self.publish_candles(closed);
+
+        // sending only fails if no clients are subscribed to trades.
+        let _ = self.senders.trades_tx.send(trade);
+    }
+
+    fn publish_candles(&self, candles: Vec<Candle>) {
+        for candle in candles {
+            debug!("candle closed: {:?}", candle);
+            // sending only fails if no clients are subscribed to candles.
+            let _ = self.senders.candles_tx.send(candle);
+        }
+    }
+}
+
+// milliseconds since the unix epoch. Candles are aligned to the local clock.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_millis() as u64
+}
+
+// spawns a process that sends each new value of the watch to the client.
+// Clients only ever see the latest value - if they're slow, intermediate values are skipped.
+fn stream_watch<T>(mut wrx: watch::Receiver<T>) -> ReceiverStream<Result<T, tonic::Status>>
+where
+    T: Clone + std::fmt::Debug + Send + Sync + 'static,
+{
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        loop {
+            match wrx.changed().await {
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Client receive error. Closing connection. {:?}", e);
+                    break;
                 }
             }
-        });
-    }
+
+            let val = wrx.borrow().clone();
+
+            match tx.send(Ok(val)).await {
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Client send error. Closing connection. {:?}", e);
+                    break;
+                }
+            };
+        }
+    });
+
+    ReceiverStream::new(rx)
 }
 
-#[derive(Debug)]
-pub struct AggregatorPublisher {}
+// spawns a process that sends each broadcast value to the client.
+// Clients that fall too far behind skip the values they missed.
+fn stream_broadcast<T>(mut brx: broadcast::Receiver<T>) -> ReceiverStream<Result<T, tonic::Status>>
+where
+    T: Clone + std::fmt::Debug + Send + Sync + 'static,
+{
+    let (tx, rx) = mpsc::channel(4);
 
-impl AggregatorPublisher {}
+    tokio::spawn(async move {
+        loop {
+            let val = match brx.recv().await {
+                Ok(val) => val,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Client lagging. Skipped {} messages.", skipped);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Client receive error. Closing connection. {:?}", e);
+                    break;
+                }
+            };
+
+            if let Err(e) = tx.send(Ok(val)).await {
+                error!("Client send error. Closing connection. {:?}", e);
+                break;
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+// spawns a process that maps each value of the stream for the client, skipping values that map to None.
+// Used to filter streams for clients that are only entitled to some exchanges.
+fn filter_stream<T, U>(
+    mut stream: ReceiverStream<Result<T, tonic::Status>>,
+    filter: impl Fn(T) -> Option<U> + Send + 'static,
+) -> ReceiverStream<Result<U, tonic::Status>>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        while let Some(val) = stream.next().await {
+            let val = match val {
+                Ok(val) => match filter(val) {
+                    Some(val) => Ok(val),
+                    None => continue,
+                },
+                Err(e) => Err(e),
+            };
+
+            if tx.send(val).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+/// removes the statuses of exchanges the client isn't entitled to.
+pub fn entitled_statuses(
+    entitlements: &Entitlements,
+    statuses: ExchangeStatuses,
+) -> ExchangeStatuses {
+    ExchangeStatuses {
+        exchanges: statuses
+            .exchanges
+            .into_iter()
+            .filter(|status| entitlements.allows_exchange(&status.exchange))
+            .collect(),
+    }
+}
 
 #[tonic::async_trait]
 impl OrderbookAggregator for OrderbookSummaryPublisher {
-    type BookSummaryStream = ReceiverStream<Result<Summary, tonic::Status>>;
+    type BookSummaryStream = SummaryStream;
 
     async fn book_summary(
         &self,
-        _request: tonic::Request<crate::orderbook::Empty>,
+        request: tonic::Request<SummaryRequest>,
     ) -> Result<tonic::Response<Self::BookSummaryStream>, tonic::Status> {
-        let (tx, rx) = mpsc::channel(4);
+        let entitlements = authorize(&request, "BookSummary")?;
+        let pair = &request.get_ref().pair;
+        if !pair.is_empty() && *pair != self.pair {
+            return Err(tonic::Status::not_found(format!(
+                "unknown pair: {} (the server streams {})",
+                pair, self.pair
+            )));
+        }
+        let conflation = Conflation::from_request(request.get_ref())
+            .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
+        debug!(
+            "new summary client: {} {:?}",
+            entitlements.client, conflation
+        );
 
-        let mut wrx: watch::Receiver<Summary> = self.watch_rx.clone();
+        let (summary_rx, summaries_tx) = self.feeds.summaries(&entitlements);
+        Ok(tonic::Response::new(stream_summaries(
+            summary_rx,
+            &summaries_tx,
+            conflation,
+        )))
+    }
 
-        tokio::spawn(async move {
-            loop {
-                match wrx.changed().await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Client receive error. Closing connection. {:?}", e);
-                        break;
-                    }
-                }
+    type BookFeaturesStream = ReceiverStream<Result<Features, tonic::Status>>;
+
+    async fn book_features(
+        &self,
+        request: tonic::Request<crate::orderbook::Empty>,
+    ) -> Result<tonic::Response<Self::BookFeaturesStream>, tonic::Status> {
+        // features are calculated from every exchange's levels.
+        let entitlements = authorize(&request, "BookFeatures")?;
+        require_all_exchanges(&entitlements, "BookFeatures")?;
+        Ok(tonic::Response::new(stream_watch(
+            self.feeds.features_rx.clone(),
+        )))
+    }
 
-                let val = wrx.borrow().clone();
+    async fn feature_layout(
+        &self,
+        request: tonic::Request<crate::orderbook::Empty>,
+    ) -> Result<tonic::Response<FeatureLayoutReply>, tonic::Status> {
+        authorize(&request, "FeatureLayout")?;
+        Ok(tonic::Response::new(FeatureLayoutReply {
+            names: self.feature_layout.clone(),
+        }))
+    }
 
-                match tx.send(Ok(val)).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Client send error. Closing connection. {:?}", e);
-                        break;
-                    }
-                };
-            }
-        });
+    type SpoofingAlertsStream = ReceiverStream<Result<SpoofingAlert, tonic::Status>>;
+
+    async fn spoofing_alerts(
+        &self,
+        request: tonic::Request<crate::orderbook::Empty>,
+    ) -> Result<tonic::Response<Self::SpoofingAlertsStream>, tonic::Status> {
+        let entitlements = authorize(&request, "SpoofingAlerts")?;
+        let stream = stream_broadcast(self.feeds.alerts_tx.subscribe());
+        Ok(tonic::Response::new(filter_stream(stream, move |alert| {
+            entitlements
+                .allows_exchange(&alert.exchange)
+                .then_some(alert)
+        })))
+    }
+
+    type TradesStream = ReceiverStream<Result<Trade, tonic::Status>>;
+
+    async fn trades(
+        &self,
+        request: tonic::Request<crate::orderbook::Empty>,
+    ) -> Result<tonic::Response<Self::TradesStream>, tonic::Status> {
+        let entitlements = authorize(&request, "Trades")?;
+        let stream = stream_broadcast(self.feeds.trades_tx.subscribe());
+        Ok(tonic::Response::new(filter_stream(stream, move |trade| {
+            entitlements
+                .allows_exchange(&trade.exchange)
+                .then_some(trade)
+        })))
+    }
+
+    type CandlesStream = ReceiverStream<Result<Candle, tonic::Status>>;
+
+    async fn candles(
+        &self,
+        request: tonic::Request<crate::orderbook::Empty>,
+    ) -> Result<tonic::Response<Self::CandlesStream>, tonic::Status> {
+        let entitlements = authorize(&request, "Candles")?;
+        let stream = stream_broadcast(self.feeds.candles_tx.subscribe());
+        // consolidated candles include every exchange, so they need entitlement to all of them.
+        Ok(tonic::Response::new(filter_stream(stream, move |candle| {
+            let entitled = if candle.source == CONSOLIDATED {
+                !entitlements.restricts_exchanges()
+            } else {
+                entitlements.allows_exchange(&candle.source)
+            };
+            entitled.then_some(candle)
+        })))
+    }
+
+    async fn reload_config(
+        &self,
+        request: tonic::Request<crate::orderbook::Empty>,
+    ) -> Result<tonic::Response<ReloadReply>, tonic::Status> {
+        authorize(&request, "ReloadConfig")?;
+        match self.reloader.lock().await.reload().await {
+            Ok(reply) => Ok(tonic::Response::new(reply)),
+            Err(e) => Err(tonic::Status::failed_precondition(format!(
+                "couldn't reload, keeping the current settings. {}",
+                e
+            ))),
+        }
+    }
+
+    async fn exchange_status(
+        &self,
+        request: tonic::Request<crate::orderbook::Empty>,
+    ) -> Result<tonic::Response<ExchangeStatuses>, tonic::Status> {
+        let entitlements = authorize(&request, "ExchangeStatus")?;
+        let statuses = self.feeds.status_rx.borrow().clone();
+        Ok(tonic::Response::new(entitled_statuses(
+            &entitlements,
+            statuses,
+        )))
+    }
+
+    type ExchangeStatusUpdatesStream = ReceiverStream<Result<ExchangeStatuses, tonic::Status>>;
+
+    async fn exchange_status_updates(
+        &self,
+        request: tonic::Request<crate::orderbook::Empty>,
+    ) -> Result<tonic::Response<Self::ExchangeStatusUpdatesStream>, tonic::Status> {
+        let entitlements = authorize(&request, "ExchangeStatusUpdates")?;
+        let stream = stream_watch(self.feeds.status_rx.clone());
+        Ok(tonic::Response::new(filter_stream(
+            stream,
+            move |statuses| Some(entitled_statuses(&entitlements, statuses)),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_give_restricted_clients_the_summaries_of_their_exchanges() {
+        let (summary_tx, summary_rx) = watch::channel(SharedSummary::default());
+        let feeds = Feeds::from_watches(
+            summary_rx,
+            watch::channel(ExchangeStatuses::default()).1,
+            watch::channel(Arc::default()).1,
+        );
+        let entitled = |exchanges: &[&str]| {
+            let exchanges = exchanges
+                .iter()
+                .map(|exchange| exchange.to_string())
+                .collect();
+            Entitlements::new("research", None, Some(exchanges), None)
+        };
+
+        let (consolidated, _) = feeds.summaries(&Entitlements::unrestricted());
+        assert!(consolidated.same_channel(&summary_tx.subscribe()));
 
-        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+        let (first, _) = feeds.summaries(&entitled(&["binance", "bitstamp"]));
+        let (second, _) = feeds.summaries(&entitled(&["bitstamp", "binance"]));
+        assert!(!first.same_channel(&consolidated));
+        assert!(first.same_channel(&second));
+        let (other, _) = feeds.summaries(&entitled(&["bitstamp"]));
+        assert!(!other.same_channel(&first));
     }
 }
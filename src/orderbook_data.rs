@@ -1,35 +1,215 @@
 //! contains the logic in the application that merges OrderBookUpdates for different exchanges
 //! and will provide a Summary with TOP_N bids/asks and the spread across all exchange data.
+//! It also tracks the lifetime of each price level (when it appeared, how its size changed and when it vanished.)
 use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::time::Instant;
 
 use crate::exchange::OrderBookUpdate;
-use crate::orderbook::Summary;
+use crate::orderbook::{Level, Summary};
+
+pub const TOP_N: usize = 10; // Determines how many bids/asks are kept.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Bid => "bid",
+            Side::Ask => "ask",
+        }
+    }
+}
 
-const TOP_N: usize = 10; // Determines how many bids/asks are kept.
+#[derive(Debug, Clone, PartialEq)]
+/// the history of a price level on an exchange, from when it appeared in the book.
+pub struct LevelLifetime {
+    pub exchange: String,
+    pub side: Side,
+    pub price: f64,
+    pub appeared: Instant,
+    // last time the level was seen, or when it vanished.
+    pub last_seen: Instant,
+    pub first_amount: f64,
+    pub max_amount: f64,
+    pub amount: f64,
+    // number of times the amount changed while the level was in the book.
+    pub size_changes: u32,
+}
+
+impl LevelLifetime {
+    fn new(exchange: &str, side: Side, level: &Level, ts: Instant) -> LevelLifetime {
+        LevelLifetime {
+            exchange: exchange.to_string(),
+            side,
+            price: level.price,
+            appeared: ts,
+            last_seen: ts,
+            first_amount: level.amount,
+            max_amount: level.amount,
+            amount: level.amount,
+            size_changes: 0,
+        }
+    }
+
+    /// how long the level has been (or was) in the book.
+    pub fn lifetime(&self) -> std::time::Duration {
+        self.last_seen.duration_since(self.appeared)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+/// the levels that changed with an exchange update.
+pub struct LevelChanges {
+    // levels that are still in the book but have less size than before.
+    pub reduced: Vec<LevelLifetime>,
+    // levels that are no longer in the book, although their price is still inside the levels the exchange sends.
+    pub vanished: Vec<LevelLifetime>,
+    // levels that moved out of the levels the exchange sends, or were cleared with the exchange's book.
+    // They may still be in the exchange's book, so they aren't reported as vanished.
+    pub dropped: Vec<LevelLifetime>,
+}
+
+// levels are keyed by side and price (f64 bits, as prices are parsed from the same strings each time).
+type LevelKey = (Side, u64);
 
 // maintains the last order book Summary for any number of exchanges.
 // This allows generation of a summary w/ top 10 bids/asks across exchanges
 #[derive(Default)]
 pub struct OrderBookData {
-    exchange_data: HashMap<String, OrderBookUpdate>,
+    // shared with snapshots, so taking a snapshot doesn't copy the levels.
+    exchange_data: HashMap<String, Arc<OrderBookUpdate>>,
+    // lifetimes of the levels currently in the book, per exchange.
+    level_lifetimes: HashMap<String, HashMap<LevelKey, LevelLifetime>>,
 }
 
 impl OrderBookData {
-    /// replaces the data from a specific exchange.
-    pub fn update_exchange_data(&mut self, update: OrderBookUpdate) {
-        self.exchange_data.insert(update.exchange.clone(), update);
+    /// replaces the data from a specific exchange and returns the levels that were reduced, vanished or dropped.
+    /// Note: exchanges only send their top levels, so a missing level only vanished if its price is still inside
+    /// the prices sent for its side. Otherwise it may just have moved out of the top levels, and it's dropped.
+    /// An empty update (eg the exchange disconnected) drops all the levels.
+    pub fn update_exchange_data(&mut self, update: OrderBookUpdate) -> LevelChanges {
+        let changes = self.track_levels(&update);
+        self.exchange_data
+            .insert(update.exchange.clone(), Arc::new(update));
+        changes
+    }
+
+    /// returns a copy of the current levels of each exchange, for readers that can't share the data.
+    /// Level lifetimes aren't included.
+    pub fn snapshot(&self) -> OrderBookData {
+        OrderBookData {
+            exchange_data: self.exchange_data.clone(),
+            level_lifetimes: HashMap::new(),
+        }
+    }
+
+    fn track_levels(&mut self, update: &OrderBookUpdate) -> LevelChanges {
+        let mut changes = LevelChanges::default();
+
+        if update.bids.is_empty() && update.asks.is_empty() {
+            if let Some(previous) = self.level_lifetimes.remove(&update.exchange) {
+                changes.dropped.extend(previous.into_values());
+            }
+            return changes;
+        }
+
+        let mut previous = self
+            .level_lifetimes
+            .remove(&update.exchange)
+            .unwrap_or_default();
+        let mut current = HashMap::with_capacity(update.bids.len() + update.asks.len());
+
+        let sides = [(Side::Bid, &update.bids), (Side::Ask, &update.asks)];
+        for (side, levels) in sides {
+            for level in levels.iter() {
+                let key = (side, level.price.to_bits());
+                let lifetime = match previous.remove(&key) {
+                    None => LevelLifetime::new(&update.exchange, side, level, update.ts),
+                    Some(mut lifetime) => {
+                        lifetime.last_seen = update.ts;
+                        if level.amount != lifetime.amount {
+                            if level.amount < lifetime.amount {
+                                changes.reduced.push(LevelLifetime {
+                                    amount: level.amount,
+                                    size_changes: lifetime.size_changes + 1,
+                                    ..lifetime.clone()
+                                });
+                            }
+                            lifetime.size_changes += 1;
+                            lifetime.amount = level.amount;
+                            lifetime.max_amount = lifetime.max_amount.max(level.amount);
+                        }
+                        lifetime
+                    }
+                };
+                current.insert(key, lifetime);
+            }
+        }
+
+        // the lowest bid and highest ask sent, so levels between them and the top of the book are visible.
+        let lowest_bid = update.bids.iter().map(|l| l.price).reduce(f64::min);
+        let highest_ask = update.asks.iter().map(|l| l.price).reduce(f64::max);
+        for (_, mut lifetime) in previous.into_iter() {
+            lifetime.last_seen = update.ts;
+            let visible = match lifetime.side {
+                Side::Bid => lowest_bid.is_some_and(|lowest| lifetime.price >= lowest),
+                Side::Ask => highest_ask.is_some_and(|highest| lifetime.price <= highest),
+            };
+            if visible {
+                changes.vanished.push(lifetime);
+            } else {
+                changes.dropped.push(lifetime);
+            }
+        }
+
+        self.level_lifetimes
+            .insert(update.exchange.clone(), current);
+        changes
+    }
+
+    /// returns the mid price of an exchange's own top of book, if it has bids and asks.
+    pub fn exchange_mid_price(&self, exchange: &str) -> Option<f64> {
+        match self.exchange_best_prices(exchange) {
+            (Some(best_bid), Some(best_ask)) => Some((best_bid + best_ask) / 2.0),
+            _ => None,
+        }
+    }
+
+    /// returns the best bid and ask prices of an exchange's own book.
+    pub fn exchange_best_prices(&self, exchange: &str) -> (Option<f64>, Option<f64>) {
+        match self.exchange_data.get(exchange) {
+            Some(update) => (
+                update.bids.iter().map(|l| l.price).reduce(f64::max),
+                update.asks.iter().map(|l| l.price).reduce(f64::min),
+            ),
+            None => (None, None),
+        }
     }
 
     /// summary returns a Summary containing top 10 bids/asks across all exchanges.
+    pub fn summary(&self) -> Summary {
+        self.summary_for(|_| true)
+    }
+
+    /// returns a Summary containing top 10 bids/asks across the exchanges that are included.
     /// can be made more efficient via k-way merge eg using vec like a minheap.
     /// (For the current requirement, this will be sufficient.)
-    pub fn summary(&self) -> Summary {
+    pub fn summary_for(&self, include: impl Fn(&str) -> bool) -> Summary {
         let mut bids = vec![];
         let mut asks = vec![];
 
-        for (_ex, ex_summary) in self.exchange_data.borrow().into_iter() {
+        for (_ex, ex_summary) in self.exchange_data.borrow().iter() {
+            if !include(&ex_summary.exchange) {
+                continue;
+            }
             // For the sake of simplicity, we clone the bids and asks. Only TOP_N are kept per exchange.
             bids.append(&mut ex_summary.bids.clone());
             asks.append(&mut ex_summary.asks.clone());
@@ -71,14 +251,18 @@ impl OrderBookData {
             spread = asks.first().unwrap().price - bids.first().unwrap().price;
         }
 
-        Summary { spread, bids, asks }
+        Summary {
+            spread,
+            bids,
+            asks,
+            ..Default::default()
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::orderbook::Level;
-    use tokio::time::Instant;
+    use tokio::time::Duration;
 
     use super::*;
 
@@ -101,9 +285,7 @@ mod tests {
 
     #[test]
     fn should_add_new_order_book_data_for_unseen_exchange() {
-        let mut order_book_data = OrderBookData {
-            exchange_data: Default::default(),
-        };
+        let mut order_book_data = OrderBookData::default();
 
         let order_book_update = OrderBookUpdate {
             ts: Instant::now(),
@@ -173,9 +355,7 @@ mod tests {
 
     #[test]
     fn should_replace_new_order_book_data_for_previously_seen_exchange() {
-        let mut order_book_data = OrderBookData {
-            exchange_data: Default::default(),
-        };
+        let mut order_book_data = OrderBookData::default();
 
         let order_book_update = OrderBookUpdate {
             ts: Instant::now(),
@@ -266,9 +446,7 @@ mod tests {
 
     #[test]
     fn should_handle_order_book_data_for_multiple_exchanges() {
-        let mut order_book_data = OrderBookData {
-            exchange_data: Default::default(),
-        };
+        let mut order_book_data = OrderBookData::default();
 
         let order_book_update = OrderBookUpdate {
             ts: Instant::now(),
@@ -356,4 +534,135 @@ mod tests {
         // top ask - top bid.
         assert_eq!(summary.spread, 40.4 - 7.8)
     }
+
+    #[test]
+    fn should_track_reduced_and_vanished_levels() {
+        let mut order_book_data = OrderBookData::default();
+        let start = Instant::now();
+
+        let changes = order_book_data.update_exchange_data(OrderBookUpdate {
+            ts: start,
+            exchange: "binance".to_string(),
+            bids: sample_levels("binance".to_string(), vec![(5.5, 10.0), (4.4, 11.0)]),
+            asks: sample_levels("binance".to_string(), vec![(6.5, 10.0)]),
+        });
+        assert_eq!(changes, LevelChanges::default());
+
+        let changes = order_book_data.update_exchange_data(OrderBookUpdate {
+            ts: start + Duration::from_millis(100),
+            exchange: "binance".to_string(),
+            bids: sample_levels("binance".to_string(), vec![(5.5, 2.0), (4.3, 11.0)]),
+            asks: sample_levels("binance".to_string(), vec![(6.5, 12.0)]),
+        });
+
+        assert_eq!(
+            changes.reduced,
+            vec![LevelLifetime {
+                exchange: "binance".to_string(),
+                side: Side::Bid,
+                price: 5.5,
+                appeared: start,
+                last_seen: start + Duration::from_millis(100),
+                first_amount: 10.0,
+                max_amount: 10.0,
+                amount: 2.0,
+                size_changes: 1,
+            }]
+        );
+
+        assert_eq!(changes.vanished.len(), 1);
+        let vanished = changes.vanished.first().unwrap();
+        assert_eq!(vanished.price, 4.4);
+        assert_eq!(vanished.side, Side::Bid);
+        assert_eq!(vanished.lifetime(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn should_drop_levels_that_move_out_of_the_visible_range() {
+        let mut order_book_data = OrderBookData::default();
+        let start = Instant::now();
+
+        order_book_data.update_exchange_data(OrderBookUpdate {
+            ts: start,
+            exchange: "binance".to_string(),
+            bids: sample_levels("binance".to_string(), vec![(5.5, 10.0), (4.4, 11.0)]),
+            asks: sample_levels("binance".to_string(), vec![(6.5, 10.0), (7.5, 10.0)]),
+        });
+
+        // new levels pushed 4.4 and 7.5 out of the top levels the exchange sends.
+        let changes = order_book_data.update_exchange_data(OrderBookUpdate {
+            ts: start + Duration::from_millis(100),
+            exchange: "binance".to_string(),
+            bids: sample_levels("binance".to_string(), vec![(5.6, 1.0), (5.5, 10.0)]),
+            asks: sample_levels("binance".to_string(), vec![(6.4, 1.0), (6.5, 10.0)]),
+        });
+
+        assert!(changes.vanished.is_empty());
+        let mut dropped: Vec<f64> = changes.dropped.iter().map(|l| l.price).collect();
+        dropped.sort_by(f64::total_cmp);
+        assert_eq!(dropped, vec![4.4, 7.5]);
+    }
+
+    #[test]
+    fn should_not_report_vanished_levels_when_exchange_is_cleared() {
+        let mut order_book_data = OrderBookData::default();
+
+        order_book_data.update_exchange_data(OrderBookUpdate {
+            ts: Instant::now(),
+            exchange: "binance".to_string(),
+            bids: sample_levels("binance".to_string(), vec![(5.5, 10.0)]),
+            asks: sample_levels("binance".to_string(), vec![(6.5, 10.0)]),
+        });
+
+        let changes = order_book_data.update_exchange_data(OrderBookUpdate {
+            ts: Instant::now(),
+            exchange: "binance".to_string(),
+            bids: vec![],
+            asks: vec![],
+        });
+
+        assert!(changes.vanished.is_empty());
+        assert_eq!(changes.dropped.len(), 2);
+    }
+
+    #[test]
+    fn should_provide_exchange_mid_price() {
+        let mut order_book_data = OrderBookData::default();
+
+        order_book_data.update_exchange_data(OrderBookUpdate {
+            ts: Instant::now(),
+            exchange: "binance".to_string(),
+            bids: sample_levels("binance".to_string(), vec![(5.5, 10.0), (6.5, 1.0)]),
+            asks: sample_levels("binance".to_string(), vec![(8.5, 10.0), (7.5, 1.0)]),
+        });
+
+        assert_eq!(order_book_data.exchange_mid_price("binance"), Some(7.0));
+        assert_eq!(order_book_data.exchange_mid_price("bitstamp"), None);
+    }
+
+    #[test]
+    fn should_summarise_included_exchanges() {
+        let mut order_book_data = OrderBookData::default();
+        for (exchange, bid, ask) in [("binance", 5.0, 8.0), ("bitstamp", 6.0, 7.0)] {
+            order_book_data.update_exchange_data(OrderBookUpdate {
+                ts: Instant::now(),
+                exchange: exchange.to_string(),
+                bids: sample_levels(exchange.to_string(), vec![(bid, 1.0)]),
+                asks: sample_levels(exchange.to_string(), vec![(ask, 1.0)]),
+            });
+        }
+
+        let summary = order_book_data.summary_for(|exchange| exchange == "binance");
+
+        assert_eq!(summary.spread, 3.0);
+        assert_eq!(
+            summary.bids,
+            sample_levels("binance".to_string(), vec![(5.0, 1.0)])
+        );
+        assert_eq!(
+            summary.asks,
+            sample_levels("binance".to_string(), vec![(8.0, 1.0)])
+        );
+        assert_eq!(order_book_data.summary().spread, 1.0);
+    }
 }
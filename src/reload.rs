@@ -0,0 +1,290 @@
+//! contains the hot reloading of exchange configuration.
+//! ExchangeConnections owns the connection tasks for each exchange (see create_exchange_ws_connection).
+//! When the settings are reloaded, only the exchanges that were added, removed or changed have their tasks
+//! restarted, so clients and the other exchanges are unaffected.
+//! Reloads are triggered by the settings file changing (it's checked periodically) or the ReloadConfig rpc.
+//! Note: only exchange settings are reloaded. The feature layout is fixed at startup, so an added exchange
+//! isn't included in the per-exchange features until the server is restarted.
+//! The pair is fixed at startup too (clients and the gateways are told which pair is streamed), so a reload
+//! that changes it is rejected.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::app_config::{settings_path, AppConfig, ExchangeConfig, Overrides};
+use crate::exchange::{self, OrderBookUpdate};
+use crate::exchange_status::StatusTracker;
+use crate::orderbook::{ReloadReply, Trade};
+use crate::result::Result;
+
+// the running connection tasks for an exchange, and the config they were started with.
+struct Connection {
+    config: ExchangeConfig,
+    order_book: JoinHandle<()>,
+    trades: Option<JoinHandle<()>>,
+}
+
+/// the connections to every enabled exchange.
+pub struct ExchangeConnections {
+    connections: HashMap<String, Connection>,
+    exchange_tx: mpsc::Sender<OrderBookUpdate>,
+    trades_tx: mpsc::Sender<Trade>,
+    status: StatusTracker,
+}
+
+impl ExchangeConnections {
+    pub fn new(
+        exchange_tx: mpsc::Sender<OrderBookUpdate>,
+        trades_tx: mpsc::Sender<Trade>,
+        status: StatusTracker,
+    ) -> ExchangeConnections {
+        ExchangeConnections {
+            connections: HashMap::new(),
+            exchange_tx,
+            trades_tx,
+            status,
+        }
+    }
+
+    /// starts connections for new exchanges, stops connections for exchanges that aren't in the configs,
+    /// and restarts connections for exchanges whose config changed. Unchanged connections are left alone.
+    pub async fn apply(&mut self, configs: Vec<ExchangeConfig>) -> ReloadReply {
+        let mut reply = ReloadReply::default();
+
+        let mut removed: Vec<String> = self
+            .connections
+            .keys()
+            .filter(|id| !configs.iter().any(|config| &config.id == *id))
+            .cloned()
+            .collect();
+        removed.sort();
+        for id in removed {
+            info!("stopping exchange connections for: [{}]", id);
+            self.stop(&id).await;
+            reply.removed.push(id);
+        }
+
+        for config in configs {
+            match self.connections.get(&config.id) {
+                Some(connection) if connection.config == config => continue,
+                Some(_) => {
+                    info!("restarting exchange connections for: [{}]", config.id);
+                    self.stop(&config.id).await;
+                    reply.restarted.push(config.id.clone());
+                }
+                None => {
+                    info!("starting exchange connections for: [{}]", config.id);
+                    reply.added.push(config.id.clone());
+                }
+            }
+            self.start(config);
+        }
+
+        reply
+    }
+
+    fn start(&mut self, config: ExchangeConfig) {
+        let order_book = exchange::create_exchange_ws_connection(
+            config.clone(),
+            self.exchange_tx.clone(),
+            self.status.reporter(&config.id),
+        );
+        let trades =
+            exchange::create_exchange_trades_ws_connection(config.clone(), self.trades_tx.clone());
+
+        self.connections.insert(
+            config.id.clone(),
+            Connection {
+                config,
+                order_book,
+                trades,
+            },
+        );
+    }
+
+    // aborts the exchange's tasks and clears its order book, as the tasks can't clear it once aborted.
+    // The tasks are awaited so they've stopped before the exchange's status is removed and its book cleared.
+    async fn stop(&mut self, id: &str) {
+        if let Some(connection) = self.connections.remove(id) {
+            connection.order_book.abort();
+            // the result is the cancellation, or the task's panic.
+            let _ = connection.order_book.await;
+            if let Some(trades) = connection.trades {
+                trades.abort();
+                let _ = trades.await;
+            }
+            self.status.remove(id);
+
+            self.exchange_tx
+                .send(exchange::empty_order_book_update(id))
+                .await
+                .expect("unexpected error sending to channel. Panic!");
+        }
+    }
+}
+
+/// re-reads and validates the settings file, then applies the exchange configs to the connections.
+pub struct ConfigReloader {
+    settings: String,
+    overrides: Overrides,
+    // the pair the server was started with.
+    pair: String,
+    connections: ExchangeConnections,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        settings: String,
+        overrides: Overrides,
+        pair: &str,
+        connections: ExchangeConnections,
+    ) -> ConfigReloader {
+        ConfigReloader {
+            settings,
+            overrides,
+            pair: pair.to_string(),
+            connections,
+        }
+    }
+
+    /// the connections are left as they are if the settings are invalid or change the pair.
+    pub async fn reload(&mut self) -> Result<ReloadReply> {
+        let conf = AppConfig::new(&self.settings, &self.overrides)?;
+        conf.validate()?;
+        let pair = conf.spot_pair()?;
+        if pair != self.pair {
+            return Err(format!(
+                "the pair can't be changed from {} to {} by a reload, restart the server to change it",
+                self.pair, pair
+            )
+            .into());
+        }
+
+        let reply = self.connections.apply(conf.exchange_configs()?).await;
+        info!("reloaded {}: {:?}", self.settings, reply);
+        Ok(reply)
+    }
+
+    fn settings_path(&self) -> PathBuf {
+        settings_path(&self.settings)
+    }
+}
+
+/// spawns a process that reloads the settings whenever the settings file is modified.
+pub fn watch_settings_file(reloader: Arc<Mutex<ConfigReloader>>, check_interval: Duration) {
+    tokio::spawn(async move {
+        let path = reloader.lock().await.settings_path();
+        let mut last_modified = modified(&path);
+        let mut interval = tokio::time::interval(check_interval);
+
+        loop {
+            interval.tick().await;
+
+            let modified = modified(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            info!("{:?} changed, reloading exchange settings", path);
+            if let Err(e) = reloader.lock().await.reload().await {
+                error!(
+                    "couldn't reload {:?}, keeping the current settings. {}",
+                    path, e
+                );
+            }
+        }
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // nothing listens on the endpoint, so the connection tasks just retry until they're aborted.
+    fn config(id: &str, receive_timeout_s: u64) -> ExchangeConfig {
+        ExchangeConfig {
+            id: id.to_string(),
+            endpoint: "ws://127.0.0.1:1".to_string(),
+            subscription_message_template: "{}".to_string(),
+            spot_pair: "BTCUSDT".to_string(),
+            receive_timeout_s,
+            trades: None,
+        }
+    }
+
+    fn reply(added: &[&str], removed: &[&str], restarted: &[&str]) -> ReloadReply {
+        let strings = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect();
+        ReloadReply {
+            added: strings(added),
+            removed: strings(removed),
+            restarted: strings(restarted),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_only_restart_changed_exchanges() {
+        let (exchange_tx, mut exchange_rx) = mpsc::channel(32);
+        let (trades_tx, _trades_rx) = mpsc::channel(32);
+        let (status, _status_rx) = StatusTracker::new();
+        let mut connections = ExchangeConnections::new(exchange_tx, trades_tx, status);
+
+        let started = connections
+            .apply(vec![config("binance", 1), config("bitstamp", 20)])
+            .await;
+        assert_eq!(started, reply(&["binance", "bitstamp"], &[], &[]));
+
+        let unchanged = connections
+            .apply(vec![config("binance", 1), config("bitstamp", 20)])
+            .await;
+        assert_eq!(unchanged, reply(&[], &[], &[]));
+
+        let changed = connections.apply(vec![config("binance", 5)]).await;
+        assert_eq!(changed, reply(&[], &["bitstamp"], &["binance"]));
+        assert_eq!(connections.connections.len(), 1);
+        assert_eq!(
+            connections.connections["binance"].config.receive_timeout_s,
+            5
+        );
+
+        // the stopped exchanges' order books are cleared.
+        let mut cleared = vec![];
+        while let Ok(update) = exchange_rx.try_recv() {
+            assert!(update.bids.is_empty() && update.asks.is_empty());
+            cleared.push(update.exchange);
+        }
+        assert!(cleared.contains(&"bitstamp".to_string()));
+        assert!(cleared.contains(&"binance".to_string()));
+    }
+
+    #[tokio::test]
+    async fn should_reject_reloads_that_change_the_pair() {
+        let (exchange_tx, _exchange_rx) = mpsc::channel(32);
+        let (trades_tx, _trades_rx) = mpsc::channel(32);
+        let (status, _status_rx) = StatusTracker::new();
+        let connections = ExchangeConnections::new(exchange_tx, trades_tx, status);
+        let overrides = Overrides {
+            pair: Some("ETHUSDT".to_string()),
+            ..Default::default()
+        };
+        let mut reloader = ConfigReloader::new(
+            "TestSettings".to_string(),
+            overrides,
+            "BTCUSDT",
+            connections,
+        );
+
+        let error = reloader.reload().await.unwrap_err();
+
+        assert!(error.to_string().contains("from BTCUSDT to ETHUSDT"));
+        assert!(reloader.connections.connections.is_empty());
+    }
+}
@@ -0,0 +1,443 @@
+//! contains the REST API, so curl and ad-hoc tools can read the book without a gRPC client.
+//!   GET /v1/book/{pair}?depth=N&exchanges=binance,bitstamp - the consolidated book, optionally of some exchanges.
+//!   GET /v1/spread/{pair}?exchanges=binance - the best bid and ask, and the spread between them.
+//!   GET /v1/exchanges - the connection status and best prices of each exchange.
+//!   GET /v1/stream/{pair}?depth=N - summaries and spread changes as server-sent events (see sse.rs.)
+//! Responses are built from a snapshot of the current order book data (see SharedBook) rather than the last summary,
+//! so the book can be consolidated from any of the exchanges.
+//! When auth is configured, clients send an `Authorization: Bearer <token>` header like gRPC clients (see auth.rs.)
+//! The book and spread need the BookSummary entitlement, and the exchanges need ExchangeStatus.
+//! Errors are returned as {"error": "..."} with the matching http status.
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::net::TcpListener;
+use tonic::metadata::MetadataMap;
+use tonic::Code;
+
+use crate::auth::{authorize_exchange, authorize_rpc, Authenticator, Entitlements};
+use crate::json::{ExchangeStatusJson, SpreadJson, SummaryJson};
+use crate::orderbook::Summary;
+use crate::orderbook_aggregator::{entitled_statuses, Feeds};
+use crate::orderbook_data::TOP_N;
+use crate::result;
+use crate::sse;
+
+/// what's shared by every request.
+pub struct Api {
+    pub feeds: Feeds,
+    authenticator: Authenticator,
+    // the pair the server streams.
+    pub pair: String,
+}
+
+impl Api {
+    pub fn new(feeds: Feeds, authenticator: Authenticator, pair: &str) -> Api {
+        Api {
+            feeds,
+            authenticator,
+            pair: pair.to_string(),
+        }
+    }
+
+    /// authenticates the client from the request's headers, and checks it's entitled to the rpc.
+    pub fn authorize(&self, headers: &HeaderMap, rpc: &str) -> Result<Entitlements, ApiError> {
+        let metadata = MetadataMap::from_headers(headers.clone());
+        let entitlements = self.authenticator.entitlements(&metadata)?;
+        authorize_rpc(&entitlements, rpc)?;
+        Ok(entitlements)
+    }
+
+    /// rejects requests for pairs other than the server's.
+    pub fn check_pair(&self, pair: &str) -> Result<(), ApiError> {
+        if pair != self.pair {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("unknown pair: {} (the server streams {})", pair, self.pair),
+            ));
+        }
+        Ok(())
+    }
+
+    // returns the summary of the requested exchanges, or every exchange the client is entitled to.
+    fn summary(
+        &self,
+        entitlements: &Entitlements,
+        exchanges: Option<&str>,
+    ) -> Result<Summary, ApiError> {
+        let requested = match exchanges {
+            Some(exchanges) => Some(self.requested_exchanges(entitlements, exchanges)?),
+            None => None,
+        };
+        let include = |exchange: &str| match &requested {
+            Some(requested) => requested.contains(exchange),
+            None => entitlements.allows_exchange(exchange),
+        };
+
+        let book = self.feeds.book.borrow().clone();
+        Ok(Summary {
+            sequence: book.sequence,
+            ..book.data.summary_for(include)
+        })
+    }
+
+    // parses the comma separated exchanges, which must be known and entitled.
+    fn requested_exchanges(
+        &self,
+        entitlements: &Entitlements,
+        exchanges: &str,
+    ) -> Result<HashSet<String>, ApiError> {
+        let statuses = self.feeds.status_rx.borrow().clone();
+        let mut requested = HashSet::new();
+        for exchange in exchanges.split(',').map(str::trim) {
+            if !statuses.exchanges.iter().any(|s| s.exchange == exchange) {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("unknown exchange: {}", exchange),
+                ));
+            }
+            authorize_exchange(entitlements, exchange)?;
+            requested.insert(exchange.to_string());
+        }
+        Ok(requested)
+    }
+}
+
+/// returns the routes of the API.
+pub fn router(api: Api) -> Router {
+    Router::new()
+        .route("/v1/book/:pair", get(book))
+        .route("/v1/spread/:pair", get(spread))
+        .route("/v1/exchanges", get(exchanges))
+        .route("/v1/stream/:pair", get(sse::summaries))
+        .with_state(Arc::new(api))
+}
+
+/// spawns a process that serves the API on the listener.
+pub fn serve(listener: TcpListener, api: Api) -> result::Result<()> {
+    let server = axum::Server::from_tcp(listener.into_std()?)?;
+
+    tokio::spawn(async move {
+        if let Err(e) = server.serve(router(api).into_make_service()).await {
+            error!("REST API stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct BookQuery {
+    depth: Option<usize>,
+    exchanges: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpreadQuery {
+    exchanges: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ExchangeJson {
+    #[serde(flatten)]
+    status: ExchangeStatusJson,
+    best_bid: Option<f64>,
+    best_ask: Option<f64>,
+}
+
+async fn book(
+    State(api): State<Arc<Api>>,
+    Path(pair): Path<String>,
+    query: Result<Query<BookQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Json<SummaryJson>, ApiError> {
+    let entitlements = api.authorize(&headers, "BookSummary")?;
+    api.check_pair(&pair)?;
+    let Query(query) = query?;
+    let depth = query.depth.unwrap_or(TOP_N);
+    if depth == 0 || depth > TOP_N {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("depth must be between 1 and {}", TOP_N),
+        ));
+    }
+
+    let summary = api.summary(&entitlements, query.exchanges.as_deref())?;
+    Ok(Json(SummaryJson::new(&api.pair, &summary, depth)))
+}
+
+async fn spread(
+    State(api): State<Arc<Api>>,
+    Path(pair): Path<String>,
+    query: Result<Query<SpreadQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Json<SpreadJson>, ApiError> {
+    let entitlements = api.authorize(&headers, "BookSummary")?;
+    api.check_pair(&pair)?;
+    let Query(query) = query?;
+
+    let summary = api.summary(&entitlements, query.exchanges.as_deref())?;
+    Ok(Json(SpreadJson::new(&api.pair, &summary)))
+}
+
+async fn exchanges(
+    State(api): State<Arc<Api>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let entitlements = api.authorize(&headers, "ExchangeStatus")?;
+
+    let statuses = entitled_statuses(&entitlements, api.feeds.status_rx.borrow().clone());
+    let book = api.feeds.book.borrow().clone();
+    let exchanges: Vec<ExchangeJson> = statuses
+        .exchanges
+        .iter()
+        .map(|status| {
+            let (best_bid, best_ask) = book.data.exchange_best_prices(&status.exchange);
+            ExchangeJson {
+                status: status.into(),
+                best_bid,
+                best_ask,
+            }
+        })
+        .collect();
+    Ok(Json(json!({ "exchanges": exchanges })))
+}
+
+/// an error response, returned as {"error": "..."}.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: String) -> ApiError {
+        ApiError { status, message }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+// auth rejections are returned with the http equivalent of their status.
+impl From<tonic::Status> for ApiError {
+    fn from(status: tonic::Status) -> ApiError {
+        let code = match status.code() {
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::FORBIDDEN,
+        };
+        ApiError::new(code, status.message().to_string())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, rejection.body_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use tokio::sync::watch;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::app_config::{ApiKeyConfig, AuthConfig};
+    use crate::exchange::OrderBookUpdate;
+    use crate::orderbook::{ExchangeStatus, ExchangeStatuses};
+    use crate::orderbook_aggregator::BookSnapshot;
+    use crate::orderbook_data::OrderBookData;
+    use crate::shared_summary::SharedSummary;
+
+    // binance and bitstamp are connected. The research key is only entitled to the book of binance,
+    // and the admin key is unrestricted.
+    fn api() -> Router {
+        let mut data = OrderBookData::default();
+        data.update_exchange_data(OrderBookUpdate::from_prices(
+            "binance",
+            vec![100.0, 99.0],
+            vec![103.0],
+        ));
+        data.update_exchange_data(OrderBookUpdate::from_prices(
+            "bitstamp",
+            vec![101.0],
+            vec![102.0],
+        ));
+        let book = watch::channel(Arc::new(BookSnapshot { data, sequence: 2 })).1;
+        let statuses = ExchangeStatuses {
+            exchanges: ["binance", "bitstamp"]
+                .iter()
+                .map(|exchange| ExchangeStatus {
+                    exchange: exchange.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        let feeds = Feeds::from_watches(
+            watch::channel(SharedSummary::default()).1,
+            watch::channel(statuses).1,
+            book,
+        );
+        let auth = AuthConfig {
+            keys: vec![
+                ApiKeyConfig {
+                    client: "research".to_string(),
+                    token: "research-key".to_string(),
+                    rpcs: Some(vec!["BookSummary".to_string()]),
+                    exchanges: Some(vec!["binance".to_string()]),
+                    pairs: None,
+                },
+                ApiKeyConfig {
+                    client: "admin".to_string(),
+                    token: "admin-key".to_string(),
+                    rpcs: None,
+                    exchanges: None,
+                    pairs: None,
+                },
+            ],
+            jwt_secret: None,
+            jwt_issuer: None,
+        };
+        router(Api::new(
+            feeds,
+            Authenticator::new(Some(auth), "BTCUSDT"),
+            "BTCUSDT",
+        ))
+    }
+
+    async fn get(uri: &str, token: Option<&str>) -> (StatusCode, serde_json::Value) {
+        let mut request = Request::get(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = api()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_return_the_book_of_entitled_exchanges() {
+        let (status, book) = get("/v1/book/BTCUSDT?depth=1", Some("research-key")).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            book,
+            json!({
+                "pair": "BTCUSDT",
+                "sequence": 2,
+                "spread": 3.0,
+                "bids": [{"exchange": "binance", "price": 100.0, "amount": 1.0}],
+                "asks": [{"exchange": "binance", "price": 103.0, "amount": 1.0}],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn should_return_the_spread() {
+        let (status, spread) =
+            get("/v1/spread/BTCUSDT?exchanges=binance", Some("research-key")).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(spread["spread"], 3.0);
+        assert_eq!(spread["best_bid"]["price"], 100.0);
+        assert_eq!(spread["best_ask"]["price"], 103.0);
+    }
+
+    #[tokio::test]
+    async fn should_return_the_exchanges() {
+        let (status, body) = get("/v1/exchanges", Some("admin-key")).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let exchanges = body["exchanges"].as_array().unwrap();
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[1]["exchange"], "bitstamp");
+        assert_eq!(exchanges[1]["state"], "connecting");
+        assert_eq!(exchanges[1]["best_bid"], 101.0);
+        assert_eq!(exchanges[1]["best_ask"], 102.0);
+    }
+
+    #[tokio::test]
+    async fn should_consolidate_every_exchange_by_default() {
+        let (_, book) = get("/v1/book/BTCUSDT?depth=1", Some("admin-key")).await;
+        assert_eq!(book["spread"], 1.0);
+
+        let (_, book) = get("/v1/book/BTCUSDT?exchanges=binance", Some("admin-key")).await;
+        assert_eq!(book["bids"].as_array().unwrap().len(), 2);
+        assert_eq!(book["spread"], 3.0);
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_requests() {
+        let error = |(status, body): (StatusCode, serde_json::Value)| {
+            (status, body["error"].as_str().unwrap().to_string())
+        };
+
+        assert_eq!(
+            error(get("/v1/book/BTCUSDT", None).await),
+            (StatusCode::UNAUTHORIZED, "missing bearer token".to_string())
+        );
+        assert_eq!(
+            error(get("/v1/book/ETHBTC", Some("research-key")).await),
+            (
+                StatusCode::NOT_FOUND,
+                "unknown pair: ETHBTC (the server streams BTCUSDT)".to_string()
+            )
+        );
+        assert_eq!(
+            error(get("/v1/book/BTCUSDT?depth=11", Some("research-key")).await),
+            (
+                StatusCode::BAD_REQUEST,
+                "depth must be between 1 and 10".to_string()
+            )
+        );
+        assert_eq!(
+            get("/v1/book/BTCUSDT?depth=all", Some("research-key"))
+                .await
+                .0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            error(get("/v1/spread/BTCUSDT?exchanges=kraken", Some("research-key")).await),
+            (
+                StatusCode::BAD_REQUEST,
+                "unknown exchange: kraken".to_string()
+            )
+        );
+        assert_eq!(
+            error(
+                get(
+                    "/v1/spread/BTCUSDT?exchanges=bitstamp",
+                    Some("research-key")
+                )
+                .await
+            ),
+            (
+                StatusCode::FORBIDDEN,
+                "not entitled to bitstamp".to_string()
+            )
+        );
+        assert_eq!(
+            error(get("/v1/exchanges", Some("research-key")).await),
+            (
+                StatusCode::FORBIDDEN,
+                "not entitled to ExchangeStatus".to_string()
+            )
+        );
+    }
+}
@@ -2,29 +2,160 @@
 #[macro_use]
 extern crate log;
 
-use tokio::sync::{mpsc, watch};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use tokio::task::JoinSet;
+use tokio_stream::wrappers::UnixListenerStream;
 use tonic::transport::Server;
 
+use crate::app_config::{AppConfig, Overrides, DEFAULT_SETTINGS};
+use crate::auth::Authenticator;
+use crate::candles::CandleBuilder;
+use crate::entitled_summaries::EntitledSummaries;
+use crate::exchange_status::StatusTracker;
+use crate::features::FeatureCalculator;
 use crate::orderbook::*;
-use crate::orderbook_aggregator::OrderbookSummaryPublisher;
+use crate::orderbook_aggregator::{Feeds, OrderbookSummaryPublisher, PublisherSenders};
+use crate::reload::{ConfigReloader, ExchangeConnections};
+use crate::shared_summary::SharedSummary;
+use crate::spoofing::SpoofingDetector;
 
 mod app_config;
+mod auth;
+mod candles;
+mod conflation;
+mod entitled_summaries;
 mod exchange;
+mod exchange_status;
+mod features;
+mod fix;
+mod fix_message;
+mod grpc_web;
+mod health;
+mod json;
 mod metrics;
+mod multicast;
 mod orderbook_aggregator;
 mod orderbook_data;
+mod reload;
+mod rest;
 mod result;
+mod shared_summary;
+mod shm;
+mod spoofing;
+mod sse;
+mod tls;
+mod uds;
+mod websocket;
 
 pub mod orderbook {
     tonic::include_proto!("orderbook");
+
+    // served by the reflection service.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("orderbook_descriptor");
+}
+
+// the service, generated separately so summaries can be streamed pre-encoded (see build.rs.)
+pub mod orderbook_service {
+    include!(concat!(env!("OUT_DIR"), "/service/orderbook.rs"));
+}
+
+// how often exchange statuses are published, so the message rates are current.
+const STATUS_PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Streams a consolidated order book from multiple exchanges over gRPC.
+/// Settings are read from the settings file, and can be overridden by ORDERBOOK_ environment variables or these flags.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// the settings file (the .toml extension is optional.)
+    #[arg(short, long, default_value = DEFAULT_SETTINGS)]
+    config: String,
+
+    /// the pair to stream, eg BTCUSDT (pair.)
+    #[arg(long)]
+    pair: Option<String>,
+
+    /// comma separated exchanges to connect to, eg binance,bitstamp (enabled_exchanges.)
+    #[arg(long, value_delimiter = ',')]
+    exchanges: Option<Vec<String>>,
+
+    /// address the gRPC server listens on (server.grpc_addr.)
+    #[arg(long)]
+    grpc_addr: Option<SocketAddr>,
+
+    /// Unix domain socket the gRPC server listens on, as well as grpc_addr (server.grpc_uds_path.)
+    #[arg(long)]
+    grpc_uds_path: Option<PathBuf>,
+
+    /// address the prometheus metrics are served on (server.metrics_addr.)
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// address the WebSocket JSON gateway listens on (server.websocket_addr.)
+    #[arg(long)]
+    websocket_addr: Option<SocketAddr>,
+
+    /// address the REST API listens on (server.rest_addr.)
+    #[arg(long)]
+    rest_addr: Option<SocketAddr>,
+
+    /// log level or env_logger filter, eg debug or orderbooks_rs=debug. Takes precedence over RUST_LOG.
+    #[arg(long)]
+    log_level: Option<String>,
+
+    /// validate the configuration and exit without connecting to anything.
+    #[arg(long)]
+    check_config: bool,
 }
 
 #[tokio::main]
 async fn main() -> result::Result<()> {
-    println!("Starting app... Use RUST_LOG=info to enable more logging.");
-    env_logger::init();
+    let args = Args::parse();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(filter) = &args.log_level {
+        logger.parse_filters(filter);
+    }
+    logger.init();
+
+    let overrides = Overrides {
+        pair: args.pair.clone(),
+        enabled_exchanges: args.exchanges.clone(),
+        grpc_addr: args.grpc_addr,
+        metrics_addr: args.metrics_addr,
+        websocket_addr: args.websocket_addr,
+        rest_addr: args.rest_addr,
+        grpc_uds_path: args.grpc_uds_path.clone(),
+    };
+    let conf = AppConfig::new(&args.config, &overrides)?;
+
+    // everything is validated up front, so problems are reported together before anything connects.
+    if let Err(report) = conf.validate() {
+        eprintln!("Configuration in {} is invalid, {}", args.config, report);
+        std::process::exit(1);
+    }
+    if args.check_config {
+        println!("Configuration in {} is valid.", args.config);
+        return Ok(());
+    }
+
+    println!("Starting app... Use --log-level or RUST_LOG=info to enable more logging.");
 
-    let conf = app_config::AppConfig::new().expect("couldn't read Settings...");
+    let grpc_addr = conf.grpc_addr().expect("couldn't read server.grpc_addr...");
+    let grpc_uds_path = conf
+        .grpc_uds_path()
+        .expect("couldn't read server.grpc_uds_path...");
+    let metrics_addr = conf
+        .metrics_addr()
+        .expect("couldn't read server.metrics_addr...");
 
     let spot_pair = conf.spot_pair().unwrap();
     let enabled_exchanges = conf.enabled_exchanges().unwrap();
@@ -37,44 +168,246 @@ async fn main() -> result::Result<()> {
         &spot_pair, &enabled_exchanges
     );
 
-    metrics::start_server_and_register();
+    metrics::start_server_and_register(metrics_addr);
 
     // watch is used to send messages to the server/client connections.
     // Each client connection will observe when there is an update and then will read the most current values.
     // Note that borrows of the value will hold a read lock so they should be very short lived.
     // This shouldn't cause any contention, but may need to revisit the use of channels
-    // Optimization: use eg Arc<Summary> instead of Summary for the channels to prevent cloning of the Summary per client.
-    let (watch_tx, watch_rx) = watch::channel(Summary {
-        spread: 0.0,
-        bids: vec![],
-        asks: vec![],
+    // Each summary is encoded once when it's published, and the encoded bytes are shared by all clients.
+    let (watch_tx, watch_rx) = watch::channel(SharedSummary::default());
+    // every summary is also broadcast for clients that queue them, as far back as the largest queue.
+    let (summaries_tx, _) = broadcast::channel(conflation::MAX_QUEUE_SIZE);
+
+    // feature vectors for ML agents are calculated from each summary and published the same way.
+    let feature_calculator = FeatureCalculator::new(enabled_exchanges.clone());
+    let feature_layout = feature_calculator.layout();
+    // until the first summary, clients get zeros in the layout's shape.
+    let (features_tx, features_rx) = watch::channel(Features {
+        values: vec![0.0; feature_layout.len()],
     });
 
+    // spoofing alerts are broadcast to clients as they're detected.
+    let spoofing_detector = SpoofingDetector::new(
+        conf.spoofing_config()
+            .expect("couldn't read spoofing config..."),
+    );
+    let (alerts_tx, _) = broadcast::channel(64);
+
+    // consolidated trades from all exchanges are broadcast to clients as they arrive.
+    let (trades_tx, _) = broadcast::channel(256);
+
+    // candles are built from the mid prices and trades, and broadcast as they close.
+    let candle_builder = CandleBuilder::new(
+        conf.candle_intervals()
+            .expect("couldn't read candle intervals..."),
+    )
+    .expect("couldn't build candles...");
+    let (candles_tx, _) = broadcast::channel(64);
+
+    // snapshots of the order book data are published for the REST API, which builds its responses from them.
+    let (book_tx, book) = watch::channel(Arc::default());
+    // clients only entitled to some exchanges are sent summaries built from the book for just those exchanges.
+    let entitled_summaries = EntitledSummaries::default();
+
     let (tx, rx) = mpsc::channel(32);
+    let (exchange_trades_tx, exchange_trades_rx) = mpsc::channel(32);
 
     // Start the process that aggregates order books and supplies updates to the watch for single producer multi consumer semantics.
-    OrderbookSummaryPublisher::start(rx, watch_tx).await;
-
-    for exchange in enabled_exchanges {
-        let conf = exchange_configs
-            .iter()
-            .find(|ex| ex.id == exchange)
-            .expect(&*format!(
-                "no config for configured exchange {}",
-                exchange.clone()
-            ))
-            .clone();
+    OrderbookSummaryPublisher::start(
+        rx,
+        exchange_trades_rx,
+        feature_calculator,
+        spoofing_detector,
+        candle_builder,
+        PublisherSenders {
+            summary_tx: watch_tx,
+            summaries_tx: summaries_tx.clone(),
+            features_tx,
+            alerts_tx: alerts_tx.clone(),
+            trades_tx: trades_tx.clone(),
+            candles_tx: candles_tx.clone(),
+            book_tx,
+            entitled_summaries: entitled_summaries.clone(),
+        },
+    )
+    .await;
+
+    // the connection status of each exchange is published when it changes, and every second for the message rates.
+    let (status_tracker, status_rx) = StatusTracker::new();
+    status_tracker.start(STATUS_PUBLISH_INTERVAL);
 
-        info!("starting exchange stream for: [{}]", exchange);
+    // the connections to each exchange are restarted if their settings change while running.
+    let mut connections = ExchangeConnections::new(tx, exchange_trades_tx, status_tracker);
+    connections.apply(exchange_configs).await;
+    let reloader = Arc::new(Mutex::new(ConfigReloader::new(
+        args.config.clone(),
+        overrides,
+        &spot_pair,
+        connections,
+    )));
+    if let Some(check_interval) = conf
+        .reload_check_interval()
+        .expect("couldn't read server.reload_check_interval_s...")
+    {
+        reload::watch_settings_file(reloader.clone(), check_interval);
+    }
 
-        exchange::create_exchange_ws_connection(conf.clone(), tx.clone());
+    let feeds = Feeds {
+        summary_rx: watch_rx,
+        summaries_tx,
+        features_rx,
+        alerts_tx,
+        trades_tx,
+        candles_tx,
+        status_rx: status_rx.clone(),
+        book,
+        entitled_summaries,
+    };
+    let route_guide =
+        OrderbookSummaryPublisher::new(feeds.clone(), &spot_pair, feature_layout, reloader);
+    // clients are authenticated if [auth] is configured, see auth.rs.
+    let auth_config = conf.auth_config().expect("couldn't read auth...");
+    let auth_enabled = auth_config.is_some();
+    if auth_enabled {
+        info!("clients must authenticate with a bearer token");
     }
+    let authenticator = Authenticator::new(auth_config, &spot_pair);
 
-    let addr = "[::1]:10000".parse().unwrap();
-    let route_guide = OrderbookSummaryPublisher::new(watch_rx);
+    // the WebSocket gateway streams the same summaries and statuses as JSON.
+    if let Some(websocket_addr) = conf
+        .websocket_addr()
+        .expect("couldn't read server.websocket_addr...")
+    {
+        info!("starting WebSocket gateway @ {}", websocket_addr);
+        let listener = TcpListener::bind(websocket_addr).await?;
+        websocket::serve(
+            listener,
+            websocket::Gateway::new(feeds.clone(), authenticator.clone(), &spot_pair),
+        );
+    }
+    // low latency consumers on the same network can receive the book over UDP multicast.
+    if let Some(multicast_config) = conf
+        .multicast_config()
+        .expect("couldn't read server.multicast...")
+    {
+        info!(
+            "publishing multicast feed to {} (retransmits @ {})",
+            multicast_config.group_addr, multicast_config.retransmit_addr
+        );
+        if auth_enabled {
+            warn!("the multicast feed isn't authenticated, anyone on the network can receive every exchange's levels");
+        }
+        multicast::start(&multicast_config, feeds.summary_rx.clone()).await?;
+    }
+    // strategies on the same host can read the book from shared memory.
+    if let Some(shared_memory_config) = conf
+        .shared_memory_config()
+        .expect("couldn't read server.shared_memory...")
+    {
+        info!(
+            "writing shared memory feed to {}",
+            shared_memory_config.path.display()
+        );
+        shm::start(&shared_memory_config, feeds.summary_rx.clone())?;
+    }
+    // legacy systems can consume the book over FIX.
+    if let Some(fix_config) = conf.fix_config().expect("couldn't read server.fix...") {
+        info!(
+            "starting FIX acceptor @ {} (SenderCompID {})",
+            fix_config.addr, fix_config.sender_comp_id
+        );
+        let listener = TcpListener::bind(fix_config.addr).await?;
+        fix::serve(
+            listener,
+            fix::Acceptor::new(
+                feeds.clone(),
+                authenticator.clone(),
+                &spot_pair,
+                &fix_config.sender_comp_id,
+            ),
+        );
+    }
+    // the REST API serves snapshots of the book as JSON.
+    if let Some(rest_addr) = conf.rest_addr().expect("couldn't read server.rest_addr...") {
+        info!("starting REST API @ {}", rest_addr);
+        let listener = TcpListener::bind(rest_addr).await?;
+        rest::serve(
+            listener,
+            rest::Api::new(feeds, authenticator.clone(), &spot_pair),
+        )?;
+    }
     let svc =
-        crate::orderbook::orderbook_aggregator_server::OrderbookAggregatorServer::new(route_guide);
-    Server::builder().add_service(svc).serve(addr).await?;
+        crate::orderbook_service::orderbook_aggregator_server::OrderbookAggregatorServer::with_interceptor(
+            route_guide,
+            authenticator,
+        );
+
+    // health checks for load balancers, and reflection for tools like grpcurl.
+    let (health_reporter, health_svc) = tonic_health::server::health_reporter();
+    health::report_health(
+        health_reporter,
+        status_rx,
+        conf.min_streaming_exchanges()
+            .expect("couldn't read server.min_streaming_exchanges..."),
+    );
+    let reflection_svc = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    let mut server = Server::builder();
+    // browsers call the rpcs with gRPC-Web, which is sent over HTTP/1.1 unless they connect with TLS.
+    let grpc_web = conf
+        .grpc_web_config()
+        .expect("couldn't read server.grpc_web...");
+    if let Some(grpc_web) = &grpc_web {
+        info!(
+            "accepting gRPC-Web requests from origins: {:?}",
+            grpc_web.allowed_origins
+        );
+        server = server.accept_http1(true);
+    }
+    // TLS only applies to grpc_addr, the socket's file permissions control who can connect to it.
+    let uds_server = server.clone();
+    let routes = |server: Server| {
+        server
+            .layer(tower::util::option_layer(
+                grpc_web.as_ref().map(grpc_web::layer),
+            ))
+            .add_service(svc.clone())
+            .add_service(health_svc.clone())
+            .add_service(reflection_svc.clone())
+    };
+
+    let mut servers = JoinSet::new();
+    if let Some(grpc_addr) = grpc_addr {
+        match conf.tls_config().expect("couldn't read server.tls...") {
+            Some(tls_config) => {
+                info!(
+                    "starting gRPC server with TLS{} @ {}",
+                    if tls_config.client_ca_path.is_some() {
+                        ", requiring client certificates"
+                    } else {
+                        ""
+                    },
+                    grpc_addr
+                );
+                server = server.tls_config(tls::server_tls_config(&tls_config)?)?;
+            }
+            None => info!("starting gRPC server @ {}", grpc_addr),
+        }
+        servers.spawn(routes(server).serve(grpc_addr));
+    }
+    // clients on the same host can connect to the Unix domain socket instead.
+    if let Some(grpc_uds_path) = grpc_uds_path {
+        info!("starting gRPC server @ {}", grpc_uds_path.display());
+        let incoming = UnixListenerStream::new(uds::bind(&grpc_uds_path)?);
+        servers.spawn(routes(uds_server).serve_with_incoming(incoming));
+    }
+    // the server stops if either listener fails.
+    while let Some(stopped) = servers.join_next().await {
+        stopped??;
+    }
 
     println!("GRPC Server stopped. shutting down...");
     Ok(())
@@ -0,0 +1,140 @@
+//! contains the summary that's shared by all BookSummary clients.
+//! Each summary is encoded once when it's published, and the same bytes are streamed to every client
+//! (the generated service streams SharedSummary in place of Summary, see build.rs.)
+//! Cloning a SharedSummary only increments reference counts, so there's no per-client copy of the levels.
+//! The only per-client field is dropped (see conflation.rs), which is encoded after the shared bytes.
+//! That's valid protobuf as fields can be in any order, so clients decode it as a normal Summary.
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes};
+use prost::encoding::{DecodeContext, WireType};
+use prost::{DecodeError, Message};
+
+use crate::orderbook::Summary;
+
+// field number of Summary.dropped in orderbook.proto.
+const DROPPED_TAG: u32 = 5;
+
+#[derive(Clone, Debug, Default)]
+pub struct SharedSummary {
+    summary: Arc<Summary>,
+    // the summary encoded when it was published.
+    encoded: Bytes,
+    /// number of summaries the client didn't receive since the previous one.
+    pub dropped: u64,
+}
+
+impl SharedSummary {
+    /// encodes the summary to share with all clients. The summary's dropped count must be 0, it's set per client.
+    pub fn new(summary: Summary) -> SharedSummary {
+        debug_assert_eq!(summary.dropped, 0, "dropped is set per client");
+
+        SharedSummary {
+            encoded: Bytes::from(summary.encode_to_vec()),
+            summary: Arc::new(summary),
+            dropped: 0,
+        }
+    }
+
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.summary.sequence
+    }
+}
+
+// Only encoding is implemented - the server never decodes summaries, and clients decode them as Summary.
+impl Message for SharedSummary {
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        buf.put_slice(&self.encoded);
+        if self.dropped != 0 {
+            prost::encoding::uint64::encode(DROPPED_TAG, &self.dropped, buf);
+        }
+    }
+
+    fn merge_field<B: Buf>(
+        &mut self,
+        _tag: u32,
+        _wire_type: WireType,
+        _buf: &mut B,
+        _ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        Err(DecodeError::new(
+            "SharedSummary can't be decoded, decode a Summary instead",
+        ))
+    }
+
+    fn encoded_len(&self) -> usize {
+        let dropped_len = if self.dropped != 0 {
+            prost::encoding::uint64::encoded_len(DROPPED_TAG, &self.dropped)
+        } else {
+            0
+        };
+        self.encoded.len() + dropped_len
+    }
+
+    fn clear(&mut self) {
+        *self = SharedSummary::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Level;
+
+    fn summary() -> Summary {
+        Summary {
+            spread: 2.0,
+            bids: vec![Level {
+                exchange: "binance".to_string(),
+                price: 10.0,
+                amount: 3.0,
+            }],
+            asks: vec![Level {
+                exchange: "bitstamp".to_string(),
+                price: 12.0,
+                amount: 1.0,
+            }],
+            sequence: 42,
+            dropped: 0,
+        }
+    }
+
+    #[test]
+    fn should_encode_the_same_bytes_as_the_summary() {
+        let shared = SharedSummary::new(summary());
+
+        assert_eq!(shared.encode_to_vec(), summary().encode_to_vec());
+        assert_eq!(shared.encoded_len(), summary().encoded_len());
+    }
+
+    #[test]
+    fn should_decode_as_a_summary_with_the_clients_dropped_count() {
+        let mut shared = SharedSummary::new(summary());
+        shared.dropped = 7;
+
+        let encoded = shared.encode_to_vec();
+        assert_eq!(encoded.len(), shared.encoded_len());
+
+        let decoded = Summary::decode(encoded.as_slice()).unwrap();
+        assert_eq!(
+            decoded,
+            Summary {
+                dropped: 7,
+                ..summary()
+            }
+        );
+    }
+
+    #[test]
+    fn should_share_the_summary_between_clones() {
+        let shared = SharedSummary::new(summary());
+        let clone = shared.clone();
+
+        assert!(std::ptr::eq(shared.summary(), clone.summary()));
+        assert_eq!(clone.sequence(), 42);
+    }
+}
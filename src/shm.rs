@@ -0,0 +1,96 @@
+//! contains the shared memory feed, for strategies on the same host as the server.
+//! Each consolidated summary is written to a memory mapped file (eg in /dev/shm) as a snapshot of the book, which
+//! readers in other processes map and read without syscalls or decoding (see shm_book.rs for the layout and the Reader.)
+//! The latest snapshots are kept in a ring buffer of seqlocked slots, so the server never waits for readers.
+//! Like the multicast feed, it writes the summaries from Feeds::summary_rx.
+use metrics::counter;
+use tokio::sync::watch;
+
+use crate::app_config::SharedMemoryConfig;
+use crate::orderbook::{Level, Summary};
+use crate::orderbook_data::TOP_N;
+use crate::result::Result;
+use crate::shared_summary::SharedSummary;
+use orderbooks_rs::shm_book::{Exchange, ShmLevel, Writer};
+
+/// creates the file and starts writing the summaries to it.
+pub fn start(
+    config: &SharedMemoryConfig,
+    summary_rx: watch::Receiver<SharedSummary>,
+) -> Result<()> {
+    let writer = Writer::create(&config.path, config.slots, TOP_N)
+        .map_err(|e| format!("couldn't create {}: {}", config.path.display(), e))?;
+    tokio::spawn(publish(writer, summary_rx));
+    Ok(())
+}
+
+async fn publish(mut writer: Writer, mut summary_rx: watch::Receiver<SharedSummary>) {
+    loop {
+        let summary = summary_rx.borrow_and_update().clone();
+        write(&mut writer, summary.summary());
+        counter!("shm.snapshots_written", 1);
+
+        if summary_rx.changed().await.is_err() {
+            error!("summary watch closed. Stopping the shared memory feed.");
+            return;
+        }
+    }
+}
+
+fn write(writer: &mut Writer, summary: &Summary) -> u64 {
+    writer.publish(
+        summary.sequence,
+        summary.spread,
+        levels(&summary.bids),
+        levels(&summary.asks),
+    )
+}
+
+fn levels(levels: &[Level]) -> impl Iterator<Item = ShmLevel> + '_ {
+    levels.iter().map(|level| ShmLevel {
+        exchange: Exchange::new(&level.exchange),
+        price: level.price,
+        amount: level.amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orderbooks_rs::shm_book::Reader;
+
+    #[test]
+    fn should_write_summaries() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("orderbooks-rs-shm-{}", std::process::id()));
+        let mut writer = Writer::create(&path, 4, TOP_N)?;
+        let level = |exchange: &str, price: f64| Level {
+            exchange: exchange.to_string(),
+            price,
+            amount: 2.0,
+        };
+        let summary = Summary {
+            spread: 1.5,
+            bids: vec![level("binance", 100.0), level("bitstamp", 99.5)],
+            asks: vec![level("bitstamp", 101.5)],
+            sequence: 42,
+            dropped: 0,
+        };
+
+        write(&mut writer, &summary);
+        let snapshot = Reader::open(&path)?.latest().unwrap();
+        std::fs::remove_file(&path)?;
+
+        assert_eq!((snapshot.sequence, snapshot.summary_sequence), (1, 42));
+        assert_eq!(snapshot.spread, 1.5);
+        let levels = |levels: &[ShmLevel]| {
+            levels
+                .iter()
+                .map(|level| level.exchange.as_str().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(levels(&snapshot.bids), vec!["binance", "bitstamp"]);
+        assert_eq!(levels(&snapshot.asks), vec!["bitstamp"]);
+        assert_eq!(snapshot.asks[0].price, 101.5);
+        Ok(())
+    }
+}
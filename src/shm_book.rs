@@ -0,0 +1,556 @@
+//! contains the layout of the shared memory feed (see shm.rs), and the Writer and Reader for it.
+//! The file is mapped by the server and by readers in other processes on the same host, so reading the book
+//! doesn't need any syscalls or decoding. It's all 64 bit words, in the host's byte order:
+//!   header: magic, version, slot count, depth, slot words, latest sequence, 2 reserved
+//!   slots:  a ring buffer of the latest snapshots of the book, each:
+//!           lock, sequence, summary sequence, published (ns since the epoch), spread, bid count, ask count, reserved,
+//!           then depth bids and depth asks: price, amount, exchange (2 words of utf-8, nul padded)
+//! Prices, amounts and the spread are the bits of an f64. Snapshot n (from 1) is written to slot (n - 1) % slot count,
+//! and the latest sequence is set once it's written.
+//! Each slot is a seqlock: the lock is odd while the slot is being written, so a reader copies the slot and tries again
+//! if the lock was odd or changed while it copied. The writer never waits for readers, and readers give up on a slot
+//! that stays locked (eg the server stopped while writing it.)
+use std::fs::{self, File, OpenOptions};
+use std::hint;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use memmap2::{MmapOptions, MmapRaw};
+
+use crate::result::Result;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"OBSHMEM\0");
+const VERSION: u64 = 1;
+const HEADER_WORDS: usize = 8;
+const SLOT_HEADER_WORDS: usize = 8;
+const LEVEL_WORDS: usize = 4;
+// how many times a reader tries to read a slot. The writer holds a slot's lock for well under a microsecond,
+// so this is only reached if the writer stopped while holding it.
+const MAX_READ_ATTEMPTS: usize = 1 << 20;
+/// the longest exchange name, in bytes. Longer names are truncated.
+pub const EXCHANGE_LEN: usize = 16;
+
+// the header's words.
+const MAGIC_WORD: usize = 0;
+const VERSION_WORD: usize = 1;
+const SLOTS_WORD: usize = 2;
+const DEPTH_WORD: usize = 3;
+const SLOT_WORDS_WORD: usize = 4;
+const LATEST_WORD: usize = 5;
+
+// the slot's words.
+const LOCK: usize = 0;
+const SEQUENCE: usize = 1;
+const SUMMARY_SEQUENCE: usize = 2;
+const PUBLISHED: usize = 3;
+const SPREAD: usize = 4;
+const BID_COUNT: usize = 5;
+const ASK_COUNT: usize = 6;
+
+fn slot_words(depth: usize) -> usize {
+    SLOT_HEADER_WORDS + 2 * depth * LEVEL_WORDS
+}
+
+// the mapping as words. They're atomics because the server writes them while readers read them.
+// The words are made from the mapping's raw pointer, which the writer may write through.
+// Safety: mappings are page aligned, live as long as the borrow, and the words are only accessed as atomics.
+fn words(map: &MmapRaw) -> &[AtomicU64] {
+    unsafe { slice::from_raw_parts(map.as_mut_ptr() as *const AtomicU64, map.len() / 8) }
+}
+
+/// an exchange name, nul padded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Exchange([u8; EXCHANGE_LEN]);
+
+impl Exchange {
+    pub fn new(name: &str) -> Exchange {
+        let mut len = name.len().min(EXCHANGE_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = [0; EXCHANGE_LEN];
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Exchange(bytes)
+    }
+
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|b| *b == 0).unwrap_or(EXCHANGE_LEN);
+        std::str::from_utf8(&self.0[..len]).unwrap_or_default()
+    }
+
+    fn to_words(self) -> [u64; 2] {
+        let (first, second) = self.0.split_at(8);
+        [
+            u64::from_ne_bytes(first.try_into().unwrap()),
+            u64::from_ne_bytes(second.try_into().unwrap()),
+        ]
+    }
+
+    fn from_words(words: [u64; 2]) -> Exchange {
+        let mut bytes = [0; EXCHANGE_LEN];
+        bytes[..8].copy_from_slice(&words[0].to_ne_bytes());
+        bytes[8..].copy_from_slice(&words[1].to_ne_bytes());
+        Exchange(bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ShmLevel {
+    pub exchange: Exchange,
+    pub price: f64,
+    pub amount: f64,
+}
+
+/// a snapshot of the book. The bids and asks are best first, like the summaries.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    pub sequence: u64,
+    // the sequence number of the summary the snapshot is of.
+    pub summary_sequence: u64,
+    // when the snapshot was written, in ns since the unix epoch.
+    pub published_ns: u64,
+    pub spread: f64,
+    pub bids: Vec<ShmLevel>,
+    pub asks: Vec<ShmLevel>,
+}
+
+/// the result of reading a snapshot from the ring.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Read {
+    Ready(Snapshot),
+    // the snapshot hasn't been written yet.
+    Pending,
+    // the snapshot's slot has been written with a later one.
+    Overwritten,
+    // the snapshot's slot stayed locked, so the writer stopped while writing it.
+    Locked,
+}
+
+/// writes the snapshots. There must only be one writer for a file.
+pub struct Writer {
+    map: MmapRaw,
+    slots: usize,
+    depth: usize,
+    sequence: u64,
+}
+
+impl Writer {
+    /// creates the file, with room for slots snapshots of depth levels on each side.
+    /// It's initialised alongside the path and renamed into place, so readers never see it half initialised.
+    /// Readers of a previous file at the path keep reading that (stale) file until they open the path again.
+    pub fn create(path: &Path, slots: usize, depth: usize) -> Result<Writer> {
+        if slots == 0 {
+            return Err("the shared memory feed needs at least 1 slot".into());
+        }
+        let len = (HEADER_WORDS + slots * slot_words(depth)) * 8;
+        let mut tmp = PathBuf::from(path);
+        tmp.as_mut_os_string().push(".tmp");
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        file.set_len(len as u64)?;
+        let map = MmapRaw::map_raw(&file)?;
+        let writer = Writer {
+            map,
+            slots,
+            depth,
+            sequence: 0,
+        };
+
+        let header = writer.words();
+        header[VERSION_WORD].store(VERSION, Ordering::Relaxed);
+        header[SLOTS_WORD].store(slots as u64, Ordering::Relaxed);
+        header[DEPTH_WORD].store(depth as u64, Ordering::Relaxed);
+        header[SLOT_WORDS_WORD].store(slot_words(depth) as u64, Ordering::Relaxed);
+        header[MAGIC_WORD].store(MAGIC, Ordering::Release);
+        fs::rename(&tmp, path)?;
+        Ok(writer)
+    }
+
+    fn words(&self) -> &[AtomicU64] {
+        words(&self.map)
+    }
+
+    /// writes the next snapshot, and returns its sequence number. Only the first depth levels of each side are written.
+    pub fn publish(
+        &mut self,
+        summary_sequence: u64,
+        spread: f64,
+        bids: impl IntoIterator<Item = ShmLevel>,
+        asks: impl IntoIterator<Item = ShmLevel>,
+    ) -> u64 {
+        self.sequence += 1;
+        let sequence = self.sequence;
+        let published_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        let start = HEADER_WORDS + (sequence - 1) as usize % self.slots * slot_words(self.depth);
+        let slot = &self.words()[start..start + slot_words(self.depth)];
+
+        let lock = slot[LOCK].load(Ordering::Relaxed);
+        slot[LOCK].store(lock + 1, Ordering::Relaxed);
+        // readers that see any of the writes below see the odd lock too.
+        fence(Ordering::Release);
+
+        slot[SEQUENCE].store(sequence, Ordering::Relaxed);
+        slot[SUMMARY_SEQUENCE].store(summary_sequence, Ordering::Relaxed);
+        slot[PUBLISHED].store(published_ns, Ordering::Relaxed);
+        slot[SPREAD].store(spread.to_bits(), Ordering::Relaxed);
+        let bid_count = write_levels(&slot[SLOT_HEADER_WORDS..], self.depth, bids);
+        let ask_count = write_levels(
+            &slot[SLOT_HEADER_WORDS + self.depth * LEVEL_WORDS..],
+            self.depth,
+            asks,
+        );
+        slot[BID_COUNT].store(bid_count as u64, Ordering::Relaxed);
+        slot[ASK_COUNT].store(ask_count as u64, Ordering::Relaxed);
+
+        slot[LOCK].store(lock + 2, Ordering::Release);
+        self.words()[LATEST_WORD].store(sequence, Ordering::Release);
+        sequence
+    }
+}
+
+// writes up to depth levels, and returns how many were written.
+fn write_levels(
+    words: &[AtomicU64],
+    depth: usize,
+    levels: impl IntoIterator<Item = ShmLevel>,
+) -> usize {
+    let mut count = 0;
+    for (level, words) in levels
+        .into_iter()
+        .zip(words.chunks(LEVEL_WORDS))
+        .take(depth)
+    {
+        let [first, second] = level.exchange.to_words();
+        words[0].store(level.price.to_bits(), Ordering::Relaxed);
+        words[1].store(level.amount.to_bits(), Ordering::Relaxed);
+        words[2].store(first, Ordering::Relaxed);
+        words[3].store(second, Ordering::Relaxed);
+        count += 1;
+    }
+    count
+}
+
+/// reads the snapshots written by the server.
+pub struct Reader {
+    map: MmapRaw,
+    slots: usize,
+    depth: usize,
+    // the sequence number of the next snapshot returned by next.
+    next: u64,
+    missed: u64,
+}
+
+impl Reader {
+    /// maps the file written by the server. Fails if it isn't a shared memory feed of this version.
+    pub fn open(path: &Path) -> Result<Reader> {
+        let file = File::open(path)?;
+        let map = MmapOptions::new().map_raw_read_only(&file)?;
+        if map.len() < HEADER_WORDS * 8 {
+            return Err(format!("{} isn't a shared memory feed", path.display()).into());
+        }
+
+        let header = words(&map);
+        if header[MAGIC_WORD].load(Ordering::Acquire) != MAGIC {
+            return Err(format!("{} isn't a shared memory feed", path.display()).into());
+        }
+        let version = header[VERSION_WORD].load(Ordering::Relaxed);
+        if version != VERSION {
+            return Err(format!(
+                "{} is version {} of the shared memory feed, expected {}",
+                path.display(),
+                version,
+                VERSION
+            )
+            .into());
+        }
+        let slots = header[SLOTS_WORD].load(Ordering::Relaxed) as usize;
+        let depth = header[DEPTH_WORD].load(Ordering::Relaxed) as usize;
+        if header[SLOT_WORDS_WORD].load(Ordering::Relaxed) as usize != slot_words(depth)
+            || slots == 0
+            || map.len() != (HEADER_WORDS + slots * slot_words(depth)) * 8
+        {
+            return Err(format!("{} has an invalid layout", path.display()).into());
+        }
+
+        let latest = header[LATEST_WORD].load(Ordering::Acquire);
+        Ok(Reader {
+            map,
+            slots,
+            depth,
+            next: latest.max(1),
+            missed: 0,
+        })
+    }
+
+    fn words(&self) -> &[AtomicU64] {
+        words(&self.map)
+    }
+
+    /// the number of levels on each side of the snapshots.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// the sequence number of the latest snapshot, or 0 if there isn't one yet.
+    pub fn latest_sequence(&self) -> u64 {
+        self.words()[LATEST_WORD].load(Ordering::Acquire)
+    }
+
+    /// returns the latest snapshot, or None if there isn't one yet (or its slot stayed locked.)
+    pub fn latest(&self) -> Option<Snapshot> {
+        loop {
+            let sequence = self.latest_sequence();
+            if sequence == 0 {
+                return None;
+            }
+            match self.read(sequence) {
+                Read::Ready(snapshot) => return Some(snapshot),
+                Read::Locked => return None,
+                // the slot's been written again since, so try the new latest.
+                Read::Pending | Read::Overwritten => {}
+            }
+        }
+    }
+
+    /// returns the next snapshot after the last one returned (starting from the latest when the reader was opened),
+    /// or None if there isn't a newer one yet (or its slot stayed locked.)
+    /// If the reader falls a whole ring behind, it skips to the latest.
+    // it's not an Iterator: None only means there's nothing new yet, and readers poll it again.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Snapshot> {
+        loop {
+            match self.read(self.next) {
+                Read::Ready(snapshot) => {
+                    self.next += 1;
+                    return Some(snapshot);
+                }
+                Read::Pending | Read::Locked => return None,
+                Read::Overwritten => {
+                    let latest = self.latest_sequence();
+                    self.missed += latest - self.next;
+                    self.next = latest;
+                }
+            }
+        }
+    }
+
+    /// the number of snapshots next skipped because the reader fell behind.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// reads the snapshot with the sequence number from the ring.
+    pub fn read(&self, sequence: u64) -> Read {
+        let start =
+            HEADER_WORDS + (sequence.max(1) - 1) as usize % self.slots * slot_words(self.depth);
+        let slot = &self.words()[start..start + slot_words(self.depth)];
+        let mut snapshot = Snapshot::default();
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            if attempts > MAX_READ_ATTEMPTS {
+                return Read::Locked;
+            }
+            let lock = slot[LOCK].load(Ordering::Acquire);
+            if lock % 2 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+            self.copy(slot, &mut snapshot);
+            // the lock is read after the copy.
+            fence(Ordering::Acquire);
+            if slot[LOCK].load(Ordering::Relaxed) == lock {
+                break;
+            }
+        }
+
+        match snapshot.sequence {
+            s if s == sequence => Read::Ready(snapshot),
+            s if s < sequence => Read::Pending,
+            _ => Read::Overwritten,
+        }
+    }
+
+    // copies the slot, which may be torn if it's written meanwhile (the caller checks the lock.)
+    fn copy(&self, slot: &[AtomicU64], snapshot: &mut Snapshot) {
+        let word = |i: usize| slot[i].load(Ordering::Relaxed);
+        snapshot.sequence = word(SEQUENCE);
+        snapshot.summary_sequence = word(SUMMARY_SEQUENCE);
+        snapshot.published_ns = word(PUBLISHED);
+        snapshot.spread = f64::from_bits(word(SPREAD));
+        let bid_count = (word(BID_COUNT) as usize).min(self.depth);
+        let ask_count = (word(ASK_COUNT) as usize).min(self.depth);
+        read_levels(&slot[SLOT_HEADER_WORDS..], bid_count, &mut snapshot.bids);
+        read_levels(
+            &slot[SLOT_HEADER_WORDS + self.depth * LEVEL_WORDS..],
+            ask_count,
+            &mut snapshot.asks,
+        );
+    }
+}
+
+fn read_levels(words: &[AtomicU64], count: usize, levels: &mut Vec<ShmLevel>) {
+    levels.clear();
+    levels.extend(words.chunks(LEVEL_WORDS).take(count).map(|words| {
+        let word = |i: usize| words[i].load(Ordering::Relaxed);
+        ShmLevel {
+            price: f64::from_bits(word(0)),
+            amount: f64::from_bits(word(1)),
+            exchange: Exchange::from_words([word(2), word(3)]),
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> TempPath {
+            TempPath(std::env::temp_dir().join(format!(
+                "orderbooks-rs-shm-{}-{}",
+                std::process::id(),
+                name
+            )))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn level(exchange: &str, price: f64, amount: f64) -> ShmLevel {
+        ShmLevel {
+            exchange: Exchange::new(exchange),
+            price,
+            amount,
+        }
+    }
+
+    #[test]
+    fn should_read_published_snapshots() -> Result<()> {
+        let path = TempPath::new("read");
+        let mut writer = Writer::create(&path.0, 8, 2)?;
+        let mut reader = Reader::open(&path.0)?;
+        assert_eq!(reader.latest(), None);
+        assert_eq!(reader.next(), None);
+
+        let bids = [
+            level("binance", 100.0, 1.0),
+            level("bitstamp", 99.0, 2.0),
+            // beyond the depth.
+            level("binance", 98.0, 3.0),
+        ];
+        let asks = [level("a-very-long-exchange-name", 101.0, 0.5)];
+        assert_eq!(writer.publish(7, 1.0, bids, asks), 1);
+        writer.publish(8, 2.0, [], []);
+
+        let first = reader.next().unwrap();
+        assert_eq!(first.sequence, 1);
+        assert_eq!(first.summary_sequence, 7);
+        assert_eq!(first.spread, 1.0);
+        assert_eq!(first.bids, bids[..2]);
+        assert_eq!(first.asks[0].exchange.as_str(), "a-very-long-exch");
+        assert!(first.published_ns > 0);
+
+        let second = reader.next().unwrap();
+        assert_eq!((second.sequence, second.bids.len()), (2, 0));
+        assert_eq!(reader.next(), None);
+        assert_eq!(reader.latest(), Some(second));
+        assert_eq!(reader.missed(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn should_skip_to_latest_when_overwritten() -> Result<()> {
+        let path = TempPath::new("lapped");
+        let mut writer = Writer::create(&path.0, 4, 1)?;
+        let mut reader = Reader::open(&path.0)?;
+
+        for sequence in 1..=10 {
+            writer.publish(sequence, 0.0, [], []);
+        }
+        assert_eq!(reader.read(1), Read::Overwritten);
+        assert_eq!(reader.read(11), Read::Pending);
+        assert_eq!(reader.next().unwrap().sequence, 10);
+        assert_eq!(reader.missed(), 9);
+        // readers opened later start from the latest.
+        assert_eq!(Reader::open(&path.0)?.next().unwrap().sequence, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_read_torn_snapshots() -> Result<()> {
+        let path = TempPath::new("torn");
+        let mut writer = Writer::create(&path.0, 2, 10)?;
+        let reader = Reader::open(&path.0)?;
+
+        // every value in a snapshot is its sequence number, so a torn read would mix them up.
+        let writes = thread::spawn(move || {
+            for sequence in 1..=100_000u64 {
+                let value = sequence as f64;
+                let levels = vec![level("binance", value, value); sequence as usize % 10 + 1];
+                writer.publish(sequence, value, levels.clone(), levels);
+            }
+        });
+        let mut reads = 0;
+        while !writes.is_finished() {
+            if let Some(snapshot) = reader.latest() {
+                let value = snapshot.sequence as f64;
+                assert_eq!(snapshot.summary_sequence, snapshot.sequence);
+                assert_eq!(snapshot.spread, value);
+                assert_eq!(snapshot.bids.len(), snapshot.sequence as usize % 10 + 1);
+                assert_eq!(snapshot.bids, snapshot.asks);
+                assert!(snapshot
+                    .bids
+                    .iter()
+                    .all(|level| level.price == value && level.amount == value));
+                reads += 1;
+            }
+        }
+        writes.join().unwrap();
+        assert!(reads > 0);
+        assert_eq!(reader.latest().unwrap().sequence, 100_000);
+        Ok(())
+    }
+
+    #[test]
+    fn should_give_up_on_slots_left_locked() -> Result<()> {
+        let path = TempPath::new("locked");
+        let mut writer = Writer::create(&path.0, 2, 1)?;
+        let mut reader = Reader::open(&path.0)?;
+        writer.publish(1, 0.0, [], []);
+
+        // as if the writer stopped while writing the first slot.
+        writer.words()[HEADER_WORDS + LOCK].fetch_add(1, Ordering::Relaxed);
+
+        assert_eq!(reader.read(1), Read::Locked);
+        assert_eq!(reader.latest(), None);
+        assert_eq!(reader.next(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_other_files() {
+        let path = TempPath::new("other");
+        fs::write(&path.0, [1u8; 128]).unwrap();
+        assert!(Reader::open(&path.0).is_err());
+    }
+}
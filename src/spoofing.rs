@@ -0,0 +1,283 @@
+//! contains a detector for predatory strategies that spoof the order book (see README: order fading and layering).
+//! It uses the level lifetimes tracked by OrderBookData to flag levels that are placed and cancelled
+//! faster than the configured thresholds. Levels that were reduced or removed around a trade at their price were
+//! most likely filled rather than cancelled, so they aren't flagged.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::app_config::SpoofingConfig;
+use crate::orderbook::{AlertKind, SpoofingAlert, Trade};
+use crate::orderbook_data::{LevelChanges, LevelLifetime, Side};
+
+// a level reduced or removed within this time of a trade at its price (on the same exchange) was traded.
+// Trades and book updates arrive on separate streams, so they don't line up exactly.
+const TRADE_WINDOW: Duration = Duration::from_secs(1);
+
+pub struct SpoofingDetector {
+    config: SpoofingConfig,
+    // when recent fleeting levels vanished, per exchange and side. Used to detect layering.
+    recent_fleeting: HashMap<(String, Side), VecDeque<Instant>>,
+    // levels already flagged as fading, so they're only reported once while in the book.
+    faded: HashSet<(String, Side, u64)>,
+    // when recent trades were received, with their exchange and price (f64 bits), oldest first.
+    recent_trades: VecDeque<(Instant, String, u64)>,
+}
+
+impl SpoofingDetector {
+    pub fn new(config: SpoofingConfig) -> SpoofingDetector {
+        SpoofingDetector {
+            config,
+            recent_fleeting: HashMap::new(),
+            faded: HashSet::new(),
+            recent_trades: VecDeque::new(),
+        }
+    }
+
+    /// records a trade, so levels reduced or removed by it aren't flagged.
+    pub fn record_trade(&mut self, trade: &Trade, received: Instant) {
+        while let Some((first, _, _)) = self.recent_trades.front() {
+            if received.duration_since(*first) > TRADE_WINDOW {
+                self.recent_trades.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.recent_trades
+            .push_back((received, trade.exchange.clone(), trade.price.to_bits()));
+    }
+
+    /// checks the level changes from an exchange update and returns any alerts.
+    pub fn check(&mut self, changes: &LevelChanges) -> Vec<SpoofingAlert> {
+        let mut alerts = vec![];
+
+        // levels out of sight are forgotten, as they might not be seen vanishing.
+        for lifetime in &changes.dropped {
+            self.faded.remove(&level_key(lifetime));
+        }
+
+        for lifetime in &changes.reduced {
+            if self.traded(lifetime) {
+                continue;
+            }
+            let cancelled = (lifetime.max_amount - lifetime.amount) / lifetime.max_amount;
+            if self.is_fleeting(lifetime)
+                && cancelled >= self.config.fade_ratio
+                && self.faded.insert(level_key(lifetime))
+            {
+                alerts.push(alert(AlertKind::OrderFading, lifetime, 1));
+            }
+        }
+
+        for lifetime in &changes.vanished {
+            self.faded.remove(&level_key(lifetime));
+
+            if !self.is_fleeting(lifetime) || self.traded(lifetime) {
+                continue;
+            }
+            alerts.push(alert(AlertKind::FleetingLevel, lifetime, 1));
+
+            let window = Duration::from_millis(self.config.layering_window_ms);
+            let recent = self
+                .recent_fleeting
+                .entry((lifetime.exchange.clone(), lifetime.side))
+                .or_default();
+            recent.push_back(lifetime.last_seen);
+            while let Some(first) = recent.front() {
+                if lifetime.last_seen.duration_since(*first) > window {
+                    recent.pop_front();
+                } else {
+                    break;
+                }
+            }
+
+            if recent.len() >= self.config.layering_min_levels {
+                alerts.push(alert(AlertKind::Layering, lifetime, recent.len() as u32));
+                recent.clear();
+            }
+        }
+
+        alerts
+    }
+
+    // whether there was a trade at the level's price around when it changed.
+    fn traded(&self, lifetime: &LevelLifetime) -> bool {
+        let price = lifetime.price.to_bits();
+        self.recent_trades
+            .iter()
+            .any(|(received, exchange, traded_price)| {
+                *traded_price == price
+                    && *exchange == lifetime.exchange
+                    && abs_diff(*received, lifetime.last_seen) <= TRADE_WINDOW
+            })
+    }
+
+    // a level is fleeting if it's big enough to matter and hasn't been in the book for long.
+    fn is_fleeting(&self, lifetime: &LevelLifetime) -> bool {
+        lifetime.max_amount >= self.config.min_amount
+            && lifetime.lifetime() <= Duration::from_millis(self.config.max_lifetime_ms)
+    }
+}
+
+fn abs_diff(a: Instant, b: Instant) -> Duration {
+    a.max(b).duration_since(a.min(b))
+}
+
+fn level_key(lifetime: &LevelLifetime) -> (String, Side, u64) {
+    (
+        lifetime.exchange.clone(),
+        lifetime.side,
+        lifetime.price.to_bits(),
+    )
+}
+
+fn alert(kind: AlertKind, lifetime: &LevelLifetime, levels: u32) -> SpoofingAlert {
+    SpoofingAlert {
+        kind: kind as i32,
+        exchange: lifetime.exchange.clone(),
+        side: lifetime.side.as_str().to_string(),
+        price: lifetime.price,
+        amount: lifetime.max_amount,
+        lifetime_ms: lifetime.lifetime().as_millis() as u64,
+        levels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SpoofingConfig {
+        SpoofingConfig {
+            max_lifetime_ms: 500,
+            min_amount: 1.0,
+            fade_ratio: 0.75,
+            layering_window_ms: 1000,
+            layering_min_levels: 3,
+        }
+    }
+
+    fn lifetime(price: f64, lifetime_ms: u64, max_amount: f64, amount: f64) -> LevelLifetime {
+        let appeared = Instant::now();
+        LevelLifetime {
+            exchange: "binance".to_string(),
+            side: Side::Bid,
+            price,
+            appeared,
+            last_seen: appeared + Duration::from_millis(lifetime_ms),
+            first_amount: max_amount,
+            max_amount,
+            amount,
+            size_changes: 0,
+        }
+    }
+
+    fn vanished(lifetimes: Vec<LevelLifetime>) -> LevelChanges {
+        LevelChanges {
+            vanished: lifetimes,
+            ..Default::default()
+        }
+    }
+
+    fn trade(price: f64) -> Trade {
+        Trade {
+            exchange: "binance".to_string(),
+            price,
+            amount: 1.0,
+            side: "sell".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_flag_levels_cancelled_faster_than_the_threshold() {
+        let mut detector = SpoofingDetector::new(config());
+
+        let alerts = detector.check(&vanished(vec![
+            lifetime(10.0, 100, 5.0, 5.0),
+            lifetime(11.0, 600, 5.0, 5.0),
+            lifetime(12.0, 100, 0.5, 0.5),
+        ]));
+
+        assert_eq!(
+            alerts,
+            vec![SpoofingAlert {
+                kind: AlertKind::FleetingLevel as i32,
+                exchange: "binance".to_string(),
+                side: "bid".to_string(),
+                price: 10.0,
+                amount: 5.0,
+                lifetime_ms: 100,
+                levels: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn should_flag_fading_levels_once() {
+        let mut detector = SpoofingDetector::new(config());
+        let changes = LevelChanges {
+            reduced: vec![lifetime(10.0, 100, 8.0, 1.0), lifetime(11.0, 100, 8.0, 4.0)],
+            ..Default::default()
+        };
+
+        let alerts = detector.check(&changes);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::OrderFading as i32);
+        assert_eq!(alerts[0].price, 10.0);
+
+        assert!(detector.check(&changes).is_empty());
+    }
+
+    #[test]
+    fn should_flag_layering_when_many_fleeting_levels_are_seen_on_one_side() {
+        let mut detector = SpoofingDetector::new(config());
+
+        let alerts = detector.check(&vanished(vec![
+            lifetime(10.0, 100, 5.0, 5.0),
+            lifetime(10.5, 100, 5.0, 5.0),
+            lifetime(11.0, 100, 5.0, 5.0),
+        ]));
+
+        assert_eq!(alerts.len(), 4);
+        let layering = alerts.last().unwrap();
+        assert_eq!(layering.kind, AlertKind::Layering as i32);
+        assert_eq!(layering.levels, 3);
+    }
+
+    #[test]
+    fn should_not_flag_levels_removed_with_trades_at_their_price() {
+        let mut detector = SpoofingDetector::new(config());
+        let filled = lifetime(10.0, 100, 5.0, 5.0);
+        detector.record_trade(&trade(10.0), filled.last_seen);
+        detector.record_trade(&trade(11.0), filled.last_seen);
+
+        let alerts = detector.check(&LevelChanges {
+            reduced: vec![lifetime(10.0, 100, 8.0, 1.0)],
+            vanished: vec![filled, lifetime(12.0, 100, 5.0, 5.0)],
+            ..Default::default()
+        });
+
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].price, 12.0);
+    }
+
+    #[test]
+    fn should_forget_faded_levels_once_dropped() {
+        let mut detector = SpoofingDetector::new(config());
+        let fading = LevelChanges {
+            reduced: vec![lifetime(10.0, 100, 8.0, 1.0)],
+            ..Default::default()
+        };
+        assert_eq!(detector.check(&fading).len(), 1);
+
+        detector.check(&LevelChanges {
+            dropped: vec![lifetime(10.0, 100, 8.0, 1.0)],
+            ..Default::default()
+        });
+
+        assert!(detector.faded.is_empty());
+        assert_eq!(detector.check(&fading).len(), 1);
+    }
+}
@@ -0,0 +1,321 @@
+//! contains the server-sent events (SSE) stream of the REST API, for web clients that can use EventSource.
+//!   GET /v1/stream/{pair}?depth=N
+//! Each summary is sent as a `summary` event, and a `spread` event is sent with the first summary and
+//! whenever the best bid or ask price changes. The events are in the same format as the REST API (see json.rs.)
+//! Summaries come from the client's watch (see Feeds::summaries.)
+//! The id of each event is the summary's sequence number, so a client that reconnects with `Last-Event-ID`
+//! (EventSource does this automatically) is only sent the current summary if it's newer than the one it has.
+//! The client can tell how many summaries it missed from the gap in the sequence numbers.
+//! Clients authenticate like the rest of the API, and need the BookSummary entitlement.
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::json::{SpreadJson, SummaryJson};
+use crate::orderbook::Summary;
+use crate::orderbook_data::TOP_N;
+use crate::rest::{Api, ApiError};
+use crate::shared_summary::SharedSummary;
+
+// comments are sent on idle streams so proxies don't close them.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    depth: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+enum StreamMessage {
+    Summary(SummaryJson),
+    Spread(SpreadJson),
+}
+
+impl StreamMessage {
+    fn event(&self) -> Event {
+        let (name, sequence, event) = match self {
+            StreamMessage::Summary(summary) => (
+                "summary",
+                summary.sequence,
+                Event::default().json_data(summary),
+            ),
+            StreamMessage::Spread(spread) => (
+                "spread",
+                spread.sequence,
+                Event::default().json_data(spread),
+            ),
+        };
+        event
+            .expect("messages are serializable")
+            .event(name)
+            .id(sequence.to_string())
+    }
+}
+
+// a client's stream.
+struct Subscription {
+    pair: String,
+    depth: usize,
+    // the best bid and ask prices of the last spread sent, if one has been.
+    last_spread: Option<(Option<f64>, Option<f64>)>,
+}
+
+impl Subscription {
+    // returns the messages to send for the summary.
+    fn messages(&mut self, shared: SharedSummary) -> Vec<StreamMessage> {
+        let summary = shared.summary();
+
+        let mut messages = vec![StreamMessage::Summary(SummaryJson::new(
+            &self.pair, summary, self.depth,
+        ))];
+        let spread = Some(best_prices(summary));
+        if spread != self.last_spread {
+            self.last_spread = spread;
+            messages.push(StreamMessage::Spread(SpreadJson::new(&self.pair, summary)));
+        }
+        messages
+    }
+}
+
+fn best_prices(summary: &Summary) -> (Option<f64>, Option<f64>) {
+    (
+        summary.bids.first().map(|level| level.price),
+        summary.asks.first().map(|level| level.price),
+    )
+}
+
+/// streams the summaries as server-sent events.
+pub async fn summaries(
+    State(api): State<Arc<Api>>,
+    Path(pair): Path<String>,
+    query: Result<Query<StreamQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Sse<ReceiverStream<Result<Event, Infallible>>>, ApiError> {
+    let entitlements = api.authorize(&headers, "BookSummary")?;
+    api.check_pair(&pair)?;
+    let Query(query) = query?;
+    let depth = query.depth.unwrap_or(TOP_N);
+    if depth == 0 || depth > TOP_N {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("depth must be between 1 and {}", TOP_N),
+        ));
+    }
+    let last_event_id = match headers.get("last-event-id") {
+        Some(id) => Some(
+            id.to_str()
+                .ok()
+                .and_then(|id| id.parse::<u64>().ok())
+                .ok_or_else(|| {
+                    ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "Last-Event-ID must be a summary sequence number".to_string(),
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    debug!(
+        "new SSE client: {} (last event id {:?})",
+        entitlements.client, last_event_id
+    );
+
+    let mut subscription = Subscription {
+        pair: api.pair.clone(),
+        depth,
+        last_spread: None,
+    };
+    let mut summary_rx = api.feeds.summaries(&entitlements).0;
+    // the current summary is sent straight away, unless the client already has it.
+    let current = summary_rx.borrow_and_update().clone();
+    let mut next = match last_event_id {
+        Some(id) if id == current.sequence() => None,
+        _ => Some(current),
+    };
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        loop {
+            if let Some(summary) = next.take() {
+                for message in subscription.messages(summary) {
+                    if tx.send(Ok(message.event())).await.is_err() {
+                        // the client disconnected.
+                        return;
+                    }
+                }
+            }
+
+            if summary_rx.changed().await.is_err() {
+                error!("summary watch closed. Closing SSE stream.");
+                return;
+            }
+            next = Some(summary_rx.borrow_and_update().clone());
+        }
+    });
+
+    Ok(
+        Sse::new(ReceiverStream::new(rx))
+            .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, HttpBody};
+    use axum::http::Request;
+    use tokio::sync::watch;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::auth::{Authenticator, Entitlements};
+    use crate::exchange::OrderBookUpdate;
+    use crate::orderbook::{ExchangeStatuses, Level};
+    use crate::orderbook_aggregator::{BookSnapshot, Feeds};
+    use crate::orderbook_data::OrderBookData;
+    use crate::rest::router;
+
+    fn summary(sequence: u64, bid: f64, ask: f64) -> SharedSummary {
+        let level = |exchange: &str, price: f64| Level {
+            exchange: exchange.to_string(),
+            price,
+            amount: 1.0,
+        };
+        SharedSummary::new(Summary {
+            spread: ask - bid,
+            bids: vec![level("binance", bid), level("bitstamp", bid - 1.0)],
+            asks: vec![level("bitstamp", ask)],
+            sequence,
+            dropped: 0,
+        })
+    }
+
+    fn names(messages: &[StreamMessage]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|message| match message {
+                StreamMessage::Summary(_) => "summary",
+                StreamMessage::Spread(_) => "spread",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_only_send_spread_when_it_changes() {
+        let mut subscription = Subscription {
+            pair: "BTCUSDT".to_string(),
+            depth: 1,
+            last_spread: None,
+        };
+
+        let first = subscription.messages(summary(1, 100.0, 101.0));
+        assert_eq!(names(&first), vec!["summary", "spread"]);
+        assert_eq!(
+            first[0],
+            StreamMessage::Summary(SummaryJson::new(
+                "BTCUSDT",
+                summary(1, 100.0, 101.0).summary(),
+                1
+            ))
+        );
+
+        let unchanged = subscription.messages(summary(2, 100.0, 101.0));
+        assert_eq!(names(&unchanged), vec!["summary"]);
+
+        let changed = subscription.messages(summary(3, 100.0, 102.0));
+        assert_eq!(names(&changed), vec!["summary", "spread"]);
+        assert_eq!(
+            changed[1],
+            StreamMessage::Spread(SpreadJson::new(
+                "BTCUSDT",
+                summary(3, 100.0, 102.0).summary()
+            ))
+        );
+    }
+
+    #[test]
+    fn should_only_include_entitled_exchanges() {
+        let mut data = OrderBookData::default();
+        data.update_exchange_data(OrderBookUpdate::from_prices("binance", vec![100.0], vec![]));
+        data.update_exchange_data(OrderBookUpdate::from_prices(
+            "bitstamp",
+            vec![99.0],
+            vec![101.0],
+        ));
+        let feeds = Feeds::from_watches(
+            watch::channel(SharedSummary::default()).1,
+            watch::channel(ExchangeStatuses::default()).1,
+            watch::channel(Arc::new(BookSnapshot { data, sequence: 1 })).1,
+        );
+        let entitlements =
+            Entitlements::new("research", None, Some(vec!["binance".to_string()]), None);
+        let mut subscription = Subscription {
+            pair: "BTCUSDT".to_string(),
+            depth: 10,
+            last_spread: None,
+        };
+
+        let summary = feeds.summaries(&entitlements).0.borrow().clone();
+        let messages = subscription.messages(summary);
+        match &messages[1] {
+            StreamMessage::Spread(spread) => {
+                assert_eq!(spread.best_bid.as_ref().unwrap().exchange, "binance");
+                assert_eq!(spread.best_ask, None);
+                assert_eq!(spread.spread, None);
+            }
+            message => panic!("expected a spread, got {:?}", message),
+        }
+    }
+
+    // returns the text of the next chunk of the stream.
+    async fn next_chunk(body: &mut axum::body::BoxBody) -> String {
+        let chunk = body.data().await.unwrap().unwrap();
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_resume_from_last_event_id() {
+        let (summary_tx, summary_rx) = watch::channel(summary(5, 100.0, 101.0));
+        let feeds = Feeds::from_watches(
+            summary_rx,
+            watch::channel(ExchangeStatuses::default()).1,
+            watch::channel(Arc::default()).1,
+        );
+        let app = router(Api::new(
+            feeds,
+            Authenticator::new(None, "BTCUSDT"),
+            "BTCUSDT",
+        ));
+        let request = |last_event_id: &str| {
+            Request::get("/v1/stream/BTCUSDT?depth=1")
+                .header("last-event-id", last_event_id)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // the client is behind, so it's sent the current summary.
+        let response = app.clone().oneshot(request("3")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut body = response.into_body();
+        let event = next_chunk(&mut body).await;
+        assert!(event.contains("\nevent:summary\n"), "{}", event);
+        assert!(event.contains("\nid:5\n"), "{}", event);
+
+        // the client already has the current summary, so it's sent the next one.
+        let mut body = app.clone().oneshot(request("5")).await.unwrap().into_body();
+        summary_tx.send(summary(6, 100.0, 101.0)).unwrap();
+        let event = next_chunk(&mut body).await;
+        assert!(event.contains("\nid:6\n"), "{}", event);
+
+        let response = app.oneshot(request("latest")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
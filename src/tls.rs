@@ -0,0 +1,179 @@
+//! contains the TLS configuration of the gRPC server.
+//! The server presents the configured certificate, and if a client CA is configured it requires every client
+//! to present a certificate signed by that CA (mutual TLS.) Clients without one are rejected during the handshake.
+use std::path::Path;
+
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+use crate::app_config::TlsConfig;
+use crate::result::Result;
+
+/// reads the certificate, key and client CA files into the server's TLS config.
+pub fn server_tls_config(config: &TlsConfig) -> Result<ServerTlsConfig> {
+    let identity = Identity::from_pem(read(&config.cert_path)?, read(&config.key_path)?);
+    let mut tls = ServerTlsConfig::new().identity(identity);
+
+    if let Some(client_ca_path) = &config.client_ca_path {
+        tls = tls.client_ca_root(Certificate::from_pem(read(client_ca_path)?));
+    }
+
+    Ok(tls)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+
+    use rcgen::{BasicConstraints, Certificate as RcgenCertificate, CertificateParams, IsCa};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Channel, ClientTlsConfig, Server};
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+
+    use super::*;
+
+    // self-signed CA, and server and client certificates signed by it, written to a temporary directory.
+    struct TestCerts {
+        dir: PathBuf,
+        ca: String,
+    }
+
+    impl TestCerts {
+        fn generate(name: &str) -> TestCerts {
+            let dir =
+                std::env::temp_dir().join(format!("orderbook-tls-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let mut ca_params = CertificateParams::new(vec![]);
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = RcgenCertificate::from_params(ca_params).unwrap();
+            let ca_pem = ca.serialize_pem().unwrap();
+
+            for (file, subject) in [("server", "localhost"), ("client", "client")] {
+                let cert =
+                    RcgenCertificate::from_params(CertificateParams::new(vec![subject.into()]))
+                        .unwrap();
+                let pem = cert.serialize_pem_with_signer(&ca).unwrap();
+                std::fs::write(dir.join(format!("{}.pem", file)), pem).unwrap();
+                std::fs::write(
+                    dir.join(format!("{}.key", file)),
+                    cert.serialize_private_key_pem(),
+                )
+                .unwrap();
+            }
+            std::fs::write(dir.join("ca.pem"), &ca_pem).unwrap();
+
+            TestCerts { dir, ca: ca_pem }
+        }
+
+        fn tls_config(&self, mutual: bool) -> TlsConfig {
+            TlsConfig {
+                cert_path: self.dir.join("server.pem"),
+                key_path: self.dir.join("server.key"),
+                client_ca_path: mutual.then(|| self.dir.join("ca.pem")),
+            }
+        }
+
+        fn client_identity(&self) -> Identity {
+            Identity::from_pem(
+                std::fs::read(self.dir.join("client.pem")).unwrap(),
+                std::fs::read(self.dir.join("client.key")).unwrap(),
+            )
+        }
+    }
+
+    impl Drop for TestCerts {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // serves the health service with the TLS config, on a random port.
+    async fn serve(tls: ServerTlsConfig) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (_reporter, health_svc) = tonic_health::server::health_reporter();
+
+        let mut server = Server::builder().tls_config(tls).unwrap();
+        tokio::spawn(
+            server
+                .add_service(health_svc)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        addr
+    }
+
+    // connects and makes a health check, returning whether it succeeded.
+    async fn check(addr: SocketAddr, tls: Option<ClientTlsConfig>) -> bool {
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let mut endpoint = Channel::from_shared(format!("{}://{}", scheme, addr)).unwrap();
+        if let Some(tls) = tls {
+            endpoint = endpoint.tls_config(tls).unwrap();
+        }
+
+        let channel = match endpoint.connect().await {
+            Ok(channel) => channel,
+            Err(_) => return false,
+        };
+        HealthClient::new(channel)
+            .check(HealthCheckRequest::default())
+            .await
+            .is_ok()
+    }
+
+    fn client_tls(certs: &TestCerts) -> ClientTlsConfig {
+        ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(&certs.ca))
+            .domain_name("localhost")
+    }
+
+    #[tokio::test]
+    async fn should_serve_tls() -> Result<()> {
+        let certs = TestCerts::generate("server");
+        let addr = serve(server_tls_config(&certs.tls_config(false))?).await;
+
+        assert!(check(addr, Some(client_tls(&certs))).await);
+        // plaintext clients and clients that don't trust the CA are rejected.
+        assert!(!check(addr, None).await);
+        assert!(!check(addr, Some(ClientTlsConfig::new().domain_name("localhost"))).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_require_client_certificates_for_mutual_tls() -> Result<()> {
+        let certs = TestCerts::generate("mutual");
+        let addr = serve(server_tls_config(&certs.tls_config(true))?).await;
+
+        assert!(
+            check(
+                addr,
+                Some(client_tls(&certs).identity(certs.client_identity()))
+            )
+            .await
+        );
+        assert!(!check(addr, Some(client_tls(&certs))).await);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_missing_files() {
+        let config = TlsConfig {
+            cert_path: PathBuf::from("missing/server.pem"),
+            key_path: PathBuf::from("missing/server.key"),
+            client_ca_path: None,
+        };
+
+        let e = server_tls_config(&config).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("couldn't read missing/server.pem"));
+    }
+}
@@ -0,0 +1,95 @@
+//! contains the Unix domain socket listener of the gRPC server, for clients on the same host.
+//! They connect to the socket file rather than a port, which avoids the TCP stack and picking ports.
+//! Connections aren't encrypted (TLS only applies to grpc_addr), so access to the socket is controlled by the
+//! permissions of the file (the server's umask) and its directory, and clients still authenticate (see auth.rs.)
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+use tokio::net::UnixListener;
+
+use crate::result::Result;
+
+/// binds the socket. A socket file left by a previous server is replaced, unless a server is still listening on it.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(format!("{} is in use by another server", path.display()).into());
+            }
+            std::fs::remove_file(path)?;
+        }
+        Ok(_) => return Err(format!("{} exists and isn't a socket", path.display()).into()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    UnixListener::bind(path)
+        .map_err(|e| format!("couldn't listen on {}: {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::net::UnixStream;
+    use tokio_stream::wrappers::UnixListenerStream;
+    use tonic::transport::{Endpoint, Server, Uri};
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+    use tower::service_fn;
+
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "orderbooks-rs-{}-{}.sock",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[tokio::test]
+    async fn should_serve_grpc_over_the_socket() -> Result<()> {
+        let path = socket_path("grpc");
+        let (_reporter, health_svc) = tonic_health::server::health_reporter();
+        let listener = bind(&path)?;
+        tokio::spawn(
+            Server::builder()
+                .add_service(health_svc)
+                .serve_with_incoming(UnixListenerStream::new(listener)),
+        );
+
+        // the uri is ignored, the connector connects to the socket.
+        let connect_path = path.clone();
+        let channel = Endpoint::try_from("http://[::]:50051")?
+            .connect_with_connector(service_fn(move |_: Uri| {
+                UnixStream::connect(connect_path.clone())
+            }))
+            .await?;
+        let response = HealthClient::new(channel)
+            .check(HealthCheckRequest::default())
+            .await?;
+        assert_eq!(response.into_inner().status, 1);
+
+        // the server is still listening.
+        assert!(bind(&path).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_replace_stale_sockets_only() -> Result<()> {
+        let path = socket_path("stale");
+        drop(bind(&path)?);
+        // the listener's closed, but the file's still there.
+        assert!(path.exists());
+        drop(bind(&path)?);
+        std::fs::remove_file(&path)?;
+
+        std::fs::write(&path, "not a socket")?;
+        assert!(bind(&path).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+//! contains the WebSocket gateway, for dashboards and scripts that can't easily use gRPC streaming.
+//! Clients connect to server.websocket_addr and send JSON messages to subscribe to channels:
+//!   {"type": "subscribe", "channel": "summary", "pair": "BTCUSDT", "depth": 5}
+//!   {"type": "subscribe", "channel": "status"}
+//!   {"type": "unsubscribe", "channel": "summary"}
+//! Each subscription is acknowledged ("subscribed"/"unsubscribed") or rejected with an "error" message.
+//! Summaries and exchange statuses are then sent as JSON (see json.rs) from the watches the BookSummary and
+//! ExchangeStatusUpdates rpcs use (see Feeds.)
+//! When auth is configured, clients authenticate with an `Authorization: Bearer <token>` header on the upgrade request,
+//! and are limited by their entitlements like gRPC clients (see auth.rs.)
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use tonic::metadata::MetadataMap;
+use tonic::Code;
+
+use crate::auth::{authorize_rpc, Authenticator, Entitlements};
+use crate::json::{exchange_statuses, ExchangeStatusJson, SummaryJson};
+use crate::orderbook::ExchangeStatuses;
+use crate::orderbook_aggregator::{entitled_statuses, Feeds};
+use crate::orderbook_data::TOP_N;
+use crate::shared_summary::SharedSummary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Channel {
+    Summary,
+    Status,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe {
+        channel: Channel,
+        pair: Option<String>,
+        depth: Option<usize>,
+    },
+    Unsubscribe {
+        channel: Channel,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Subscribed {
+        channel: Channel,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pair: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        depth: Option<usize>,
+    },
+    Unsubscribed {
+        channel: Channel,
+    },
+    Summary(SummaryJson),
+    Status {
+        exchanges: Vec<ExchangeStatusJson>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// what's shared by every connection.
+pub struct Gateway {
+    feeds: Feeds,
+    authenticator: Authenticator,
+    // the pair the server streams.
+    pair: String,
+}
+
+impl Gateway {
+    pub fn new(feeds: Feeds, authenticator: Authenticator, pair: &str) -> Gateway {
+        Gateway {
+            feeds,
+            authenticator,
+            pair: pair.to_string(),
+        }
+    }
+}
+
+/// spawns a process that accepts WebSocket connections on the listener.
+pub fn serve(listener: TcpListener, gateway: Gateway) {
+    let gateway = Arc::new(gateway);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(handle_connection(stream, peer, gateway.clone()));
+                }
+                Err(e) => error!("couldn't accept WebSocket connection: {}", e),
+            }
+        }
+    });
+}
+
+// a client's subscriptions.
+struct Session {
+    entitlements: Entitlements,
+    // the depth of the summaries, if subscribed.
+    summary_depth: Option<usize>,
+    status: bool,
+}
+
+impl Session {
+    // updates the subscriptions, returning the acknowledgement or error to send.
+    fn handle(&mut self, text: &str, pair: &str) -> ServerMessage {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(e) => return error(format!("invalid message: {}", e)),
+        };
+
+        match message {
+            ClientMessage::Subscribe {
+                channel: Channel::Summary,
+                pair: requested_pair,
+                depth,
+            } => {
+                if let Some(requested_pair) = requested_pair.filter(|p| p != pair) {
+                    return error(format!(
+                        "unknown pair: {} (the server streams {})",
+                        requested_pair, pair
+                    ));
+                }
+                let depth = depth.unwrap_or(TOP_N);
+                if depth == 0 || depth > TOP_N {
+                    return error(format!("depth must be between 1 and {}", TOP_N));
+                }
+                if let Err(status) = authorize_rpc(&self.entitlements, "BookSummary") {
+                    return error(status.message().to_string());
+                }
+
+                self.summary_depth = Some(depth);
+                ServerMessage::Subscribed {
+                    channel: Channel::Summary,
+                    pair: Some(pair.to_string()),
+                    depth: Some(depth),
+                }
+            }
+            ClientMessage::Subscribe {
+                channel: Channel::Status,
+                ..
+            } => {
+                if let Err(status) = authorize_rpc(&self.entitlements, "ExchangeStatusUpdates") {
+                    return error(status.message().to_string());
+                }
+
+                self.status = true;
+                ServerMessage::Subscribed {
+                    channel: Channel::Status,
+                    pair: None,
+                    depth: None,
+                }
+            }
+            ClientMessage::Unsubscribe { channel } => {
+                match channel {
+                    Channel::Summary => self.summary_depth = None,
+                    Channel::Status => self.status = false,
+                }
+                ServerMessage::Unsubscribed { channel }
+            }
+        }
+    }
+}
+
+fn error(message: String) -> ServerMessage {
+    ServerMessage::Error { message }
+}
+
+async fn handle_connection(stream: TcpStream, peer: SocketAddr, gateway: Arc<Gateway>) {
+    // the client is authenticated from the upgrade request's headers. The callback's signature is tungstenite's.
+    let mut entitlements = None;
+    #[allow(clippy::result_large_err)]
+    let authenticate = |request: &Request, response: Response| {
+        let metadata = MetadataMap::from_headers(request.headers().clone());
+        match gateway.authenticator.entitlements(&metadata) {
+            Ok(authenticated) => {
+                entitlements = Some(authenticated);
+                Ok(response)
+            }
+            Err(status) => Err(rejection(status)),
+        }
+    };
+    let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, authenticate).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            debug!("WebSocket handshake with {} failed: {}", peer, e);
+            return;
+        }
+    };
+    let mut session = Session {
+        entitlements: entitlements
+            .expect("the handshake succeeded, so the client is authenticated"),
+        summary_depth: None,
+        status: false,
+    };
+    debug!(
+        "new WebSocket client: {} {}",
+        session.entitlements.client, peer
+    );
+
+    let (mut sink, mut source) = ws_stream.split();
+    let mut summary_rx = gateway.feeds.summaries(&session.entitlements).0;
+    let mut status_rx = gateway.feeds.status_rx.clone();
+
+    loop {
+        let messages = tokio::select! {
+            message = source.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = session.handle(&text, &gateway.pair);
+                    // new subscriptions start with the current value.
+                    let current = match reply {
+                        ServerMessage::Subscribed { channel: Channel::Summary, .. } => {
+                            Some(summary_message(&session, &gateway.pair, &mut summary_rx))
+                        }
+                        ServerMessage::Subscribed { channel: Channel::Status, .. } => {
+                            Some(status_message(&session, &mut status_rx))
+                        }
+                        _ => None,
+                    };
+                    std::iter::once(reply).chain(current).collect()
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                // pings are answered by tungstenite, and binary messages aren't used.
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    debug!("WebSocket client {} error: {}", peer, e);
+                    break;
+                }
+            },
+            Ok(_) = summary_rx.changed(), if session.summary_depth.is_some() => {
+                vec![summary_message(&session, &gateway.pair, &mut summary_rx)]
+            }
+            Ok(_) = status_rx.changed(), if session.status => {
+                vec![status_message(&session, &mut status_rx)]
+            }
+        };
+
+        for message in messages {
+            let json = serde_json::to_string(&message).expect("messages are serializable");
+            if let Err(e) = sink.send(Message::Text(json)).await {
+                debug!("WebSocket client {} send error: {}", peer, e);
+                return;
+            }
+        }
+    }
+}
+
+fn summary_message(
+    session: &Session,
+    pair: &str,
+    summary_rx: &mut watch::Receiver<SharedSummary>,
+) -> ServerMessage {
+    let summary = summary_rx.borrow_and_update().clone();
+    let depth = session.summary_depth.unwrap_or(TOP_N);
+    ServerMessage::Summary(SummaryJson::new(pair, summary.summary(), depth))
+}
+
+fn status_message(
+    session: &Session,
+    status_rx: &mut watch::Receiver<ExchangeStatuses>,
+) -> ServerMessage {
+    let statuses = status_rx.borrow_and_update().clone();
+    let statuses = entitled_statuses(&session.entitlements, statuses);
+    ServerMessage::Status {
+        exchanges: exchange_statuses(&statuses),
+    }
+}
+
+// rejects the upgrade request with the http equivalent of the auth status.
+fn rejection(status: tonic::Status) -> ErrorResponse {
+    let code = match status.code() {
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        _ => StatusCode::FORBIDDEN,
+    };
+    let mut response = ErrorResponse::new(Some(status.message().to_string()));
+    *response.status_mut() = code;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_tungstenite::{connect_async, WebSocketStream};
+
+    use super::*;
+    use crate::orderbook::{Level, Summary};
+
+    async fn next_json<S>(ws: &mut WebSocketStream<S>) -> serde_json::Value
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        match ws.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    fn session() -> Session {
+        Session {
+            entitlements: Entitlements::new(
+                "research",
+                Some(vec!["BookSummary".to_string()]),
+                None,
+                None,
+            ),
+            summary_depth: None,
+            status: false,
+        }
+    }
+
+    #[test]
+    fn should_subscribe_to_summaries() {
+        let mut session = session();
+
+        assert_eq!(
+            session.handle(
+                r#"{"type": "subscribe", "channel": "summary", "pair": "BTCUSDT", "depth": 5}"#,
+                "BTCUSDT"
+            ),
+            ServerMessage::Subscribed {
+                channel: Channel::Summary,
+                pair: Some("BTCUSDT".to_string()),
+                depth: Some(5),
+            }
+        );
+        assert_eq!(session.summary_depth, Some(5));
+
+        session.handle(
+            r#"{"type": "unsubscribe", "channel": "summary"}"#,
+            "BTCUSDT",
+        );
+        assert_eq!(session.summary_depth, None);
+    }
+
+    #[test]
+    fn should_reject_invalid_subscriptions() {
+        let mut session = session();
+        let mut error = |text: &str| match session.handle(text, "BTCUSDT") {
+            ServerMessage::Error { message } => message,
+            reply => panic!("expected an error, got {:?}", reply),
+        };
+
+        assert_eq!(
+            error(r#"{"type": "subscribe", "channel": "summary", "pair": "ETHBTC"}"#),
+            "unknown pair: ETHBTC (the server streams BTCUSDT)"
+        );
+        assert_eq!(
+            error(r#"{"type": "subscribe", "channel": "summary", "depth": 11}"#),
+            "depth must be between 1 and 10"
+        );
+        // the client isn't entitled to ExchangeStatusUpdates.
+        assert_eq!(
+            error(r#"{"type": "subscribe", "channel": "status"}"#),
+            "not entitled to ExchangeStatusUpdates"
+        );
+        assert!(
+            error(r#"{"type": "subscribe", "channel": "trades"}"#).starts_with("invalid message")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_stream_summaries_as_json() {
+        let (summary_tx, summary_rx) = watch::channel(SharedSummary::default());
+        let (_status_tx, status_rx) = watch::channel(ExchangeStatuses::default());
+        let feeds = Feeds::from_watches(summary_rx, status_rx, watch::channel(Arc::default()).1);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(
+            listener,
+            Gateway::new(feeds, Authenticator::new(None, "BTCUSDT"), "BTCUSDT"),
+        );
+
+        let (mut ws, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+        ws.send(Message::Text(
+            r#"{"type": "subscribe", "channel": "summary", "depth": 1}"#.to_string(),
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(next_json(&mut ws).await["type"], "subscribed");
+        // the current summary is sent when subscribing.
+        assert_eq!(next_json(&mut ws).await["sequence"], 0);
+
+        let level = |price: f64| Level {
+            exchange: "binance".to_string(),
+            price,
+            amount: 1.0,
+        };
+        summary_tx
+            .send(SharedSummary::new(Summary {
+                spread: 1.0,
+                bids: vec![level(100.0), level(99.0)],
+                asks: vec![level(101.0)],
+                sequence: 1,
+                dropped: 0,
+            }))
+            .unwrap();
+
+        let summary = next_json(&mut ws).await;
+        assert_eq!(summary["type"], "summary");
+        assert_eq!(summary["sequence"], 1);
+        assert_eq!(summary["bids"].as_array().unwrap().len(), 1);
+        assert_eq!(summary["bids"][0]["price"], 100.0);
+    }
+}